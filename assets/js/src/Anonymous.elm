@@ -1,16 +1,23 @@
-port module Anonymous exposing (Model, Msg, createCredential, init, subscriptions, update, view)
+port module Anonymous exposing (Model, Msg, createCredential, getConditionalCredential, getCredential, init, isSignedIn, subscriptions, update, view)
 
-import AttestationResponse exposing (attestationResponseDecoder)
+import AttestationResponse exposing (AttestationResponse, attestationResponseDecoder, attestationResponseEncoder)
 import CredentialOption
     exposing
         ( CredentialCreationOpption
-        , PublicKeyCredentialCreationOption
         , credentialCreationOptionDecoder
         , publicKeyCredentialCreationOptionEncoder
+        , toPublicKeyCredentialCreationOption
+        )
+import Helper exposing (isJust)
+import RequestOption
+    exposing
+        ( CredentialRequestOption
+        , PublicKeyCredentialRequestOption
+        , credentialRequestOptionDecoder
+        , publicKeyCredentialRequestOptionEncoder
         )
-import Helper exposing (isJust, toCharCodePoints)
 import Html exposing (Html, button, div, input, label, text)
-import Html.Attributes exposing (disabled, placeholder, value)
+import Html.Attributes exposing (attribute, disabled, placeholder, value)
 import Html.Events exposing (onClick, onInput)
 import Http
 import Json.Decode as D
@@ -27,6 +34,18 @@ port createCredential : Value -> Cmd msg
 port receiveAttestationResponse : (Value -> msg) -> Sub msg
 
 
+port getCredential : Value -> Cmd msg
+
+
+port receiveAssertionResponse : (Value -> msg) -> Sub msg
+
+
+port getConditionalCredential : Value -> Cmd msg
+
+
+port receiveConditionalAssertionResponse : (Value -> msg) -> Sub msg
+
+
 
 -- MODEL
 
@@ -34,6 +53,7 @@ port receiveAttestationResponse : (Value -> msg) -> Sub msg
 type alias Model =
     { username : Maybe String
     , displayName : Maybe String
+    , signedIn : Bool
     }
 
 
@@ -43,7 +63,12 @@ type alias Model =
 
 init : ( Model, Cmd Msg )
 init =
-    ( { username = Nothing, displayName = Nothing }, Cmd.none )
+    ( { username = Nothing, displayName = Nothing, signedIn = False }, requestConditionalAssertion )
+
+
+isSignedIn : Model -> Bool
+isSignedIn model =
+    model.signedIn
 
 
 
@@ -56,6 +81,13 @@ type Msg
     | CreateCredentialCreationOpption
     | GotCredentialCreationOption (Result Http.Error CredentialCreationOpption)
     | ReceiveAttestationResponse Value
+    | GotVerification (Result Http.Error ())
+    | SignInWithPasskey
+    | GotCredentialRequestOption (Result Http.Error CredentialRequestOption)
+    | ReceiveAssertionResponse Value
+    | GotSignIn (Result Http.Error ())
+    | GotConditionalRequestOption (Result Http.Error CredentialRequestOption)
+    | ReceiveConditionalAssertionResponse Value
 
 
 update : Msg -> Model -> ( Model, Cmd Msg )
@@ -98,7 +130,7 @@ update msg model =
                 Ok response ->
                     let
                         publicKeyCredentialCreationOption =
-                            transformCredentialCreationOption response
+                            toPublicKeyCredentialCreationOption response
                     in
                     ( model, createCredential (publicKeyCredentialCreationOptionEncoder publicKeyCredentialCreationOption) )
 
@@ -107,30 +139,61 @@ update msg model =
 
         ReceiveAttestationResponse value ->
             case D.decodeValue attestationResponseDecoder value of
-                Ok assertion ->
+                Ok attestationResponse ->
+                    ( model, verifyCredential attestationResponse )
+
+                Err _ ->
                     ( model, Cmd.none )
 
+        GotVerification result ->
+            case result of
+                Ok _ ->
+                    ( { model | signedIn = True }, Cmd.none )
+
                 Err _ ->
                     ( model, Cmd.none )
 
+        SignInWithPasskey ->
+            ( model, requestDiscoverableAssertion )
 
-transformCredentialCreationOption : CredentialCreationOpption -> PublicKeyCredentialCreationOption
-transformCredentialCreationOption option =
-    let
-        encodedUser =
-            let
-                user =
-                    option.user
-            in
-            { id = toCharCodePoints option.user.id
-            , name = option.user.name
-            , displayName = option.user.displayName
-            }
-    in
-    { challenge = toCharCodePoints option.challenge
-    , rp = option.rp
-    , user = encodedUser
-    , pubKeyCredParams = option.pubKeyCredParams
+        GotCredentialRequestOption result ->
+            case result of
+                Ok response ->
+                    ( model, getCredential (publicKeyCredentialRequestOptionEncoder (transformCredentialRequestOption response)) )
+
+                Err _ ->
+                    ( model, Cmd.none )
+
+        ReceiveAssertionResponse value ->
+            ( model, verifyAssertion value )
+
+        GotSignIn result ->
+            case result of
+                Ok _ ->
+                    ( { model | signedIn = True }, Cmd.none )
+
+                Err _ ->
+                    ( model, Cmd.none )
+
+        GotConditionalRequestOption result ->
+            case result of
+                Ok response ->
+                    ( model, getConditionalCredential (publicKeyCredentialRequestOptionEncoder (transformCredentialRequestOption response)) )
+
+                Err _ ->
+                    ( model, Cmd.none )
+
+        ReceiveConditionalAssertionResponse value ->
+            ( model, verifyConditionalAssertion value )
+
+
+transformCredentialRequestOption : CredentialRequestOption -> PublicKeyCredentialRequestOption
+transformCredentialRequestOption option =
+    { challenge = option.challenge
+    , timeout = option.timeout
+    , rpId = option.rpId
+    , allowCredentials = option.allowCredentials
+    , userVerification = option.userVerification
     }
 
 
@@ -150,6 +213,8 @@ view model =
                 [ text "username"
                 , input
                     [ placeholder "username"
+                    -- lets the browser offer passkeys along with saved usernames
+                    , attribute "autocomplete" "username webauthn"
                     , onInput UpdateUsername
                     , value <| Maybe.withDefault "" model.username
                     ]
@@ -172,6 +237,8 @@ view model =
                 ]
                 [ text "register" ]
             ]
+        , div []
+            [ button [ onClick SignInWithPasskey ] [ text "sign in with a passkey" ] ]
         ]
 
 
@@ -203,9 +270,62 @@ registerUser registration_form =
 
 
 
+verifyCredential : AttestationResponse -> Cmd Msg
+verifyCredential attestationResponse =
+    Http.post
+        { url = "/verifiy_credential"
+        , body = Http.jsonBody <| attestationResponseEncoder attestationResponse
+        , expect = Http.expectWhatever GotVerification
+        }
+
+
+
+-- No username: the authenticator offers the discoverable credentials it holds for this site.
+requestDiscoverableAssertion : Cmd Msg
+requestDiscoverableAssertion =
+    Http.post
+        { url = "/get_discoverable_assertion_options"
+        , body = Http.emptyBody
+        , expect = Http.expectJson GotCredentialRequestOption credentialRequestOptionDecoder
+        }
+
+
+-- Passkey autofill: the browser keeps this request pending until a passkey is picked from the username field.
+requestConditionalAssertion : Cmd Msg
+requestConditionalAssertion =
+    Http.post
+        { url = "/get_conditional_assertion_options"
+        , body = Http.emptyBody
+        , expect = Http.expectJson GotConditionalRequestOption credentialRequestOptionDecoder
+        }
+
+
+verifyConditionalAssertion : Value -> Cmd Msg
+verifyConditionalAssertion assertionResponse =
+    Http.post
+        { url = "/verify_conditional_assertion"
+        , body = Http.jsonBody assertionResponse
+        , expect = Http.expectWhatever GotSignIn
+        }
+
+
+verifyAssertion : Value -> Cmd Msg
+verifyAssertion assertionResponse =
+    Http.post
+        { url = "/verify_assertion"
+        , body = Http.jsonBody assertionResponse
+        , expect = Http.expectWhatever GotSignIn
+        }
+
+
+
 -- SUBSCRIPTIONS
 
 
 subscriptions : Model -> Sub Msg
 subscriptions model =
-    Sub.batch [ receiveAttestationResponse ReceiveAttestationResponse ]
+    Sub.batch
+        [ receiveAttestationResponse ReceiveAttestationResponse
+        , receiveAssertionResponse ReceiveAssertionResponse
+        , receiveConditionalAssertionResponse ReceiveConditionalAssertionResponse
+        ]
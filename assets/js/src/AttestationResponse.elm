@@ -1,7 +1,8 @@
-module AttestationResponse exposing (attestationResponseDecoder)
+module AttestationResponse exposing (AttestationResponse, attestationResponseDecoder, attestationResponseEncoder)
 
-import Json.Decode as D exposing (Decoder, string)
-import Json.Decode.Pipeline exposing (required)
+import Json.Decode as D exposing (Decoder, list, string)
+import Json.Decode.Pipeline exposing (optional, required)
+import Json.Encode as E exposing (Value)
 
 
 
@@ -9,8 +10,9 @@ import Json.Decode.Pipeline exposing (required)
 
 
 type alias AttestationResponse =
-    {  attObj : String
+    { attObj : String
     , clientData : String
+    , transports : List String
     }
 
 
@@ -23,3 +25,17 @@ attestationResponseDecoder =
     D.succeed AttestationResponse
         |> required "attObj" string
         |> required "clientData" string
+        |> optional "transports" (list string) []
+
+
+
+-- ENCODER
+
+
+attestationResponseEncoder : AttestationResponse -> Value
+attestationResponseEncoder response =
+    E.object
+        [ ( "attObj", E.string response.attObj )
+        , ( "clientData", E.string response.clientData )
+        , ( "transports", E.list E.string response.transports )
+        ]
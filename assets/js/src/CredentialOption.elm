@@ -3,10 +3,11 @@ module CredentialOption exposing
     , PublicKeyCredentialCreationOption
     , credentialCreationOptionDecoder
     , publicKeyCredentialCreationOptionEncoder
+    , toPublicKeyCredentialCreationOption
     )
 
-import Json.Decode as D exposing (Decoder, int, list, string)
-import Json.Decode.Pipeline exposing (required)
+import Json.Decode as D exposing (Decoder, bool, int, list, nullable, string)
+import Json.Decode.Pipeline exposing (optional, required)
 import Json.Encode as E exposing (Value)
 
 
@@ -30,6 +31,10 @@ type alias CredentialCreationOpption =
     , rp : RelyingParty
     , user : User
     , pubKeyCredParams : PubKeyCredParams
+    , timeout : Maybe Int
+    , excludeCredentials : List ExcludeCredential
+    , authenticatorSelection : Maybe AuthenticatorSelection
+    , attestation : Maybe String
     }
 
 
@@ -45,24 +50,44 @@ type alias RelyingParty =
     }
 
 
-type alias EncodedUser =
-    { id : List Int
-    , name : String
-    , displayName : String
+type alias ExcludeCredential =
+    { type_ : String
+    , id : String
     }
 
 
 type alias AuthenticatorSelection =
-    { requireResidentKey : Bool
-    , userVerification : String
+    { requireResidentKey : Maybe Bool
+    , residentKey : Maybe String
+    , userVerification : Maybe String
     }
 
 
+-- challenge and user.id stay base64url encoded, the port turns them into buffers.
+
+
 type alias PublicKeyCredentialCreationOption =
-    { challenge : List Int
+    { challenge : String
     , rp : RelyingParty
-    , user : EncodedUser
+    , user : User
     , pubKeyCredParams : PubKeyCredParams
+    , timeout : Maybe Int
+    , excludeCredentials : List ExcludeCredential
+    , authenticatorSelection : Maybe AuthenticatorSelection
+    , attestation : Maybe String
+    }
+
+
+toPublicKeyCredentialCreationOption : CredentialCreationOpption -> PublicKeyCredentialCreationOption
+toPublicKeyCredentialCreationOption option =
+    { challenge = option.challenge
+    , rp = option.rp
+    , user = option.user
+    , pubKeyCredParams = option.pubKeyCredParams
+    , timeout = option.timeout
+    , excludeCredentials = option.excludeCredentials
+    , authenticatorSelection = option.authenticatorSelection
+    , attestation = option.attestation
     }
 
 
@@ -70,10 +95,10 @@ type alias PublicKeyCredentialCreationOption =
 -- ENCODER
 
 
-encodedUserEncoder : EncodedUser -> Value
-encodedUserEncoder user =
+userEncoder : User -> Value
+userEncoder user =
     E.object
-        [ ( "id", E.list E.int user.id )
+        [ ( "id", E.string user.id )
         , ( "name", E.string user.name )
         , ( "displayName", E.string user.displayName )
         ]
@@ -100,14 +125,46 @@ relyingPartyEncoder rp =
         ]
 
 
+excludeCredentialEncoder : ExcludeCredential -> Value
+excludeCredentialEncoder credential =
+    E.object
+        [ ( "type", E.string credential.type_ )
+        , ( "id", E.string credential.id )
+        ]
+
+
+maybeField : String -> (a -> Value) -> Maybe a -> List ( String, Value )
+maybeField name encoder value =
+    case value of
+        Just v ->
+            [ ( name, encoder v ) ]
+
+        Nothing ->
+            []
+
+
+authenticatorSelectionEncoder : AuthenticatorSelection -> Value
+authenticatorSelectionEncoder selection =
+    E.object
+        (maybeField "requireResidentKey" E.bool selection.requireResidentKey
+            ++ maybeField "residentKey" E.string selection.residentKey
+            ++ maybeField "userVerification" E.string selection.userVerification
+        )
+
+
 publicKeyCredentialCreationOptionEncoder : PublicKeyCredentialCreationOption -> Value
 publicKeyCredentialCreationOptionEncoder option =
     E.object
-        [ ( "challenge", E.list E.int option.challenge )
-        , ( "rp", relyingPartyEncoder option.rp )
-        , ( "user", encodedUserEncoder option.user )
-        , ( "pubKeyCredParams", pubKeyCredParamsEncoder option.pubKeyCredParams )
-        ]
+        ([ ( "challenge", E.string option.challenge )
+         , ( "rp", relyingPartyEncoder option.rp )
+         , ( "user", userEncoder option.user )
+         , ( "pubKeyCredParams", pubKeyCredParamsEncoder option.pubKeyCredParams )
+         , ( "excludeCredentials", E.list excludeCredentialEncoder option.excludeCredentials )
+         ]
+            ++ maybeField "timeout" E.int option.timeout
+            ++ maybeField "authenticatorSelection" authenticatorSelectionEncoder option.authenticatorSelection
+            ++ maybeField "attestation" E.string option.attestation
+        )
 
 
 
@@ -126,6 +183,25 @@ credentialCreationOptionDecoder =
         |> required "rp" relyingPartyDecoder
         |> required "user" userDecoder
         |> required "pubKeyCredParams" pubKeyCredParamsDecoder
+        |> optional "timeout" (nullable int) Nothing
+        |> optional "excludeCredentials" (list excludeCredentialDecoder) []
+        |> optional "authenticatorSelection" (nullable authenticatorSelectionDecoder) Nothing
+        |> optional "attestation" (nullable string) Nothing
+
+
+authenticatorSelectionDecoder : Decoder AuthenticatorSelection
+authenticatorSelectionDecoder =
+    D.succeed AuthenticatorSelection
+        |> optional "requireResidentKey" (nullable bool) Nothing
+        |> optional "residentKey" (nullable string) Nothing
+        |> optional "userVerification" (nullable string) Nothing
+
+
+excludeCredentialDecoder : Decoder ExcludeCredential
+excludeCredentialDecoder =
+    D.succeed ExcludeCredential
+        |> required "type" string
+        |> required "id" string
 
 
 pubKeyCredParamDecoder : Decoder PubKeyCredParam
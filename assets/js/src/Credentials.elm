@@ -0,0 +1,351 @@
+port module Credentials exposing (Model, Msg, afterStepUp, init, needsStepUp, subscriptions, update, view)
+
+import AttestationResponse exposing (AttestationResponse, attestationResponseDecoder, attestationResponseEncoder)
+import CredentialOption
+    exposing
+        ( CredentialCreationOpption
+        , credentialCreationOptionDecoder
+        , publicKeyCredentialCreationOptionEncoder
+        , toPublicKeyCredentialCreationOption
+        )
+import Html exposing (Html, button, div, input, table, tbody, td, text, th, thead, tr)
+import Html.Attributes exposing (placeholder, value)
+import Html.Events exposing (onClick, onInput)
+import Http
+import Json.Decode as D exposing (Decoder, bool, int, list, nullable, string)
+import Json.Decode.Pipeline exposing (required)
+import Json.Encode as E exposing (Value)
+import StepUp exposing (ApiError(..))
+
+
+
+-- PORT
+
+
+port addCredential : Value -> Cmd msg
+
+
+port receiveAddedAttestationResponse : (Value -> msg) -> Sub msg
+
+
+
+-- MODEL
+
+
+type alias Credential =
+    { id : Int
+    , nickname : Maybe String
+    , model : Maybe String
+    , aaguid : Maybe String
+    , transports : List String
+    , createdAt : String
+    , lastUsedAt : Maybe String
+    , current : Bool
+    }
+
+
+type alias Model =
+    { credentials : List Credential
+    , renaming : Maybe ( Int, String )
+    -- The credential to remove once the user has stepped up.
+    , removing : Maybe Int
+    -- Whether to add an authenticator once the user has stepped up.
+    , adding : Bool
+    , error : Maybe String
+    }
+
+
+
+-- INIT
+
+
+init : ( Model, Cmd Msg )
+init =
+    ( { credentials = [], renaming = Nothing, removing = Nothing, adding = False, error = Nothing }, fetchCredentials )
+
+
+needsStepUp : Model -> Bool
+needsStepUp model =
+    model.removing /= Nothing || model.adding
+
+
+afterStepUp : Bool -> Model -> ( Model, Cmd Msg )
+afterStepUp steppedUp model =
+    let
+        pending =
+            Cmd.batch
+                [ Maybe.withDefault Cmd.none (Maybe.map deleteCredential model.removing)
+                , if model.adding then
+                    requestCreationOption
+
+                  else
+                    Cmd.none
+                ]
+    in
+    if not (needsStepUp model) then
+        ( model, Cmd.none )
+
+    else if steppedUp then
+        ( { model | removing = Nothing, adding = False }, pending )
+
+    else
+        ( { model | removing = Nothing, adding = False, error = Just "changing your authenticators needs you to confirm it's you" }, Cmd.none )
+
+
+
+-- UPDATE
+
+
+type Msg
+    = GotCredentials (Result Http.Error (List Credential))
+    | StartRenaming Int String
+    | UpdateNickname String
+    | CancelRenaming
+    | SaveNickname
+    | Renamed (Result Http.Error ())
+    | Delete Int
+    | Deleted Int (Result ApiError ())
+    | Add
+    | GotCreationOption (Result ApiError CredentialCreationOpption)
+    | ReceiveAttestationResponse Value
+    | Added (Result Http.Error ())
+
+
+update : Msg -> Model -> ( Model, Cmd Msg )
+update msg model =
+    case msg of
+        GotCredentials result ->
+            case result of
+                Ok credentials ->
+                    ( { model | credentials = credentials, error = Nothing }, Cmd.none )
+
+                Err _ ->
+                    ( { model | error = Just "failed to load your authenticators" }, Cmd.none )
+
+        StartRenaming id nickname ->
+            ( { model | renaming = Just ( id, nickname ) }, Cmd.none )
+
+        UpdateNickname nickname ->
+            ( { model | renaming = Maybe.map (\( id, _ ) -> ( id, nickname )) model.renaming }, Cmd.none )
+
+        CancelRenaming ->
+            ( { model | renaming = Nothing }, Cmd.none )
+
+        SaveNickname ->
+            case model.renaming of
+                Just ( id, nickname ) ->
+                    ( { model | renaming = Nothing }, renameCredential id nickname )
+
+                Nothing ->
+                    ( model, Cmd.none )
+
+        Renamed result ->
+            case result of
+                Ok _ ->
+                    ( model, fetchCredentials )
+
+                Err _ ->
+                    ( { model | error = Just "failed to rename the authenticator" }, Cmd.none )
+
+        Delete id ->
+            ( model, deleteCredential id )
+
+        Deleted id result ->
+            case result of
+                Ok _ ->
+                    ( model, fetchCredentials )
+
+                Err ReauthRequired ->
+                    ( { model | removing = Just id }, Cmd.none )
+
+                Err (HttpError (Http.BadStatus 409)) ->
+                    ( { model | error = Just "you can't remove your last authenticator" }, Cmd.none )
+
+                Err _ ->
+                    ( { model | error = Just "failed to remove the authenticator" }, Cmd.none )
+
+        Add ->
+            ( model, requestCreationOption )
+
+        GotCreationOption result ->
+            case result of
+                Ok option ->
+                    ( model, addCredential (publicKeyCredentialCreationOptionEncoder (toPublicKeyCredentialCreationOption option)) )
+
+                Err ReauthRequired ->
+                    ( { model | adding = True }, Cmd.none )
+
+                Err _ ->
+                    ( { model | error = Just "failed to add an authenticator" }, Cmd.none )
+
+        ReceiveAttestationResponse value ->
+            -- null when the user dismissed the browser's dialog, or picked an authenticator already registered
+            case D.decodeValue attestationResponseDecoder value of
+                Ok attestationResponse ->
+                    ( model, verifyAddedCredential attestationResponse )
+
+                Err _ ->
+                    ( model, Cmd.none )
+
+        Added result ->
+            case result of
+                Ok _ ->
+                    ( model, fetchCredentials )
+
+                Err (Http.BadStatus 409) ->
+                    ( { model | error = Just "this authenticator is already registered" }, Cmd.none )
+
+                Err _ ->
+                    ( { model | error = Just "failed to add the authenticator" }, Cmd.none )
+
+
+
+-- VIEW
+
+
+view : Model -> Html Msg
+view model =
+    div []
+        [ div [] [ text <| Maybe.withDefault "" model.error ]
+        , table []
+            [ thead []
+                [ tr []
+                    (List.map (\header -> th [] [ text header ])
+                        [ "name", "model", "transports", "created", "last used", "" ]
+                    )
+                ]
+            -- The server refuses to remove the last one, so it has no remove button.
+            , tbody [] (List.map (viewCredential model.renaming (List.length model.credentials > 1)) model.credentials)
+            ]
+        , div [] [ button [ onClick Add ] [ text "add an authenticator" ] ]
+        ]
+
+
+viewCredential : Maybe ( Int, String ) -> Bool -> Credential -> Html Msg
+viewCredential renaming removable credential =
+    let
+        nickname =
+            Maybe.withDefault "" credential.nickname
+
+        nameCell =
+            case renaming of
+                Just ( id, editing ) ->
+                    if id == credential.id then
+                        td []
+                            [ input [ placeholder "name", onInput UpdateNickname, value editing ] []
+                            , button [ onClick SaveNickname ] [ text "save" ]
+                            , button [ onClick CancelRenaming ] [ text "cancel" ]
+                            ]
+
+                    else
+                        td [] [ text nickname ]
+
+                Nothing ->
+                    td [] [ text nickname, button [ onClick (StartRenaming credential.id nickname) ] [ text "rename" ] ]
+    in
+    tr []
+        [ nameCell
+        , td [] [ text <| Maybe.withDefault (Maybe.withDefault "unknown" credential.aaguid) credential.model ]
+        , td [] [ text <| String.join ", " credential.transports ]
+        , td [] [ text credential.createdAt ]
+        , td [] [ text <| Maybe.withDefault "never" credential.lastUsedAt ]
+        , td []
+            [ text <|
+                if credential.current then
+                    "signed in with this "
+
+                else
+                    ""
+            , if removable then
+                button [ onClick (Delete credential.id) ] [ text "remove" ]
+
+              else
+                text ""
+            ]
+        ]
+
+
+
+-- HTTP
+
+
+credentialDecoder : Decoder Credential
+credentialDecoder =
+    D.succeed Credential
+        |> required "id" int
+        |> required "nickname" (nullable string)
+        |> required "model" (nullable string)
+        |> required "aaguid" (nullable string)
+        |> required "transports" (list string)
+        |> required "createdAt" string
+        |> required "lastUsedAt" (nullable string)
+        |> required "current" bool
+
+
+fetchCredentials : Cmd Msg
+fetchCredentials =
+    Http.get
+        { url = "/credentials"
+        , expect = Http.expectJson GotCredentials (list credentialDecoder)
+        }
+
+
+renameCredential : Int -> String -> Cmd Msg
+renameCredential id nickname =
+    let
+        body =
+            if String.isEmpty nickname then
+                E.object [ ( "nickname", E.null ) ]
+
+            else
+                E.object [ ( "nickname", E.string nickname ) ]
+    in
+    Http.request
+        { method = "PATCH"
+        , headers = []
+        , url = "/credentials/" ++ String.fromInt id
+        , body = Http.jsonBody body
+        , expect = Http.expectWhatever Renamed
+        , timeout = Nothing
+        , tracker = Nothing
+        }
+
+
+deleteCredential : Int -> Cmd Msg
+deleteCredential id =
+    Http.request
+        { method = "DELETE"
+        , headers = []
+        , url = "/credentials/" ++ String.fromInt id
+        , body = Http.emptyBody
+        , expect = StepUp.expectWhatever (Deleted id)
+        , timeout = Nothing
+        , tracker = Nothing
+        }
+
+
+requestCreationOption : Cmd Msg
+requestCreationOption =
+    Http.post
+        { url = "/get_add_credential_options"
+        , body = Http.emptyBody
+        , expect = StepUp.expectJson GotCreationOption credentialCreationOptionDecoder
+        }
+
+
+verifyAddedCredential : AttestationResponse -> Cmd Msg
+verifyAddedCredential attestationResponse =
+    Http.post
+        { url = "/verify_added_credential"
+        , body = Http.jsonBody <| attestationResponseEncoder attestationResponse
+        , expect = Http.expectWhatever Added
+        }
+
+
+
+-- SUBSCRIPTIONS
+
+
+subscriptions : Sub Msg
+subscriptions =
+    receiveAddedAttestationResponse ReceiveAttestationResponse
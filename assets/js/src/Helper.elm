@@ -1,7 +1,4 @@
-module Helper exposing (isJust, toCharCodePoints)
-
-import Base64
-import Char
+module Helper exposing (isJust)
 
 
 isJust : Maybe a -> Bool
@@ -12,13 +9,3 @@ isJust maybeValue =
 
         Nothing ->
             False
-
-
-toCharCodePoints : String -> List Int
-toCharCodePoints encoded =
-    case Base64.decode encoded of
-        Err _ ->
-            []
-
-        Ok decoded ->
-            List.map Char.toCode <| String.toList decoded
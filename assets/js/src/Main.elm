@@ -1,8 +1,14 @@
 module Main exposing (main)
 
 import Anonymous
+import Credentials
+import Profile
+import Sessions
+import StepUp
 import Browser exposing (Document)
-import Html exposing (div, h1, text)
+import Html exposing (button, div, h1, h2, text)
+import Html.Events exposing (onClick)
+import Http
 
 
 
@@ -25,6 +31,14 @@ main =
 
 type Model
     = Anonymous Anonymous.Model
+    | SignedIn SignedInModel
+
+
+type alias SignedInModel =
+    { credentials : Credentials.Model
+    , profile : Profile.Model
+    , sessions : Sessions.Model
+    }
 
 
 
@@ -43,6 +57,12 @@ init _ =
 type Msg
     = Unknown
     | GotAnonymousMsg Anonymous.Msg
+    | GotCredentialsMsg Credentials.Msg
+    | GotProfileMsg Profile.Msg
+    | GotSessionsMsg Sessions.Msg
+    | GotStepUpMsg StepUp.Msg
+    | SignOut
+    | SignedOut (Result Http.Error ())
 
 
 updateWith : (subModel -> Model) -> (subMsg -> Msg) -> ( subModel, Cmd subMsg ) -> ( Model, Cmd Msg )
@@ -54,13 +74,113 @@ update : Msg -> Model -> ( Model, Cmd Msg )
 update msg model =
     case ( model, msg ) of
         ( Anonymous subModel, GotAnonymousMsg subMsg ) ->
-            Anonymous.update subMsg subModel |> updateWith Anonymous GotAnonymousMsg
+            let
+                ( newModel, cmd ) =
+                    Anonymous.update subMsg subModel
+            in
+            if Anonymous.isSignedIn newModel then
+                signedIn
+
+            else
+                updateWith Anonymous GotAnonymousMsg ( newModel, cmd )
+
+        ( SignedIn signedInModel, GotCredentialsMsg subMsg ) ->
+            let
+                ( newModel, cmd ) =
+                    Credentials.update subMsg signedInModel.credentials
+            in
+            updateWith (\subModel -> SignedIn { signedInModel | credentials = subModel }) GotCredentialsMsg ( newModel, cmd )
+                |> stepUpWhen (not (Credentials.needsStepUp signedInModel.credentials) && Credentials.needsStepUp newModel)
+
+        ( SignedIn signedInModel, GotProfileMsg subMsg ) ->
+            let
+                ( newModel, cmd ) =
+                    Profile.update subMsg signedInModel.profile
+            in
+            updateWith (\subModel -> SignedIn { signedInModel | profile = subModel }) GotProfileMsg ( newModel, cmd )
+                |> stepUpWhen (not (Profile.needsStepUp signedInModel.profile) && Profile.needsStepUp newModel)
+
+        ( SignedIn signedInModel, GotSessionsMsg subMsg ) ->
+            Sessions.update subMsg signedInModel.sessions
+                |> updateWith (\subModel -> SignedIn { signedInModel | sessions = subModel }) GotSessionsMsg
+
+        ( SignedIn signedInModel, GotStepUpMsg subMsg ) ->
+            case StepUp.update subMsg of
+                StepUp.Running cmd ->
+                    ( model, Cmd.map GotStepUpMsg cmd )
+
+                StepUp.SteppedUp ->
+                    afterStepUp True signedInModel
+
+                StepUp.Failed ->
+                    afterStepUp False signedInModel
+
+        ( SignedIn _, SignOut ) ->
+            ( model, signOut )
+
+        ( SignedIn _, SignedOut _ ) ->
+            updateWith Anonymous GotAnonymousMsg Anonymous.init
 
         ( _, _ ) ->
             ( model, Cmd.none )
 
 
 
+signedIn : ( Model, Cmd Msg )
+signedIn =
+    let
+        ( credentials, credentialsCmd ) =
+            Credentials.init
+
+        ( profile, profileCmd ) =
+            Profile.init
+
+        ( sessions, sessionsCmd ) =
+            Sessions.init
+    in
+    ( SignedIn { credentials = credentials, profile = profile, sessions = sessions }
+    , Cmd.batch
+        [ Cmd.map GotCredentialsMsg credentialsCmd
+        , Cmd.map GotProfileMsg profileCmd
+        , Cmd.map GotSessionsMsg sessionsCmd
+        ]
+    )
+
+
+-- A sensitive operation was refused with "reauth_required": ask for a user verified assertion, then let it retry.
+stepUpWhen : Bool -> ( Model, Cmd Msg ) -> ( Model, Cmd Msg )
+stepUpWhen refused ( model, cmd ) =
+    if refused then
+        ( model, Cmd.batch [ cmd, Cmd.map GotStepUpMsg StepUp.start ] )
+
+    else
+        ( model, cmd )
+
+
+afterStepUp : Bool -> SignedInModel -> ( Model, Cmd Msg )
+afterStepUp steppedUp signedInModel =
+    let
+        ( credentials, credentialsCmd ) =
+            Credentials.afterStepUp steppedUp signedInModel.credentials
+
+        ( profile, profileCmd ) =
+            Profile.afterStepUp steppedUp signedInModel.profile
+    in
+    ( SignedIn { signedInModel | credentials = credentials, profile = profile }
+    , Cmd.batch [ Cmd.map GotCredentialsMsg credentialsCmd, Cmd.map GotProfileMsg profileCmd ]
+    )
+
+
+signOut : Cmd Msg
+signOut =
+    Http.post
+        { url = "/sign_out"
+        , body = Http.emptyBody
+        , expect = Http.expectWhatever SignedOut
+        }
+
+
+
 -- VIEW
 
 
@@ -73,6 +193,17 @@ view model =
                 case model of
                     Anonymous subModel ->
                         Html.map GotAnonymousMsg (Anonymous.view subModel)
+
+                    SignedIn signedInModel ->
+                        div []
+                            [ button [ onClick SignOut ] [ text "sign out" ]
+                            , h2 [] [ text "profile" ]
+                            , Html.map GotProfileMsg (Profile.view signedInModel.profile)
+                            , h2 [] [ text "authenticators" ]
+                            , Html.map GotCredentialsMsg (Credentials.view signedInModel.credentials)
+                            , h2 [] [ text "sessions" ]
+                            , Html.map GotSessionsMsg (Sessions.view signedInModel.sessions)
+                            ]
         in
         [ div [] [ h1 [] [ text "yo" ] ]
         , subView
@@ -89,3 +220,9 @@ subscriptions model =
     case model of
         Anonymous anonymous ->
             Sub.map GotAnonymousMsg (Anonymous.subscriptions anonymous)
+
+        SignedIn _ ->
+            Sub.batch
+                [ Sub.map GotStepUpMsg StepUp.subscriptions
+                , Sub.map GotCredentialsMsg Credentials.subscriptions
+                ]
@@ -0,0 +1,114 @@
+module Profile exposing (Model, Msg, afterStepUp, init, needsStepUp, update, view)
+
+import Html exposing (Html, button, div, input, label, text)
+import Html.Attributes exposing (disabled, placeholder, value)
+import Html.Events exposing (onClick, onInput)
+import Http
+import Json.Encode as E
+import StepUp exposing (ApiError(..))
+
+
+
+-- MODEL
+
+
+type alias Model =
+    { displayName : String
+    -- The display name to save once the user has stepped up.
+    , saving : Maybe String
+    , message : Maybe String
+    }
+
+
+
+-- INIT
+
+
+init : ( Model, Cmd Msg )
+init =
+    ( { displayName = "", saving = Nothing, message = Nothing }, Cmd.none )
+
+
+needsStepUp : Model -> Bool
+needsStepUp model =
+    model.saving /= Nothing
+
+
+afterStepUp : Bool -> Model -> ( Model, Cmd Msg )
+afterStepUp steppedUp model =
+    case ( model.saving, steppedUp ) of
+        ( Just displayName, True ) ->
+            ( { model | saving = Nothing }, updateDisplayName displayName )
+
+        ( Just _, False ) ->
+            ( { model | saving = Nothing, message = Just "changing your display name needs you to confirm it's you" }, Cmd.none )
+
+        ( Nothing, _ ) ->
+            ( model, Cmd.none )
+
+
+
+-- UPDATE
+
+
+type Msg
+    = UpdateDisplayName String
+    | SaveDisplayName
+    | Saved String (Result ApiError ())
+
+
+update : Msg -> Model -> ( Model, Cmd Msg )
+update msg model =
+    case msg of
+        UpdateDisplayName displayName ->
+            ( { model | displayName = displayName }, Cmd.none )
+
+        SaveDisplayName ->
+            ( model, updateDisplayName model.displayName )
+
+        Saved displayName result ->
+            case result of
+                Ok _ ->
+                    ( { model | displayName = "", message = Just ("your display name is now " ++ displayName) }, Cmd.none )
+
+                Err ReauthRequired ->
+                    ( { model | saving = Just displayName }, Cmd.none )
+
+                Err (HttpError (Http.BadStatus 400)) ->
+                    ( { model | message = Just "a display name is 1 to 32 characters, not only digits" }, Cmd.none )
+
+                Err _ ->
+                    ( { model | message = Just "failed to change your display name" }, Cmd.none )
+
+
+
+-- VIEW
+
+
+view : Model -> Html Msg
+view model =
+    div []
+        [ div [] [ text <| Maybe.withDefault "" model.message ]
+        , label []
+            [ text "display name"
+            , input [ placeholder "display name", onInput UpdateDisplayName, value model.displayName ] []
+            ]
+        , button [ onClick SaveDisplayName, disabled (String.isEmpty model.displayName) ] [ text "change" ]
+        ]
+
+
+
+-- HTTP
+
+
+updateDisplayName : String -> Cmd Msg
+updateDisplayName displayName =
+    Http.request
+        { method = "PATCH"
+        , headers = []
+        , url = "/user"
+        , body = Http.jsonBody (E.object [ ( "display_name", E.string displayName ) ])
+        , expect = StepUp.expectWhatever (Saved displayName)
+        , timeout = Nothing
+        , tracker = Nothing
+        }
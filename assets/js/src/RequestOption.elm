@@ -0,0 +1,97 @@
+module RequestOption exposing
+    ( CredentialRequestOption
+    , PublicKeyCredentialRequestOption
+    , credentialRequestOptionDecoder
+    , publicKeyCredentialRequestOptionEncoder
+    )
+
+import Json.Decode as D exposing (Decoder, int, list, nullable, string)
+import Json.Decode.Pipeline exposing (optional, required)
+import Json.Encode as E exposing (Value)
+
+
+
+-- MODEL
+
+
+type alias AllowCredential =
+    { type_ : String
+    , id : String
+    , transports : Maybe (List String)
+    }
+
+
+type alias CredentialRequestOption =
+    { challenge : String
+    , timeout : Maybe Int
+    , rpId : Maybe String
+    , allowCredentials : List AllowCredential
+    , userVerification : Maybe String
+    }
+
+
+type alias PublicKeyCredentialRequestOption =
+    { challenge : String
+    , timeout : Maybe Int
+    , rpId : Maybe String
+    , allowCredentials : List AllowCredential
+    , userVerification : Maybe String
+    }
+
+
+
+-- ENCODER
+
+
+maybeField : String -> (a -> Value) -> Maybe a -> List ( String, Value )
+maybeField name encoder value =
+    case value of
+        Just v ->
+            [ ( name, encoder v ) ]
+
+        Nothing ->
+            []
+
+
+allowCredentialEncoder : AllowCredential -> Value
+allowCredentialEncoder credential =
+    E.object
+        ([ ( "type", E.string credential.type_ )
+         , ( "id", E.string credential.id )
+         ]
+            ++ maybeField "transports" (E.list E.string) credential.transports
+        )
+
+
+publicKeyCredentialRequestOptionEncoder : PublicKeyCredentialRequestOption -> Value
+publicKeyCredentialRequestOptionEncoder option =
+    E.object
+        ([ ( "challenge", E.string option.challenge )
+         , ( "allowCredentials", E.list allowCredentialEncoder option.allowCredentials )
+         ]
+            ++ maybeField "timeout" E.int option.timeout
+            ++ maybeField "rpId" E.string option.rpId
+            ++ maybeField "userVerification" E.string option.userVerification
+        )
+
+
+
+-- DECODER
+
+
+allowCredentialDecoder : Decoder AllowCredential
+allowCredentialDecoder =
+    D.succeed AllowCredential
+        |> required "type" string
+        |> required "id" string
+        |> optional "transports" (nullable (list string)) Nothing
+
+
+credentialRequestOptionDecoder : Decoder CredentialRequestOption
+credentialRequestOptionDecoder =
+    D.succeed CredentialRequestOption
+        |> required "challenge" string
+        |> optional "timeout" (nullable int) Nothing
+        |> optional "rpId" (nullable string) Nothing
+        |> optional "allowCredentials" (list allowCredentialDecoder) []
+        |> optional "userVerification" (nullable string) Nothing
@@ -0,0 +1,167 @@
+module Sessions exposing (Model, Msg, init, update, view)
+
+import Html exposing (Html, button, div, table, tbody, td, text, th, thead, tr)
+import Html.Events exposing (onClick)
+import Http
+import Json.Decode as D exposing (Decoder, bool, int, list, nullable, string)
+import Json.Decode.Pipeline exposing (required)
+
+
+
+-- MODEL
+
+
+type alias Session =
+    { id : Int
+    , device : Maybe String
+    , userAgent : Maybe String
+    , ipAddress : Maybe String
+    , createdAt : String
+    , lastSeenAt : String
+    , current : Bool
+    }
+
+
+type alias Model =
+    { sessions : List Session
+    , error : Maybe String
+    }
+
+
+
+-- INIT
+
+
+init : ( Model, Cmd Msg )
+init =
+    ( { sessions = [], error = Nothing }, fetchSessions )
+
+
+
+-- UPDATE
+
+
+type Msg
+    = GotSessions (Result Http.Error (List Session))
+    | Revoke Int
+    | RevokeOthers
+    | Revoked (Result Http.Error ())
+
+
+update : Msg -> Model -> ( Model, Cmd Msg )
+update msg model =
+    case msg of
+        GotSessions result ->
+            case result of
+                Ok sessions ->
+                    ( { model | sessions = sessions, error = Nothing }, Cmd.none )
+
+                Err _ ->
+                    ( { model | error = Just "failed to load your sessions" }, Cmd.none )
+
+        Revoke id ->
+            ( model, revokeSession id )
+
+        RevokeOthers ->
+            ( model, revokeOtherSessions )
+
+        Revoked result ->
+            case result of
+                Ok _ ->
+                    ( model, fetchSessions )
+
+                Err _ ->
+                    ( { model | error = Just "failed to sign the session out" }, Cmd.none )
+
+
+
+-- VIEW
+
+
+view : Model -> Html Msg
+view model =
+    div []
+        [ div [] [ text <| Maybe.withDefault "" model.error ]
+        , table []
+            [ thead []
+                [ tr []
+                    (List.map (\header -> th [] [ text header ])
+                        [ "device", "ip address", "signed in", "last seen", "" ]
+                    )
+                ]
+            , tbody [] (List.map viewSession model.sessions)
+            ]
+        , button [ onClick RevokeOthers ] [ text "sign out everywhere else" ]
+        ]
+
+
+viewSession : Session -> Html Msg
+viewSession session =
+    let
+        device =
+            Maybe.withDefault (Maybe.withDefault "unknown" session.userAgent) session.device
+
+        action =
+            if session.current then
+                text "this device"
+
+            else
+                button [ onClick (Revoke session.id) ] [ text "sign out" ]
+    in
+    tr []
+        [ td [] [ text device ]
+        , td [] [ text <| Maybe.withDefault "unknown" session.ipAddress ]
+        , td [] [ text session.createdAt ]
+        , td [] [ text session.lastSeenAt ]
+        , td [] [ action ]
+        ]
+
+
+
+-- HTTP
+
+
+sessionDecoder : Decoder Session
+sessionDecoder =
+    D.succeed Session
+        |> required "id" int
+        |> required "device" (nullable string)
+        |> required "userAgent" (nullable string)
+        |> required "ipAddress" (nullable string)
+        |> required "createdAt" string
+        |> required "lastSeenAt" string
+        |> required "current" bool
+
+
+fetchSessions : Cmd Msg
+fetchSessions =
+    Http.get
+        { url = "/sessions"
+        , expect = Http.expectJson GotSessions (list sessionDecoder)
+        }
+
+
+revokeSession : Int -> Cmd Msg
+revokeSession id =
+    Http.request
+        { method = "DELETE"
+        , headers = []
+        , url = "/sessions/" ++ String.fromInt id
+        , body = Http.emptyBody
+        , expect = Http.expectWhatever Revoked
+        , timeout = Nothing
+        , tracker = Nothing
+        }
+
+
+revokeOtherSessions : Cmd Msg
+revokeOtherSessions =
+    Http.request
+        { method = "DELETE"
+        , headers = []
+        , url = "/sessions"
+        , body = Http.emptyBody
+        , expect = Http.expectWhatever Revoked
+        , timeout = Nothing
+        , tracker = Nothing
+        }
@@ -0,0 +1,136 @@
+port module StepUp exposing (ApiError(..), Msg, Outcome(..), expectJson, expectWhatever, start, subscriptions, update)
+
+import Http
+import Json.Decode as D
+import Json.Encode exposing (Value)
+import RequestOption exposing (CredentialRequestOption, credentialRequestOptionDecoder, publicKeyCredentialRequestOptionEncoder)
+
+
+
+-- PORT
+
+
+port getStepUpCredential : Value -> Cmd msg
+
+
+port receiveStepUpAssertionResponse : (Value -> msg) -> Sub msg
+
+
+
+-- ERROR
+
+
+type ApiError
+    = ReauthRequired
+    | HttpError Http.Error
+
+
+{-| Like Http.expectWhatever, but tells apart the "reauth_required" problem of a sensitive operation,
+which succeeds once the user has stepped up.
+-}
+expectWhatever : (Result ApiError () -> msg) -> Http.Expect msg
+expectWhatever toMsg =
+    Http.expectStringResponse toMsg (fromResponse (\_ -> Ok ()))
+
+
+{-| Like Http.expectJson, telling apart "reauth_required" as expectWhatever does.
+-}
+expectJson : (Result ApiError a -> msg) -> D.Decoder a -> Http.Expect msg
+expectJson toMsg decoder =
+    Http.expectStringResponse toMsg <|
+        fromResponse (D.decodeString decoder >> Result.mapError (D.errorToString >> Http.BadBody >> HttpError))
+
+
+fromResponse : (String -> Result ApiError a) -> Http.Response String -> Result ApiError a
+fromResponse fromBody response =
+    case response of
+        Http.GoodStatus_ _ body ->
+            fromBody body
+
+        Http.BadStatus_ metadata body ->
+            if D.decodeString (D.field "code" D.string) body == Ok "reauth_required" then
+                Err ReauthRequired
+
+            else
+                Err (HttpError (Http.BadStatus metadata.statusCode))
+
+        Http.BadUrl_ url ->
+            Err (HttpError (Http.BadUrl url))
+
+        Http.Timeout_ ->
+            Err (HttpError Http.Timeout)
+
+        Http.NetworkError_ ->
+            Err (HttpError Http.NetworkError)
+
+
+
+-- UPDATE
+
+
+type Msg
+    = GotCredentialRequestOption (Result Http.Error CredentialRequestOption)
+    | ReceiveAssertionResponse Value
+    | GotVerification (Result Http.Error ())
+
+
+type Outcome
+    = Running (Cmd Msg)
+    | SteppedUp
+    | Failed
+
+
+start : Cmd Msg
+start =
+    Http.post
+        { url = "/get_step_up_options"
+        , body = Http.emptyBody
+        , expect = Http.expectJson GotCredentialRequestOption credentialRequestOptionDecoder
+        }
+
+
+update : Msg -> Outcome
+update msg =
+    case msg of
+        GotCredentialRequestOption result ->
+            case result of
+                Ok option ->
+                    Running (getStepUpCredential (publicKeyCredentialRequestOptionEncoder option))
+
+                Err _ ->
+                    Failed
+
+        ReceiveAssertionResponse value ->
+            -- null when the user dismissed the browser's dialog
+            case D.decodeValue (D.null ()) value of
+                Ok _ ->
+                    Failed
+
+                Err _ ->
+                    Running (verifyStepUp value)
+
+        GotVerification result ->
+            case result of
+                Ok _ ->
+                    SteppedUp
+
+                Err _ ->
+                    Failed
+
+
+verifyStepUp : Value -> Cmd Msg
+verifyStepUp assertionResponse =
+    Http.post
+        { url = "/verify_step_up"
+        , body = Http.jsonBody assertionResponse
+        , expect = Http.expectWhatever GotVerification
+        }
+
+
+
+-- SUBSCRIPTIONS
+
+
+subscriptions : Sub Msg
+subscriptions =
+    receiveStepUpAssertionResponse ReceiveAssertionResponse
@@ -0,0 +1,2 @@
+alter table users alter column deleted_at set default now();
+alter table users alter column webauthn_user_id type varchar(20);
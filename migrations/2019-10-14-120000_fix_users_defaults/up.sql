@@ -0,0 +1,5 @@
+-- webauthn user handles are up to 64 bytes, and users must not be soft deleted on creation.
+-- Only rows the old default deleted on insert are restored, deleted_at then equals created_at.
+alter table users alter column webauthn_user_id type varchar(64);
+alter table users alter column deleted_at drop default;
+update users set deleted_at = null where deleted_at = created_at;
@@ -0,0 +1,20 @@
+alter table users add column credential_id varchar unique;
+alter table users add column public_key varchar unique;
+alter table users add column sign_count integer not null default 0;
+
+-- Only one credential per user fits, keep the oldest one.
+update users set
+  credential_id = rtrim(translate(encode(c.credential_id, 'base64'), E'+/\n', '-_'), '='),
+  public_key = rtrim(translate(encode(c.public_key, 'base64'), E'+/\n', '-_'), '='),
+  sign_count = c.sign_count
+from (
+  select distinct on (user_id) user_id, credential_id, public_key, sign_count
+  from credentials
+  order by user_id, created_at
+) c
+where users.id = c.user_id;
+
+delete from users where credential_id is null;
+alter table users alter column credential_id set not null;
+
+drop table credentials;
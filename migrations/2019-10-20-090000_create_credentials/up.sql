@@ -0,0 +1,34 @@
+create table credentials (
+  id serial primary key,
+  user_id integer not null references users (id) on delete cascade,
+  credential_id bytea not null unique,
+  public_key bytea not null,
+  -- alg, aaguid and attestation_format are unknown for credentials moved over from users.
+  alg integer,
+  sign_count bigint not null default 0,
+  aaguid bytea,
+  transports varchar[] not null default '{}',
+  attestation_format varchar,
+  backup_eligible boolean not null default false,
+  backup_state boolean not null default false,
+  nickname varchar,
+  created_at timestamp not null default now(),
+  last_used_at timestamp
+);
+
+create index credentials_user_id_idx on credentials (user_id);
+
+-- users stored them base64url encoded without padding.
+insert into credentials (user_id, credential_id, public_key, sign_count, created_at)
+select
+  id,
+  decode(rpad(translate(credential_id, '-_', '+/'), (length(credential_id) + 3) / 4 * 4, '='), 'base64'),
+  decode(rpad(translate(public_key, '-_', '+/'), (length(public_key) + 3) / 4 * 4, '='), 'base64'),
+  sign_count,
+  created_at
+from users
+where public_key is not null;
+
+alter table users drop column credential_id;
+alter table users drop column public_key;
+alter table users drop column sign_count;
@@ -0,0 +1 @@
+drop table suspected_clones;
@@ -0,0 +1,11 @@
+-- Assertions whose signature counter did not increase, which hints at a cloned authenticator.
+create table suspected_clones (
+  id serial primary key,
+  credential_id integer not null references credentials (id) on delete cascade,
+  stored_sign_count bigint not null,
+  reported_sign_count bigint not null,
+  rejected boolean not null,
+  created_at timestamp not null default now()
+);
+
+create index suspected_clones_credential_id_idx on suspected_clones (credential_id);
@@ -0,0 +1 @@
+update users set webauthn_user_id = rpad(translate(webauthn_user_id, '-_', '+/'), (length(webauthn_user_id) + 3) / 4 * 4, '=');
@@ -0,0 +1,2 @@
+-- user.id goes to the browser as base64url encoded bytes, store it the same way.
+update users set webauthn_user_id = translate(rtrim(webauthn_user_id, '='), '+/', '-_');
@@ -0,0 +1,13 @@
+-- Signed in browsers, so that a user can see where they are signed in and sign the others out.
+create table user_sessions (
+  id serial primary key,
+  user_id integer not null references users (id) on delete cascade,
+  -- SHA-256 of the token kept in the cookie session, the token itself is not stored.
+  token_hash bytea not null unique,
+  user_agent varchar,
+  ip_address varchar,
+  created_at timestamp not null default now(),
+  last_seen_at timestamp not null default now()
+);
+
+create index user_sessions_user_id_idx on user_sessions (user_id);
@@ -0,0 +1,3 @@
+alter table user_sessions drop column authenticated_at;
+alter table user_sessions drop column user_verified;
+alter table user_sessions drop column credential_id;
@@ -0,0 +1,4 @@
+-- How the session was authenticated, for routes asking for user verification or a recent sign in.
+alter table user_sessions add column credential_id integer references credentials (id) on delete set null;
+alter table user_sessions add column user_verified boolean not null default false;
+alter table user_sessions add column authenticated_at timestamp not null default now();
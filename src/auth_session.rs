@@ -0,0 +1,383 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use actix::Addr;
+use actix_redis::{Command, RedisActor, RespValue};
+use actix_service::{Service, Transform};
+use actix_session::Session;
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest};
+use actix_web::cookie::{Cookie, CookieJar, Key};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::ErrorInternalServerError;
+use actix_web::http::HeaderMap;
+use actix_web::http::header::{HeaderValue, SET_COOKIE, USER_AGENT};
+use diesel::pg::PgConnection;
+use diesel::QueryResult;
+use futures::future::{ok, Either, FutureResult};
+use futures::{future, Future, Poll};
+use sha2::{Digest, Sha256};
+use crate::config::Config;
+use crate::error::AppError;
+use crate::helper::generate_random;
+use crate::db;
+use crate::models::{Credential, NewUserSession, SessionTimeouts, User, UserSession};
+use crate::session_key::CookieSettings;
+
+// The cookie session only carries this token, which stands for a user_sessions row.
+// Signing out elsewhere deletes the row, and the token stops working.
+const TOKEN_KEY: &str = "session_token";
+const TOKEN_LENGTH: usize = 32;
+const MAX_USER_AGENT_LENGTH: usize = 512;
+// Of the random value naming the cookie session in Redis.
+const SESSION_ID_LENGTH: usize = 32;
+
+fn hash_token(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+fn token_hash_of(session: &Session) -> Option<Vec<u8>> {
+    session.get::<String>(TOKEN_KEY).ok().and_then(|token| token).map(|token| hash_token(&token))
+}
+
+// Where a request comes from, as shown in the list of sessions.
+#[derive(Debug)]
+pub struct Client {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+impl Client {
+    pub fn of(req: &HttpRequest) -> Self {
+        let user_agent = req.headers().get(USER_AGENT)
+            .and_then(|user_agent| user_agent.to_str().ok())
+            .map(|user_agent| user_agent.chars().take(MAX_USER_AGENT_LENGTH).collect());
+        // X-Forwarded-For or Forwarded when behind a proxy, the peer address otherwise.
+        let ip_address = req.connection_info().remote().map(|remote| {
+            remote.parse::<SocketAddr>().map(|address| address.ip().to_string()).unwrap_or_else(|_| remote.to_owned())
+        });
+        Client { user_agent, ip_address }
+    }
+}
+
+/// A successful ceremony, recorded within its database transaction and then started in the cookie session.
+pub struct SignIn {
+    token: String,
+    client: Client,
+    // The session being replaced, when signing in again from the same browser.
+    previous_token_hash: Option<Vec<u8>>,
+}
+
+impl SignIn {
+    pub fn new(session: &Session, req: &HttpRequest) -> Self {
+        SignIn {
+            token: generate_random(TOKEN_LENGTH),
+            client: Client::of(req),
+            previous_token_hash: token_hash_of(session),
+        }
+    }
+
+    pub fn record(&self, conn: &PgConnection, credential: &Credential, user_verified: bool, timeouts: &SessionTimeouts) -> QueryResult<UserSession> {
+        if let Some(previous_token_hash) = &self.previous_token_hash {
+            UserSession::delete_by_token_hash(conn, previous_token_hash)?;
+        }
+        UserSession::delete_expired(conn, credential.user_id, timeouts)?;
+        NewUserSession {
+            user_id: credential.user_id,
+            token_hash: hash_token(&self.token),
+            credential_id: Some(credential.id),
+            user_verified,
+            user_agent: self.client.user_agent.clone(),
+            ip_address: self.client.ip_address.clone(),
+        }.insert(conn)
+    }
+
+    /// Rotates the token: whatever the cookie session held before sign in is dropped,
+    /// and SessionRenewal moves it to a new name once saved.
+    pub fn start(self, session: &Session, req: &HttpRequest) -> actix_web::Result<()> {
+        session.clear();
+        session.set(TOKEN_KEY, self.token)?;
+        req.extensions_mut().insert(RenewSession);
+        Ok(())
+    }
+}
+
+// Put in the request extensions by SignIn::start.
+struct RenewSession;
+
+/// Whether a response sets the cookie of the given name.
+pub fn sets_cookie(headers: &HeaderMap, name: &str) -> bool {
+    let prefix = format!("{}=", name);
+    headers.get_all(SET_COOKIE).any(|header| header.to_str().map(|header| header.starts_with(&prefix)).unwrap_or(false))
+}
+
+struct Renewal {
+    key: Key,
+    cookie: CookieSettings,
+}
+
+impl Renewal {
+    // The value of the request's session cookie, if signed by our key.
+    fn verify(&self, cookie: Cookie<'static>) -> Option<String> {
+        let mut jar = CookieJar::new();
+        jar.add_original(cookie);
+        jar.signed(&self.key).get(&self.cookie.name).map(|cookie| cookie.value().to_owned())
+    }
+
+    // The cookie for a session name, as RedisSession would set it.
+    fn build(&self, value: String) -> Cookie<'static> {
+        let mut jar = CookieJar::new();
+        jar.signed(&self.key).add(Cookie::new(self.cookie.name.clone(), value));
+        let signed = jar.get(&self.cookie.name).expect("just added").value().to_owned();
+        self.cookie.build(signed)
+    }
+}
+
+/// Middleware, wrapped outside of RedisSession, which moves the session of a request that signed in to a new random
+/// name, so that a session cookie planted before sign in is not signed in along.
+/// RedisSession keeps the name a request's cookie gives it and only picks one for a request without a session.
+pub struct SessionRenewal {
+    inner: Arc<Renewal>,
+    redis: Addr<RedisActor>,
+}
+
+impl SessionRenewal {
+    /// Takes the key and cookie settings RedisSession was given.
+    pub fn new(key: &[u8], cookie: CookieSettings, redis: Addr<RedisActor>) -> Self {
+        let inner = Renewal { key: Key::from_master(key), cookie };
+        SessionRenewal { inner: Arc::new(inner), redis }
+    }
+}
+
+impl<S, B> Transform<S> for SessionRenewal
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SessionRenewalMiddleware<S>;
+    type Future = FutureResult<Self::Transform, Self::InitError>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SessionRenewalMiddleware { service, inner: self.inner.clone(), redis: self.redis.clone() })
+    }
+}
+
+pub struct SessionRenewalMiddleware<S> {
+    service: S,
+    inner: Arc<Renewal>,
+    redis: Addr<RedisActor>,
+}
+
+impl<S, B> Service for SessionRenewalMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let inner = self.inner.clone();
+        let redis = self.redis.clone();
+        // The name RedisSession is about to load the session from, KeyRotation already re-signed it.
+        let previous = req.cookie(&inner.cookie.name).and_then(|cookie| inner.verify(cookie));
+        Box::new(self.service.call(req).and_then(move |mut res| {
+            let renew = res.request().extensions().get::<RenewSession>().is_some();
+            let previous = match previous {
+                // Otherwise RedisSession saved it under a name of its own and set the cookie already.
+                Some(previous) if renew && !sets_cookie(res.headers(), &inner.cookie.name) => previous,
+                _ => return Either::A(ok(res)),
+            };
+            let renewed = generate_random(SESSION_ID_LENGTH);
+            let set_cookie = HeaderValue::from_str(&inner.build(renewed.clone()).encoded().to_string());
+            let command = Command(RespValue::Array(vec![
+                RespValue::BulkString(b"RENAME".to_vec()),
+                RespValue::BulkString(previous.into_bytes()),
+                RespValue::BulkString(renewed.into_bytes()),
+            ]));
+            Either::B(redis.send(command).then(move |result| match result {
+                Ok(Ok(RespValue::Error(e))) => Err(ErrorInternalServerError(e)),
+                Ok(Ok(_)) => {
+                    let set_cookie = set_cookie.map_err(ErrorInternalServerError)?;
+                    res.headers_mut().append(SET_COOKIE, set_cookie);
+                    Ok(res)
+                },
+                Ok(Err(e)) => Err(ErrorInternalServerError(e)),
+                Err(e) => Err(ErrorInternalServerError(e)),
+            }))
+        }))
+    }
+}
+
+/// What is needed to find the signed in session of a request, gathered before moving to a blocking thread.
+pub struct CurrentSession {
+    token_hash: Option<Vec<u8>>,
+    client: Client,
+    timeouts: SessionTimeouts,
+}
+
+impl CurrentSession {
+    pub fn of(session: &Session, req: &HttpRequest, config: &Config) -> Self {
+        CurrentSession {
+            token_hash: token_hash_of(session),
+            client: Client::of(req),
+            timeouts: config.session.timeouts(),
+        }
+    }
+
+    pub fn is_signed_in(&self) -> bool {
+        self.token_hash.is_some()
+    }
+
+    /// The session, refreshed as seen now. Unauthorized once it has expired or been revoked.
+    pub fn load(&self, conn: &PgConnection) -> Result<UserSession, AppError> {
+        let token_hash = self.token_hash.as_ref().ok_or(AppError::Unauthorized)?;
+        let user_session = UserSession::find_active(conn, token_hash, &self.timeouts)?.ok_or(AppError::Unauthorized)?;
+        Ok(user_session.touch(conn, self.client.ip_address.as_ref().map(String::as_str))?)
+    }
+
+    /// Deletes the session, if any. The cookie session is left to the caller to clear.
+    pub fn end(&self, conn: &PgConnection) -> QueryResult<()> {
+        match &self.token_hash {
+            Some(token_hash) => UserSession::delete_by_token_hash(conn, token_hash),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The signed in user of a request, as a handler argument. Requests without a live session fail with 401.
+pub struct AuthenticatedUser {
+    pub user: User,
+    // The credential signed in with, None once it has been removed.
+    pub credential: Option<Credential>,
+    pub session: UserSession,
+}
+
+impl AuthenticatedUser {
+    pub fn user_verified(&self) -> bool {
+        self.session.user_verified
+    }
+
+    /// Time since the user last proved possession of a credential.
+    pub fn authentication_age(&self) -> Duration {
+        // last_seen_at was just set by the database, so both come from the same clock.
+        (self.session.last_seen_at - self.session.authenticated_at).to_std().unwrap_or_default()
+    }
+
+    fn load(conn: &PgConnection, current: &CurrentSession) -> Result<Self, AppError> {
+        let session = current.load(conn)?;
+        let user = User::find(conn, session.user_id)?.ok_or(AppError::Unauthorized)?;
+        let credential = match session.credential_id {
+            Some(id) => Credential::find_for_user(conn, user.id, id)?,
+            None => None,
+        };
+        Ok(AuthenticatedUser { user, credential, session })
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = actix_web::Error;
+    type Future = Box<dyn Future<Item = Self, Error = Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let extracted = Session::extract(req).and_then(|session| {
+            let config = web::Data::<Config>::extract(req)?;
+            Ok((CurrentSession::of(&session, req, &config), web::Data::<db::Pool>::extract(req)?))
+        });
+        let (current, pool) = match extracted {
+            Ok(extracted) => extracted,
+            Err(e) => return Box::new(future::err(e)),
+        };
+        if !current.is_signed_in() {
+            return Box::new(future::err(AppError::Unauthorized.into()))
+        }
+        Box::new(
+            web::block(move || -> Result<_, AppError> {
+                let conn = pool.get()?;
+                AuthenticatedUser::load(&conn, &current)
+            })
+            .map_err(|e| -> actix_web::Error { AppError::from(e).into() })
+        )
+    }
+}
+
+/// For routes which must not be reached with a credential that skipped user verification.
+pub struct RequireUserVerification;
+
+impl RequireUserVerification {
+    pub fn check(&self, user: &AuthenticatedUser) -> Result<(), AppError> {
+        if user.user_verified() {
+            Ok(())
+        } else {
+            Err(AppError::UserVerificationRequired)
+        }
+    }
+}
+
+/// For routes which need the user to have authenticated within the given time.
+pub struct RequireRecentAuth(pub Duration);
+
+impl RequireRecentAuth {
+    pub fn check(&self, user: &AuthenticatedUser) -> Result<(), AppError> {
+        if user.authentication_age() <= self.0 {
+            Ok(())
+        } else {
+            Err(AppError::ReauthenticationRequired)
+        }
+    }
+}
+
+/// For sensitive operations: a user verified assertion, at sign in or by stepping up, within the given time.
+pub struct RequireStepUp(pub Duration);
+
+impl RequireStepUp {
+    pub fn check(&self, user: &AuthenticatedUser) -> Result<(), AppError> {
+        RequireUserVerification.check(user)
+            .and_then(|_| RequireRecentAuth(self.0).check(user))
+            // Stepping up is the remedy either way, and what the frontend starts on this error.
+            .map_err(|_| AppError::ReauthenticationRequired)
+    }
+}
+
+const BROWSERS: &[(&str, &str)] = &[
+    // Checked in this order, as Edge and Opera also claim to be Chrome, and Chrome to be Safari.
+    ("Edg/", "Edge"),
+    ("OPR/", "Opera"),
+    ("Firefox/", "Firefox"),
+    ("FxiOS/", "Firefox"),
+    ("CriOS/", "Chrome"),
+    ("Chrome/", "Chrome"),
+    ("Safari/", "Safari"),
+];
+
+const PLATFORMS: &[(&str, &str)] = &[
+    ("iPhone", "iPhone"),
+    ("iPad", "iPad"),
+    ("Android", "Android"),
+    ("CrOS", "Chrome OS"),
+    ("Windows", "Windows"),
+    ("Mac OS X", "macOS"),
+    ("Linux", "Linux"),
+];
+
+/// A rough "Firefox on Windows", to tell sessions apart at a glance.
+pub fn describe_device(user_agent: &str) -> Option<String> {
+    let find = |table: &[(&str, &'static str)]| table.iter().find(|(token, _)| user_agent.contains(token)).map(|(_, name)| *name);
+    match (find(BROWSERS), find(PLATFORMS)) {
+        (Some(browser), Some(platform)) => Some(format!("{} on {}", browser, platform)),
+        (Some(name), None) | (None, Some(name)) => Some(name.to_owned()),
+        (None, None) => None,
+    }
+}
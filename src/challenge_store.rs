@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use actix::Addr;
+use actix_redis::{Command, RedisActor, RespValue};
+use futures::{future, Future};
+use serde::{Deserialize, Serialize};
+
+// How long a used challenge is remembered after its use or expiry, so that a replay is told apart from an unknown challenge.
+const REPLAY_RETENTION: Duration = Duration::from_secs(10 * 60);
+const REDIS_KEY_PREFIX: &str = "challenge:";
+const REDIS_USED: &str = "used";
+// Reads the challenge and leaves a tombstone in its place in one step, so that concurrent uses cannot both get it.
+const REDIS_TAKE_SCRIPT: &str = r#"
+local value = redis.call('GET', KEYS[1])
+if value then
+  redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+end
+return value
+"#;
+
+#[derive(Debug, Fail)]
+pub enum ChallengeError {
+    #[fail(display = "challenge is unknown")]
+    Unknown,
+    #[fail(display = "challenge has expired")]
+    Expired,
+    #[fail(display = "challenge has already been used")]
+    Replayed,
+    #[fail(display = "challenge was issued for another ceremony")]
+    Mismatch,
+    #[fail(display = "challenge store failed: {}", _0)]
+    Store(String),
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Ceremony {
+    // The account is created once the credential is verified.
+    Registration {
+        user_name: String,
+        display_name: String,
+        // base64url encoded user.id
+        user_handle: String,
+    },
+    // Without a user name, the account is resolved from the user handle of a discoverable credential.
+    Authentication {
+        user_name: Option<String>,
+    },
+    // Re-authentication of a signed in user, only good for the session it was issued to.
+    StepUp {
+        session_id: i32,
+    },
+    // Another credential for a signed in user, only good for that user.
+    AddCredential {
+        user_id: i32,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChallengeBinding {
+    ceremony: Ceremony,
+    rp_id: String,
+    // Milliseconds since the UNIX epoch.
+    expires_at: u64,
+}
+
+impl ChallengeBinding {
+    pub fn new(ceremony: Ceremony, rp_id: &str, timeout: Duration) -> Self {
+        ChallengeBinding {
+            ceremony,
+            rp_id: rp_id.to_owned(),
+            expires_at: unix_millis() + timeout.as_millis() as u64,
+        }
+    }
+
+    /// The ceremony the challenge was issued for, provided it was issued for this RP ID.
+    pub fn into_ceremony(self, rp_id: &str) -> Result<Ceremony, ChallengeError> {
+        if self.rp_id == rp_id {
+            Ok(self.ceremony)
+        } else {
+            Err(ChallengeError::Mismatch)
+        }
+    }
+
+    /// Checks that the challenge was issued for exactly this ceremony, on this RP ID.
+    pub fn expect_ceremony(self, rp_id: &str, expected: &Ceremony) -> Result<(), ChallengeError> {
+        if self.into_ceremony(rp_id)? == *expected {
+            Ok(())
+        } else {
+            Err(ChallengeError::Mismatch)
+        }
+    }
+
+    fn time_left(&self) -> Duration {
+        Duration::from_millis(self.expires_at.saturating_sub(unix_millis()))
+    }
+
+    fn is_expired(&self) -> bool {
+        unix_millis() >= self.expires_at
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+pub type ChallengeFuture<T> = Box<dyn Future<Item = T, Error = ChallengeError>>;
+
+// Challenges are looked up by their base64url encoding, the value the browser signs in clientDataJSON.
+pub trait ChallengeStore {
+    fn issue(&self, challenge: &str, binding: ChallengeBinding) -> ChallengeFuture<()>;
+
+    /// Consumes the challenge, a second take of the same challenge fails with Replayed.
+    fn take(&self, challenge: &str) -> ChallengeFuture<ChallengeBinding>;
+}
+
+enum Entry {
+    Pending(ChallengeBinding),
+    Used,
+}
+
+// For a single server, workers share it through the Arc.
+#[derive(Clone, Default)]
+pub struct MemoryChallengeStore {
+    entries: Arc<Mutex<HashMap<String, (Entry, Instant)>>>,
+}
+
+impl MemoryChallengeStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ChallengeStore for MemoryChallengeStore {
+    fn issue(&self, challenge: &str, binding: ChallengeBinding) -> ChallengeFuture<()> {
+        let now = Instant::now();
+        let mut entries = match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(_) => return Box::new(future::err(ChallengeError::Store("poisoned lock".to_owned()))),
+        };
+        // Abandoned ceremonies never come back for their challenge.
+        entries.retain(|_, (_, forget_at)| *forget_at > now);
+        let forget_at = now + binding.time_left() + REPLAY_RETENTION;
+        entries.insert(challenge.to_owned(), (Entry::Pending(binding), forget_at));
+        Box::new(future::ok(()))
+    }
+
+    fn take(&self, challenge: &str) -> ChallengeFuture<ChallengeBinding> {
+        let mut entries = match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(_) => return Box::new(future::err(ChallengeError::Store("poisoned lock".to_owned()))),
+        };
+        let entry = entries.get_mut(challenge)
+            .map(|entry| std::mem::replace(entry, (Entry::Used, Instant::now() + REPLAY_RETENTION)));
+        Box::new(future::result(match entry {
+            None => Err(ChallengeError::Unknown),
+            Some((Entry::Used, _)) => Err(ChallengeError::Replayed),
+            Some((Entry::Pending(binding), _)) if binding.is_expired() => Err(ChallengeError::Expired),
+            Some((Entry::Pending(binding), _)) => Ok(binding),
+        }))
+    }
+}
+
+// Shared by every server using the same Redis, the RedisActor lives in the worker it is started from.
+pub struct RedisChallengeStore {
+    redis: Addr<RedisActor>,
+}
+
+impl RedisChallengeStore {
+    pub fn new(redis: Addr<RedisActor>) -> Self {
+        RedisChallengeStore { redis }
+    }
+
+    fn command(&self, args: &[&str]) -> impl Future<Item = RespValue, Error = ChallengeError> {
+        let command = Command(RespValue::Array(args.iter().map(|arg| RespValue::BulkString(arg.as_bytes().to_vec())).collect()));
+        self.redis.send(command).then(|result| match result {
+            Ok(Ok(RespValue::Error(e))) => Err(ChallengeError::Store(e)),
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(e)) => Err(ChallengeError::Store(e.to_string())),
+            Err(e) => Err(ChallengeError::Store(e.to_string())),
+        })
+    }
+}
+
+impl ChallengeStore for RedisChallengeStore {
+    fn issue(&self, challenge: &str, binding: ChallengeBinding) -> ChallengeFuture<()> {
+        let value = match serde_json::to_string(&binding) {
+            Ok(value) => value,
+            Err(e) => return Box::new(future::err(ChallengeError::Store(e.to_string()))),
+        };
+        let ttl = (binding.time_left() + REPLAY_RETENTION).as_millis().to_string();
+        let key = format!("{}{}", REDIS_KEY_PREFIX, challenge);
+        Box::new(self.command(&["SET", &key, &value, "PX", &ttl, "NX"]).and_then(|reply| match reply {
+            // NX refuses to overwrite, a 32 bytes random challenge does not collide.
+            RespValue::Nil => Err(ChallengeError::Store("challenge already exists".to_owned())),
+            _ => Ok(()),
+        }))
+    }
+
+    fn take(&self, challenge: &str) -> ChallengeFuture<ChallengeBinding> {
+        let key = format!("{}{}", REDIS_KEY_PREFIX, challenge);
+        let retention = REPLAY_RETENTION.as_millis().to_string();
+        Box::new(self.command(&["EVAL", REDIS_TAKE_SCRIPT, "1", &key, REDIS_USED, &retention]).and_then(|reply| match reply {
+            RespValue::Nil => Err(ChallengeError::Unknown),
+            RespValue::BulkString(ref value) if value.as_slice() == REDIS_USED.as_bytes() => Err(ChallengeError::Replayed),
+            RespValue::BulkString(value) => {
+                let binding: ChallengeBinding = serde_json::from_slice(&value).map_err(|e| ChallengeError::Store(e.to_string()))?;
+                if binding.is_expired() {
+                    Err(ChallengeError::Expired)
+                } else {
+                    Ok(binding)
+                }
+            },
+            reply => Err(ChallengeError::Store(format!("unexpected reply {:?}", reply))),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::discriminant;
+    use super::*;
+
+    const RP_ID: &str = "localhost";
+    const CHALLENGE: &str = "oV3hU0ZHVtJw2m6cB1j8kQ4yXn5sTa7dLq9RfEgPiKc";
+
+    fn issue(store: &MemoryChallengeStore, ceremony: Ceremony, timeout: Duration) {
+        store.issue(CHALLENGE, ChallengeBinding::new(ceremony, RP_ID, timeout)).wait().unwrap();
+    }
+
+    fn expect_error<T: std::fmt::Debug>(result: Result<T, ChallengeError>, expected: ChallengeError) {
+        match result {
+            Err(e) => assert_eq!(discriminant(&e), discriminant(&expected), "{}", e),
+            Ok(value) => panic!("got {:?}, expected {}", value, expected),
+        }
+    }
+
+    #[test]
+    fn takes_issued_challenge() {
+        let store = MemoryChallengeStore::new();
+        issue(&store, Ceremony::StepUp { session_id: 1 }, Duration::from_secs(60));
+        let binding = store.take(CHALLENGE).wait().unwrap();
+        assert_eq!(binding.into_ceremony(RP_ID).unwrap(), Ceremony::StepUp { session_id: 1 });
+    }
+
+    #[test]
+    fn rejects_second_take() {
+        let store = MemoryChallengeStore::new();
+        issue(&store, Ceremony::Authentication { user_name: None }, Duration::from_secs(60));
+        store.take(CHALLENGE).wait().unwrap();
+        expect_error(store.take(CHALLENGE).wait(), ChallengeError::Replayed);
+        // Still a replay after a failed take.
+        expect_error(store.take(CHALLENGE).wait(), ChallengeError::Replayed);
+    }
+
+    #[test]
+    fn rejects_expired_challenge() {
+        let store = MemoryChallengeStore::new();
+        issue(&store, Ceremony::Authentication { user_name: None }, Duration::from_secs(0));
+        expect_error(store.take(CHALLENGE).wait(), ChallengeError::Expired);
+        expect_error(store.take(CHALLENGE).wait(), ChallengeError::Replayed);
+    }
+
+    #[test]
+    fn rejects_unknown_challenge() {
+        let store = MemoryChallengeStore::new();
+        issue(&store, Ceremony::Authentication { user_name: None }, Duration::from_secs(60));
+        expect_error(store.take("58q9gTru_TLuaLMHJoR-Ho1jaD3v3_dp9sBfEj1zqXd").wait(), ChallengeError::Unknown);
+    }
+
+    #[test]
+    fn rejects_other_rp_id() {
+        let binding = ChallengeBinding::new(Ceremony::AddCredential { user_id: 1 }, RP_ID, Duration::from_secs(60));
+        expect_error(binding.into_ceremony("example.localhost"), ChallengeError::Mismatch);
+    }
+
+    #[test]
+    fn rejects_other_ceremony() {
+        let binding = || ChallengeBinding::new(Ceremony::StepUp { session_id: 1 }, RP_ID, Duration::from_secs(60));
+        binding().expect_ceremony(RP_ID, &Ceremony::StepUp { session_id: 1 }).unwrap();
+        expect_error(binding().expect_ceremony(RP_ID, &Ceremony::StepUp { session_id: 2 }), ChallengeError::Mismatch);
+        expect_error(binding().expect_ceremony(RP_ID, &Ceremony::AddCredential { user_id: 1 }), ChallengeError::Mismatch);
+        expect_error(binding().expect_ceremony("example.localhost", &Ceremony::StepUp { session_id: 1 }), ChallengeError::Mismatch);
+    }
+
+    // The Redis store keeps bindings as JSON, a challenge has to come back as the ceremony it was issued for.
+    #[test]
+    fn keeps_ceremony_through_serialization() {
+        let binding = ChallengeBinding::new(Ceremony::AddCredential { user_id: 1 }, RP_ID, Duration::from_secs(60));
+        let binding: ChallengeBinding = serde_json::from_str(&serde_json::to_string(&binding).unwrap()).unwrap();
+        assert_eq!(binding.into_ceremony(RP_ID).unwrap(), Ceremony::AddCredential { user_id: 1 });
+    }
+}
@@ -0,0 +1,368 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use serde::{Deserialize, Deserializer};
+use serde::de::Error as _;
+use toml::Value;
+use crate::models::SessionTimeouts;
+use crate::webauthn::{Algorithm, Attestation, OriginError, OriginPolicy, SignCountPolicy, UserVerification};
+
+const CONFIG_PATH_VAR: &str = "YO_CONFIG";
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+const DEFAULT_COOKIE_NAME: &str = "yo_session";
+const DEFAULT_KEY_GRACE_PERIOD: u64 = 24 * 60 * 60;
+const DEFAULT_STEP_UP_WINDOW: u64 = 5 * 60;
+
+#[derive(Debug, Fail)]
+pub enum ConfigError {
+    #[fail(display = "failed to read {:?}: {}", _0, _1)]
+    Io(PathBuf, #[cause] io::Error),
+    #[fail(display = "{:?} is not a valid configuration: {}", _0, _1)]
+    Parse(PathBuf, #[cause] toml::de::Error),
+    #[fail(display = "environment variable {} is invalid: {}", _0, _1)]
+    InvalidEnvironment(&'static str, String),
+    #[fail(display = "{} is invalid: {}", _0, _1)]
+    Invalid(&'static str, String),
+}
+
+#[derive(Clone, Copy)]
+enum OverrideKind {
+    String,
+    // Comma separated.
+    List,
+    Integer,
+    Boolean,
+}
+
+// Environment variables taking precedence over the file, as (variable, table, key, kind).
+// DATABASE_URL keeps the name the diesel CLI reads too.
+const ENV_OVERRIDES: &[(&str, &str, &str, OverrideKind)] = &[
+    ("YO_PROFILE", "server", "profile", OverrideKind::String),
+    ("YO_BIND", "server", "bind", OverrideKind::String),
+    ("YO_TLS_CERTIFICATE", "server", "tls_certificate", OverrideKind::String),
+    ("YO_TLS_PRIVATE_KEY", "server", "tls_private_key", OverrideKind::String),
+    ("YO_RP_ID", "relying_party", "id", OverrideKind::String),
+    ("YO_RP_NAME", "relying_party", "name", OverrideKind::String),
+    ("YO_RP_ORIGINS", "relying_party", "origins", OverrideKind::List),
+    ("YO_RP_RELATED_ORIGINS", "relying_party", "related_origins", OverrideKind::List),
+    ("YO_ALGORITHMS", "webauthn", "algorithms", OverrideKind::List),
+    ("YO_ATTESTATION", "webauthn", "attestation", OverrideKind::String),
+    ("YO_USER_VERIFICATION", "webauthn", "user_verification", OverrideKind::String),
+    ("YO_SIGN_COUNT_POLICY", "webauthn", "sign_count_policy", OverrideKind::String),
+    ("YO_CEREMONY_TIMEOUT", "webauthn", "timeout", OverrideKind::Integer),
+    ("YO_CHALLENGE_STORE", "webauthn", "challenge_store", OverrideKind::String),
+    ("YO_TRUSTED_ATTESTATION_REQUIRED", "webauthn", "trusted_attestation_required", OverrideKind::Boolean),
+    ("YO_NONE_ATTESTATION_PERMITTED", "webauthn", "none_attestation_permitted", OverrideKind::Boolean),
+    ("YO_SELF_ATTESTATION_PERMITTED", "webauthn", "self_attestation_permitted", OverrideKind::Boolean),
+    ("YO_METADATA_STATEMENT_REQUIRED", "webauthn", "metadata_statement_required", OverrideKind::Boolean),
+    ("YO_REDIS_ADDRESS", "redis", "address", OverrideKind::String),
+    ("DATABASE_URL", "database", "url", OverrideKind::String),
+    ("YO_SESSION_KEY", "session", "key", OverrideKind::String),
+    ("YO_SESSION_KEY_FILE", "session", "key_file", OverrideKind::String),
+    ("YO_SESSION_IDLE_TIMEOUT", "session", "idle_timeout", OverrideKind::Integer),
+    ("YO_SESSION_ABSOLUTE_TIMEOUT", "session", "absolute_timeout", OverrideKind::Integer),
+    ("YO_SESSION_STEP_UP_WINDOW", "session", "step_up_window", OverrideKind::Integer),
+];
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub relying_party: RelyingPartyConfig,
+    pub webauthn: WebAuthnConfig,
+    pub redis: RedisConfig,
+    pub database: DatabaseConfig,
+    pub session: SessionConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    #[serde(default)]
+    pub profile: Profile,
+    pub bind: String,
+    pub tls_certificate: PathBuf,
+    pub tls_private_key: PathBuf,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    // Tolerates a missing or weak session key, for local development only.
+    Development,
+    Production,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile::Production
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RelyingPartyConfig {
+    pub id: String,
+    pub name: String,
+    pub icon: Option<String>,
+    // Origins the browser may report in clientDataJSON, e.g. "https://example.com:8443" or "https://*.example.com".
+    pub origins: Vec<String>,
+    // Origins outside of the RP ID, published in /.well-known/webauthn.
+    #[serde(default)]
+    pub related_origins: Vec<String>,
+    #[serde(default)]
+    pub android_apk_key_hashes: Vec<String>,
+    #[serde(default)]
+    pub ios_bundle_ids: Vec<String>,
+}
+
+impl RelyingPartyConfig {
+    pub fn origin_policy(&self) -> Result<OriginPolicy, OriginError> {
+        Ok(OriginPolicy::new(&self.id, &self.origins)?
+            .with_related_origins(&self.related_origins)?
+            .with_android_apps(&self.android_apk_key_hashes)
+            .with_ios_apps(&self.ios_bundle_ids))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebAuthnConfig {
+    // Offered in pubKeyCredParams in this order of preference.
+    #[serde(deserialize_with = "deserialize_algorithms")]
+    pub algorithms: Vec<Algorithm>,
+    pub attestation: Attestation,
+    pub user_verification: UserVerification,
+    // What an assertion whose signature counter did not increase gets, see SignCountPolicy.
+    #[serde(default)]
+    pub sign_count_policy: SignCountPolicy,
+    // Milliseconds, passed to the browser as the ceremony timeout.
+    pub timeout: usize,
+    // Seconds a conditional mediation challenge stays usable.
+    pub conditional_challenge_ttl: u64,
+    #[serde(default)]
+    pub challenge_store: ChallengeStoreKind,
+    // Accepts authenticators that return no attestation, which attestation = "none" asks them to. Off unless configured.
+    #[serde(default)]
+    pub none_attestation_permitted: bool,
+    // Accepts attestations signed by the credential key itself. Off unless configured.
+    #[serde(default)]
+    pub self_attestation_permitted: bool,
+    pub trust_anchors: PathBuf,
+    // Refuses attestations whose certificate chain does not reach a trust anchor, including self and none attestations.
+    #[serde(default)]
+    pub trusted_attestation_required: bool,
+    pub metadata_blob: PathBuf,
+    pub metadata_root: PathBuf,
+    // Refuses authenticators the metadata BLOB has no statement for.
+    #[serde(default)]
+    pub metadata_statement_required: bool,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChallengeStoreKind {
+    // Shared by every server instance.
+    Redis,
+    // Per process, for a single server.
+    Memory,
+}
+
+impl Default for ChallengeStoreKind {
+    fn default() -> Self {
+        ChallengeStoreKind::Redis
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedisConfig {
+    pub address: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionConfig {
+    // Base64 in the file, empty when read from key_file instead.
+    #[serde(default, deserialize_with = "deserialize_base64")]
+    pub key: Vec<u8>,
+    // A file holding the base64 key, e.g. a mounted secret.
+    pub key_file: Option<PathBuf>,
+    // Keys rotated out, still accepted for key_grace_period seconds after retired_at.
+    #[serde(default)]
+    pub previous_keys: Vec<PreviousKeyConfig>,
+    #[serde(default = "default_key_grace_period")]
+    pub key_grace_period: u64,
+    #[serde(default = "default_cookie_name")]
+    pub cookie_name: String,
+    #[serde(default)]
+    pub same_site: SameSitePolicy,
+    // Seconds a signed in session survives without requests.
+    pub idle_timeout: u64,
+    // Seconds a signed in session lasts at most, however active.
+    pub absolute_timeout: u64,
+    // Seconds a user verified assertion keeps sensitive operations open.
+    #[serde(default = "default_step_up_window")]
+    pub step_up_window: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PreviousKeyConfig {
+    #[serde(deserialize_with = "deserialize_base64")]
+    pub key: Vec<u8>,
+    // A TOML offset date-time, e.g. 2019-11-17T00:00:00Z.
+    #[serde(deserialize_with = "deserialize_datetime")]
+    pub retired_at: SystemTime,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSitePolicy {
+    Strict,
+    Lax,
+    None,
+}
+
+impl Default for SameSitePolicy {
+    fn default() -> Self {
+        SameSitePolicy::Lax
+    }
+}
+
+fn default_key_grace_period() -> u64 {
+    DEFAULT_KEY_GRACE_PERIOD
+}
+
+fn default_cookie_name() -> String {
+    DEFAULT_COOKIE_NAME.to_owned()
+}
+
+fn default_step_up_window() -> u64 {
+    DEFAULT_STEP_UP_WINDOW
+}
+
+impl SessionConfig {
+    pub fn timeouts(&self) -> SessionTimeouts {
+        SessionTimeouts {
+            idle: Duration::from_secs(self.idle_timeout),
+            absolute: Duration::from_secs(self.absolute_timeout),
+        }
+    }
+
+    pub fn step_up_window(&self) -> Duration {
+        Duration::from_secs(self.step_up_window)
+    }
+}
+
+fn deserialize_algorithms<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Algorithm>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|name| Algorithm::from_name(name).ok_or_else(|| D::Error::custom(format!("unknown algorithm {:?}", name))))
+        .collect()
+}
+
+fn deserialize_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    base64::decode(&encoded).map_err(|_| D::Error::custom("session key is not valid base64"))
+}
+
+fn deserialize_datetime<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+    let datetime = toml::value::Datetime::deserialize(deserializer)?.to_string();
+    chrono::DateTime::parse_from_rfc3339(&datetime)
+        .map(SystemTime::from)
+        .map_err(|_| D::Error::custom(format!("{} is not an offset date-time", datetime)))
+}
+
+impl WebAuthnConfig {
+    pub fn conditional_challenge_ttl(&self) -> Duration {
+        Duration::from_secs(self.conditional_challenge_ttl)
+    }
+}
+
+impl Config {
+    /// Reads the file named by YO_CONFIG, config.toml by default, then applies the environment overrides.
+    pub fn load() -> Result<Self, ConfigError> {
+        let path = env::var_os(CONFIG_PATH_VAR).map(PathBuf::from).unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH));
+        Self::from_file(&path)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+        let mut value = content.parse::<Value>().map_err(|e| ConfigError::Parse(path.to_owned(), e))?;
+        apply_env_overrides(&mut value)?;
+        let config: Config = value.try_into().map_err(|e| ConfigError::Parse(path.to_owned(), e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.relying_party.origins.is_empty() {
+            return Err(ConfigError::Invalid("relying_party.origins", "at least one origin is required".to_owned()))
+        }
+        self.relying_party.origin_policy().map_err(|e| ConfigError::Invalid("relying_party", e.to_string()))?;
+        if self.database.url.is_empty() {
+            return Err(ConfigError::Invalid("database.url", "is not set".to_owned()))
+        }
+        if self.redis.address.is_empty() {
+            return Err(ConfigError::Invalid("redis.address", "is not set".to_owned()))
+        }
+        if self.webauthn.algorithms.is_empty() {
+            return Err(ConfigError::Invalid("webauthn.algorithms", "at least one algorithm is required".to_owned()))
+        }
+        if self.webauthn.timeout == 0 {
+            return Err(ConfigError::Invalid("webauthn.timeout", "must be greater than zero".to_owned()))
+        }
+        if self.session.cookie_name.is_empty() {
+            return Err(ConfigError::Invalid("session.cookie_name", "is not set".to_owned()))
+        }
+        if self.session.idle_timeout == 0 || self.session.idle_timeout > self.session.absolute_timeout {
+            return Err(ConfigError::Invalid("session.idle_timeout", "must be greater than zero and at most session.absolute_timeout".to_owned()))
+        }
+        if self.session.step_up_window == 0 {
+            return Err(ConfigError::Invalid("session.step_up_window", "must be greater than zero".to_owned()))
+        }
+        let files = [
+            ("server.tls_certificate", &self.server.tls_certificate),
+            ("server.tls_private_key", &self.server.tls_private_key),
+            ("webauthn.trust_anchors", &self.webauthn.trust_anchors),
+        ];
+        for &(field, path) in files.iter() {
+            if !path.exists() {
+                return Err(ConfigError::Invalid(field, format!("{:?} does not exist", path)))
+            }
+        }
+        Ok(())
+    }
+}
+
+fn apply_env_overrides(value: &mut Value) -> Result<(), ConfigError> {
+    let root = value.as_table_mut().ok_or_else(|| ConfigError::Invalid("configuration", "not a table".to_owned()))?;
+    for &(variable, table, key, kind) in ENV_OVERRIDES {
+        let raw = match env::var(variable) {
+            Ok(raw) => raw,
+            Err(env::VarError::NotPresent) => continue,
+            Err(env::VarError::NotUnicode(_)) => return Err(ConfigError::InvalidEnvironment(variable, "not valid unicode".to_owned())),
+        };
+        let overridden = match kind {
+            OverrideKind::String => Value::String(raw),
+            OverrideKind::List => Value::Array(raw.split(',').map(|v| Value::String(v.trim().to_owned())).filter(|v| v.as_str() != Some("")).collect()),
+            OverrideKind::Integer => Value::Integer(raw.parse().map_err(|_| ConfigError::InvalidEnvironment(variable, format!("{:?} is not an integer", raw)))?),
+            OverrideKind::Boolean => Value::Boolean(raw.parse().map_err(|_| ConfigError::InvalidEnvironment(variable, format!("{:?} is neither true nor false", raw)))?),
+        };
+        let table = root.entry(table.to_string())
+            .or_insert_with(|| Value::Table(Default::default()))
+            .as_table_mut()
+            .ok_or_else(|| ConfigError::Invalid(table, "not a table".to_owned()))?;
+        table.insert(key.to_string(), overridden);
+    }
+    Ok(())
+}
@@ -0,0 +1,10 @@
+use diesel::pg::PgConnection;
+use diesel::r2d2::{self, ConnectionManager};
+
+pub type Pool = r2d2::Pool<ConnectionManager<PgConnection>>;
+pub type Connection = r2d2::PooledConnection<ConnectionManager<PgConnection>>;
+
+pub fn init_pool(database_url: &str) -> Result<Pool, r2d2::PoolError> {
+    let manager = ConnectionManager::<PgConnection>::new(database_url);
+    r2d2::Pool::builder().build(manager)
+}
@@ -0,0 +1,195 @@
+use actix_web::{HttpResponse, ResponseError};
+use actix_web::error::BlockingError;
+use actix_web::http::StatusCode;
+use diesel::r2d2::PoolError;
+use diesel::result::Error as DieselError;
+use serde::Serialize;
+use validator::ValidationErrors;
+use crate::challenge_store::ChallengeError;
+use crate::models::{CredentialError, RegisterUserError};
+use crate::webauthn::error::{ErrorKind, WebAuthnError};
+
+// Everything a handler can fail with, rendered as an RFC 7807 problem document.
+#[derive(Debug, Fail)]
+pub enum AppError {
+    #[fail(display = "{}", _0)]
+    WebAuthn(#[cause] WebAuthnError),
+    #[fail(display = "invalid request: {}", _0)]
+    InvalidRequest(String),
+    #[fail(display = "{}", _0)]
+    Challenge(#[cause] ChallengeError),
+    #[fail(display = "sign in required")]
+    Unauthorized,
+    #[fail(display = "sign in with user verification required")]
+    UserVerificationRequired,
+    #[fail(display = "recent authentication required")]
+    ReauthenticationRequired,
+    #[fail(display = "credential is not registered to this user")]
+    UnknownCredential,
+    #[fail(display = "signature counter did not increase, the authenticator may be cloned")]
+    SuspectedClone,
+    #[fail(display = "not found")]
+    NotFound,
+    #[fail(display = "username or credential is already registered")]
+    AlreadyRegistered,
+    #[fail(display = "the last credential cannot be removed without another way to sign in")]
+    LastCredential,
+    #[fail(display = "stored credential public key is invalid")]
+    InvalidStoredPublicKey,
+    #[fail(display = "failed to get a database connection: {}", _0)]
+    Connection(#[cause] PoolError),
+    #[fail(display = "database error: {}", _0)]
+    Database(#[cause] DieselError),
+    #[fail(display = "blocking task was canceled")]
+    Canceled,
+}
+
+impl AppError {
+    /// A stable identifier for clients to match on, the display message may change.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::WebAuthn(e) => e.code(),
+            AppError::InvalidRequest(_) => "invalid_request",
+            AppError::Challenge(ChallengeError::Unknown) => "unknown_challenge",
+            AppError::Challenge(ChallengeError::Expired) => "expired_challenge",
+            AppError::Challenge(ChallengeError::Replayed) => "replayed_challenge",
+            AppError::Challenge(ChallengeError::Mismatch) => "challenge_mismatch",
+            AppError::Unauthorized => "unauthorized",
+            AppError::UserVerificationRequired => "user_verification_required",
+            AppError::ReauthenticationRequired => "reauth_required",
+            AppError::UnknownCredential => "unknown_credential",
+            AppError::SuspectedClone => "suspected_clone",
+            AppError::NotFound => "not_found",
+            AppError::AlreadyRegistered => "already_registered",
+            AppError::LastCredential => "last_credential",
+            AppError::Challenge(ChallengeError::Store(_))
+            | AppError::InvalidStoredPublicKey
+            | AppError::Connection(_)
+            | AppError::Database(_)
+            | AppError::Canceled => "internal_error",
+        }
+    }
+
+    // Only WebAuthn errors have one, the other codes say enough.
+    fn kind(&self) -> Option<ErrorKind> {
+        match self {
+            AppError::WebAuthn(e) => Some(e.kind()),
+            _ => None,
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            // Misconfigured trust anchors are our fault, not the client's.
+            AppError::WebAuthn(WebAuthnError::InvalidTrustAnchor(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::WebAuthn(WebAuthnError::InvalidCredential)
+            | AppError::WebAuthn(WebAuthnError::InvalidUserHandle)
+            | AppError::WebAuthn(WebAuthnError::InvalidSignCount(_))
+            | AppError::WebAuthn(WebAuthnError::InvalidSignature) => StatusCode::UNAUTHORIZED,
+            AppError::WebAuthn(_) => StatusCode::BAD_REQUEST,
+            AppError::Challenge(ChallengeError::Store(_)) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::InvalidRequest(_) | AppError::Challenge(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized | AppError::UnknownCredential | AppError::SuspectedClone => StatusCode::UNAUTHORIZED,
+            AppError::UserVerificationRequired | AppError::ReauthenticationRequired => StatusCode::FORBIDDEN,
+            AppError::NotFound => StatusCode::NOT_FOUND,
+            AppError::AlreadyRegistered | AppError::LastCredential => StatusCode::CONFLICT,
+            AppError::InvalidStoredPublicKey
+            | AppError::Connection(_)
+            | AppError::Database(_)
+            | AppError::Canceled => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+// Spec: https://tools.ietf.org/html/rfc7807
+#[derive(Serialize)]
+struct Problem {
+    #[serde(rename(serialize = "type"))]
+    r#type: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<ErrorKind>,
+}
+
+impl ResponseError for AppError {
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status();
+        let problem = Problem {
+            r#type: "about:blank",
+            title: status.canonical_reason().unwrap_or(""),
+            status: status.as_u16(),
+            // Internal errors may carry database details, which stay on the server.
+            detail: if status.is_server_error() { "internal error".to_owned() } else { self.to_string() },
+            code: self.code(),
+            kind: self.kind(),
+        };
+        HttpResponse::build(status)
+            .content_type("application/problem+json")
+            .body(serde_json::to_string(&problem).unwrap_or_default())
+    }
+}
+
+impl From<WebAuthnError> for AppError {
+    fn from(e: WebAuthnError) -> Self {
+        AppError::WebAuthn(e)
+    }
+}
+
+impl From<ChallengeError> for AppError {
+    fn from(e: ChallengeError) -> Self {
+        AppError::Challenge(e)
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(e: ValidationErrors) -> Self {
+        let mut fields: Vec<&str> = e.errors().keys().cloned().collect();
+        fields.sort();
+        AppError::InvalidRequest(fields.join(", "))
+    }
+}
+
+impl From<PoolError> for AppError {
+    fn from(e: PoolError) -> Self {
+        AppError::Connection(e)
+    }
+}
+
+impl From<DieselError> for AppError {
+    fn from(e: DieselError) -> Self {
+        AppError::Database(e)
+    }
+}
+
+impl From<RegisterUserError> for AppError {
+    fn from(e: RegisterUserError) -> Self {
+        match e {
+            RegisterUserError::Conflict => AppError::AlreadyRegistered,
+            RegisterUserError::Connection(e) => AppError::Connection(e),
+            RegisterUserError::Database(e) => AppError::Database(e),
+        }
+    }
+}
+
+impl From<CredentialError> for AppError {
+    fn from(e: CredentialError) -> Self {
+        match e {
+            CredentialError::NotFound => AppError::NotFound,
+            CredentialError::LastCredential => AppError::LastCredential,
+            CredentialError::Connection(e) => AppError::Connection(e),
+            CredentialError::Database(e) => AppError::Database(e),
+        }
+    }
+}
+
+impl<E: Into<AppError> + std::fmt::Debug> From<BlockingError<E>> for AppError {
+    fn from(e: BlockingError<E>) -> Self {
+        match e {
+            BlockingError::Error(e) => e.into(),
+            BlockingError::Canceled => AppError::Canceled,
+        }
+    }
+}
@@ -1,10 +1,8 @@
 use rand::{thread_rng, Rng};
-use rand::distributions::Alphanumeric;
 
+// Raw random bytes, base64url encoded without padding as WebAuthn serializes buffers.
 pub fn generate_random(length: usize) -> String {
-    let s: String = thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(length)
-        .collect();
-    base64::encode(s.as_bytes())
-}
\ No newline at end of file
+    let mut bytes = vec![0; length];
+    thread_rng().fill(&mut bytes[..]);
+    base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD)
+}
@@ -12,27 +12,67 @@ extern crate actix_session;
 extern crate listenfd;
 extern crate actix_redis;
 extern crate serde_cbor;
+#[macro_use] extern crate diesel;
+extern crate dotenv;
+extern crate chrono;
+extern crate futures;
+extern crate toml;
+extern crate actix;
+extern crate actix_service;
 
 use actix_session::Session;
 use actix_files::NamedFile;
-use actix_web::{App, HttpServer, middleware, web, HttpResponse, Responder};
+use actix_web::{App, HttpRequest, HttpServer, middleware, web, HttpResponse};
+use futures::{future, Future};
 use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
 use serde::{Serialize, Deserialize};
+use std::borrow::Borrow;
 use std::path::PathBuf;
+use std::time::Duration;
 use validator::{Validate, ValidationError};
 use listenfd::ListenFd;
-use actix_redis::RedisSession;
+use actix_redis::{RedisActor, RedisSession};
 
 mod webauthn;
 mod helper;
+mod schema;
+mod models;
+mod config;
+mod db;
+mod error;
+mod challenge_store;
+mod auth_session;
+mod session_key;
+
+use chrono::NaiveDateTime;
+use diesel::Connection;
+use diesel::pg::PgConnection;
+use auth_session::{AuthenticatedUser, CurrentSession, RequireStepUp, SessionRenewal, SignIn};
+use challenge_store::{Ceremony, ChallengeBinding, ChallengeError, ChallengeStore, MemoryChallengeStore, RedisChallengeStore};
+use config::{ChallengeStoreKind, Config};
+use error::AppError;
+use session_key::{CookieSettings, KeyRing, KeyRotation};
+use models::{Credential, CredentialError, NewCredential, NewSuspectedClone, NewUser, RegisterUserError, UserSession};
 
 use webauthn::{
     PublicKeyCredentialCreationOptions,
+    AuthenticatorSelection,
+    ResidentKeyRequirement,
     RelyingParty,
     User,
     CredParam,
-    Algorithm,
     RegistrationResponse,
+    PublicKeyCredentialRequestOptions,
+    UserVerification,
+    AllowCredential,
+    ExcludeCredential,
+    AuthenticatorTransport,
+    AuthenticationResponse,
+    CoseKey,
+    WebAuthnError,
+    TrustAnchorStore,
+    MetadataStore,
+    OriginPolicy,
 };
 
 fn index() -> actix_web::Result<NamedFile> {
@@ -55,32 +95,67 @@ fn validate_name(value: &str) -> Result<(), ValidationError>{
     }
 }
 
-fn create_credential(session: Session, register_form: web::Json<RegistrationForm>) -> actix_web::Result<HttpResponse> {
-    session.clear();
-    match register_form.validate() {
-        Ok(()) => {
-            let rp = RelyingParty::new("yo", "localhost", None);
-            let user = User::new(&register_form.username, &register_form.display_name, None);
-            let pub_key_cred_params = vec![Algorithm::ES256, Algorithm::PS256, Algorithm::RS256].into_iter().map(CredParam::new).collect();
-            let options = PublicKeyCredentialCreationOptions::new(
-                rp,
-                user,
-                32,
-                pub_key_cred_params,
-                None,
-                None,
-                None,
-                None,
-                None,
-            );
-            session.set("username", &options.user.name)?;
-            session.set("display_name", &options.user.display_name)?;
-            session.set("challenge", &options.challenge)?;
-            session.set("ukey", &options.user.id)?;
-            Ok(HttpResponse::Ok().json(options))
-        }
-        Err(_) => Ok(HttpResponse::BadRequest().finish()),  // TODO: error handling
+fn create_credential(
+    config: web::Data<Config>,
+    challenges: web::Data<Box<dyn ChallengeStore>>,
+    register_form: web::Json<RegistrationForm>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    if let Err(e) = register_form.validate() {
+        return Box::new(future::err(AppError::from(e).into()))
     }
+    let user = User::new(&register_form.username, &register_form.display_name, None);
+    let options = creation_options(&config, user, None);
+    // The account is created from what the challenge is bound to, not from what the browser sends back.
+    let ceremony = Ceremony::Registration {
+        user_name: options.user.name.clone(),
+        display_name: options.user.display_name.clone(),
+        user_handle: options.user.id.clone(),
+    };
+    issue_challenge(&challenges, &config, &options.challenge, ceremony, ceremony_timeout(&config), HttpResponse::Ok().json(&options))
+}
+
+fn creation_options(config: &Config, user: User, exclude_credentials: Option<Vec<ExcludeCredential>>) -> PublicKeyCredentialCreationOptions {
+    let relying_party = &config.relying_party;
+    let rp = RelyingParty::new(&relying_party.name, &relying_party.id, relying_party.icon.as_ref().map(String::as_str));
+    let pub_key_cred_params = config.webauthn.algorithms.iter().cloned().map(CredParam::new).collect();
+    PublicKeyCredentialCreationOptions::new(
+        rp,
+        user,
+        32,
+        pub_key_cred_params,
+        Some(config.webauthn.timeout),
+        exclude_credentials,
+        // Discoverable credentials let the user sign in without typing the username.
+        Some(AuthenticatorSelection::with_resident_key(Some(config.webauthn.user_verification), None, ResidentKeyRequirement::Preferred)),
+        Some(config.webauthn.attestation),
+        None,
+    )
+}
+
+fn ceremony_timeout(config: &Config) -> Duration {
+    Duration::from_millis(config.webauthn.timeout as u64)
+}
+
+// Responds with the options once their challenge is stored.
+fn issue_challenge(
+    challenges: &web::Data<Box<dyn ChallengeStore>>,
+    config: &Config,
+    challenge: &str,
+    ceremony: Ceremony,
+    timeout: Duration,
+    response: HttpResponse,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let binding = ChallengeBinding::new(ceremony, &config.relying_party.id, timeout);
+    Box::new(challenges.issue(challenge, binding).then(move |result| -> actix_web::Result<HttpResponse> {
+        result.map_err(AppError::from)?;
+        Ok(response)
+    }))
+}
+
+// The challenge the browser signed, which is how the ceremony is looked up.
+fn challenge_of(client_data: &str) -> Result<String, AppError> {
+    let client_data = webauthn::helper::base64_decode(client_data, "clientDataJSON")?;
+    Ok(webauthn::get_client_data(&client_data)?.challenge)
 }
 
 #[derive(Deserialize)]
@@ -89,13 +164,806 @@ pub struct AttestationResponse {
     pub att_obj: String,
     #[serde(rename(deserialize = "clientData"))]
     pub client_data: String,
+    // AuthenticatorAttestationResponse.getTransports(), where the browser supports it.
+    #[serde(default)]
+    pub transports: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct SignedInUser {
+    username: String,
+    #[serde(rename(serialize = "credentialId"))]
+    credential_id: String,
+}
+
+// The steps shared by signing up and adding a credential, whose NewCredential is returned without its user,
+// along with whether the user was verified.
+fn verify_registration(
+    config: &Config,
+    origin_policy: &OriginPolicy,
+    trust_anchors: &TrustAnchorStore,
+    metadata: Option<&MetadataStore>,
+    attestation_response: AttestationResponse,
+    challenge: &str,
+) -> Result<(NewCredential, bool), AppError> {
+    let transports = attestation_response.transports.clone();
+    let mut registration_response = RegistrationResponse::new(&config.relying_party.id, origin_policy, attestation_response);
+    registration_response.pub_key_cred_algs = config.webauthn.algorithms.clone();
+    registration_response.uv_required = config.webauthn.user_verification == UserVerification::Required;
+    registration_response.none_attestation_permitted = config.webauthn.none_attestation_permitted;
+    registration_response.self_attestation_permitted = config.webauthn.self_attestation_permitted;
+    registration_response.trust_anchors = Some(trust_anchors);
+    registration_response.metadata = metadata;
+    registration_response.metadata_statement_required = config.webauthn.metadata_statement_required;
+    registration_response.trusted_attestaion_cert_required = config.webauthn.trusted_attestation_required;
+    let verified = registration_response.verify(challenge)?;
+
+    let new_credential = NewCredential {
+        user_id: 0,  // assigned by the caller
+        credential_id: verified.credential_id,
+        public_key: verified.credential_public_key,
+        alg: Some(verified.algorithm.code() as i32),
+        sign_count: i64::from(verified.sign_count),
+        aaguid: Some(verified.aaguid.to_vec()),
+        transports,
+        attestation_format: Some(verified.attestation_format.identifier().to_owned()),
+        backup_eligible: verified.flags.backup_eligible,
+        backup_state: verified.flags.backup_state,
+        nickname: None,
+    };
+    Ok((new_credential, verified.flags.user_verified))
+}
+
+fn verify_credential(
+    req: HttpRequest,
+    session: Session,
+    config: web::Data<Config>,
+    origin_policy: web::Data<OriginPolicy>,
+    pool: web::Data<db::Pool>,
+    trust_anchors: web::Data<TrustAnchorStore>,
+    metadata: web::Data<Option<MetadataStore>>,
+    challenges: web::Data<Box<dyn ChallengeStore>>,
+    attestation_response: web::Json<AttestationResponse>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let attestation_response = attestation_response.into_inner();
+    let challenge = match challenge_of(&attestation_response.client_data) {
+        Ok(challenge) => challenge,
+        Err(e) => return Box::new(future::err(e.into())),
+    };
+    let sign_in = SignIn::new(&session, &req);
+    let timeouts = config.session.timeouts();
+    Box::new(
+        // The challenge is spent whatever happens next.
+        challenges.take(&challenge)
+            .map_err(AppError::from)
+            .and_then(move |binding| -> Result<_, AppError> {
+                let (username, display_name, ukey) = match binding.into_ceremony(&config.relying_party.id)? {
+                    Ceremony::Registration { user_name, display_name, user_handle } => (user_name, display_name, user_handle),
+                    Ceremony::Authentication { .. } | Ceremony::StepUp { .. } | Ceremony::AddCredential { .. } => return Err(ChallengeError::Mismatch.into()),
+                };
+                let (new_credential, user_verified) = verify_registration(
+                    &config,
+                    origin_policy.get_ref(),
+                    trust_anchors.get_ref(),
+                    metadata.get_ref().as_ref(),
+                    attestation_response,
+                    &challenge,
+                )?;
+                let new_user = NewUser {
+                    webauthn_user_id: ukey,
+                    display_name,
+                    name: username,
+                };
+                Ok((new_user, new_credential, user_verified))
+            })
+            .and_then(move |(new_user, new_credential, user_verified)| {
+                web::block(move || -> Result<_, RegisterUserError> {
+                    let conn = pool.get().map_err(RegisterUserError::Connection)?;
+                    let (user, credential) = new_user.register(&conn, new_credential)?;
+                    sign_in.record(&conn, &credential, user_verified, &timeouts)?;
+                    Ok((user, credential, sign_in))
+                })
+                .map_err(AppError::from)
+            })
+            .then(move |result| -> actix_web::Result<HttpResponse> {
+                let (user, credential, sign_in) = result?;
+                sign_in.start(&session, &req)?;
+                Ok(HttpResponse::Created().json(SignedInUser {
+                    username: user.name,
+                    credential_id: base64::encode_config(&credential.credential_id, base64::URL_SAFE_NO_PAD),
+                }))
+            })
+    )
+}
+
+#[derive(Serialize)]
+struct CredentialSummary {
+    id: i32,
+    nickname: Option<String>,
+    // Authenticator model, from the FIDO metadata statement of its AAGUID.
+    model: Option<String>,
+    aaguid: Option<String>,
+    transports: Vec<String>,
+    #[serde(rename(serialize = "createdAt"))]
+    created_at: NaiveDateTime,
+    #[serde(rename(serialize = "lastUsedAt"))]
+    last_used_at: Option<NaiveDateTime>,
+    // The credential the browser asking signed in with.
+    current: bool,
+}
+
+impl CredentialSummary {
+    fn new(credential: Credential, metadata: Option<&MetadataStore>, current_id: Option<i32>) -> Self {
+        let aaguid = credential.aaguid.as_ref()
+            .filter(|aaguid| aaguid.len() == 16 && aaguid.iter().any(|b| *b != 0))
+            .map(|v| {
+                let mut aaguid = [0; 16];
+                aaguid.copy_from_slice(v);
+                aaguid
+            });
+        CredentialSummary {
+            id: credential.id,
+            nickname: credential.nickname,
+            model: aaguid.and_then(|aaguid| metadata?.statement(&aaguid)).map(|statement| statement.description.clone()),
+            aaguid: aaguid.as_ref().map(webauthn::helper::format_aaguid),
+            transports: credential.transports,
+            created_at: credential.created_at,
+            last_used_at: credential.last_used_at,
+            current: Some(credential.id) == current_id,
+        }
+    }
+}
+
+fn list_credentials(
+    user: AuthenticatedUser,
+    pool: web::Data<db::Pool>,
+    metadata: web::Data<Option<MetadataStore>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let current_id = user.credential.as_ref().map(|credential| credential.id);
+    Box::new(
+        web::block(move || -> Result<_, AppError> {
+            let conn = pool.get()?;
+            Ok(user.user.credentials(&conn)?)
+        })
+        .then(move |result| -> actix_web::Result<HttpResponse> {
+            let credentials: Vec<CredentialSummary> = result.map_err(AppError::from)?.into_iter()
+                .map(|credential| CredentialSummary::new(credential, metadata.get_ref().as_ref(), current_id))
+                .collect();
+            Ok(HttpResponse::Ok().json(credentials))
+        })
+    )
+}
+
+// Another authenticator for the signed in user, such as a backup key. Like removing one, it needs a recent step-up.
+fn get_add_credential_options(
+    user: AuthenticatedUser,
+    config: web::Data<Config>,
+    pool: web::Data<db::Pool>,
+    challenges: web::Data<Box<dyn ChallengeStore>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    if let Err(e) = RequireStepUp(config.session.step_up_window()).check(&user) {
+        return Box::new(future::err(e.into()))
+    }
+    let ceremony = Ceremony::AddCredential { user_id: user.user.id };
+    Box::new(
+        web::block(move || -> Result<_, AppError> {
+            let conn = pool.get()?;
+            let credentials = user.user.credentials(&conn)?;
+            Ok((user.user, credentials))
+        })
+        .map_err(|e| -> actix_web::Error { AppError::from(e).into() })
+        .and_then(move |(user, credentials)| {
+            // The account's own user handle, so that the new credential resolves to it when discovered.
+            let mut webauthn_user = User::new(&user.name, &user.display_name, None);
+            webauthn_user.id = user.webauthn_user_id;
+            // An authenticator already holding one of the user's credentials refuses to create another.
+            let exclude_credentials = credentials.iter()
+                .map(|credential| ExcludeCredential::new(base64::encode_config(&credential.credential_id, base64::URL_SAFE_NO_PAD), None))
+                .collect();
+            let options = creation_options(&config, webauthn_user, Some(exclude_credentials));
+            issue_challenge(&challenges, &config, &options.challenge, ceremony, ceremony_timeout(&config), HttpResponse::Ok().json(&options))
+        })
+    )
+}
+
+fn verify_added_credential(
+    user: AuthenticatedUser,
+    config: web::Data<Config>,
+    origin_policy: web::Data<OriginPolicy>,
+    pool: web::Data<db::Pool>,
+    trust_anchors: web::Data<TrustAnchorStore>,
+    metadata: web::Data<Option<MetadataStore>>,
+    challenges: web::Data<Box<dyn ChallengeStore>>,
+    attestation_response: web::Json<AttestationResponse>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let attestation_response = attestation_response.into_inner();
+    let challenge = match challenge_of(&attestation_response.client_data) {
+        Ok(challenge) => challenge,
+        Err(e) => return Box::new(future::err(e.into())),
+    };
+    let user_id = user.user.id;
+    let current_id = user.credential.as_ref().map(|credential| credential.id);
+    let summary_metadata = metadata.clone();
+    Box::new(
+        challenges.take(&challenge)
+            .map_err(AppError::from)
+            .and_then(move |binding| -> Result<_, AppError> {
+                // A challenge issued to another user does not count.
+                binding.expect_ceremony(&config.relying_party.id, &Ceremony::AddCredential { user_id })?;
+                let (new_credential, _) = verify_registration(
+                    &config,
+                    origin_policy.get_ref(),
+                    trust_anchors.get_ref(),
+                    metadata.get_ref().as_ref(),
+                    attestation_response,
+                    &challenge,
+                )?;
+                Ok(new_credential)
+            })
+            .and_then(move |new_credential| {
+                web::block(move || -> Result<_, RegisterUserError> {
+                    let conn = pool.get().map_err(RegisterUserError::Connection)?;
+                    user.user.add_credential(&conn, new_credential)
+                })
+                .map_err(AppError::from)
+            })
+            .then(move |result| -> actix_web::Result<HttpResponse> {
+                let credential = result?;
+                Ok(HttpResponse::Created().json(CredentialSummary::new(credential, summary_metadata.get_ref().as_ref(), current_id)))
+            })
+    )
+}
+
+#[derive(Debug, Validate, Deserialize)]
+struct RenameCredentialForm {
+    #[validate(length(min = 1, max = 64))]
+    nickname: Option<String>,
+}
+
+fn rename_credential(
+    user: AuthenticatedUser,
+    pool: web::Data<db::Pool>,
+    id: web::Path<i32>,
+    rename_form: web::Json<RenameCredentialForm>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    if let Err(e) = rename_form.validate() {
+        return Box::new(future::err(AppError::from(e).into()))
+    }
+    let id = id.into_inner();
+    let nickname = rename_form.into_inner().nickname;
+    Box::new(
+        web::block(move || -> Result<_, AppError> {
+            let conn = pool.get()?;
+            Ok(Credential::rename(&conn, user.user.id, id, nickname.as_ref().map(String::as_str))?)
+        })
+        .then(|result| -> actix_web::Result<HttpResponse> {
+            result.map_err(AppError::from)?;
+            Ok(HttpResponse::NoContent().finish())
+        })
+    )
+}
+
+// Losing an authenticator should not let whoever holds an old session remove the others.
+fn delete_credential(
+    user: AuthenticatedUser,
+    config: web::Data<Config>,
+    pool: web::Data<db::Pool>,
+    id: web::Path<i32>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    if let Err(e) = RequireStepUp(config.session.step_up_window()).check(&user) {
+        return Box::new(future::err(e.into()))
+    }
+    let id = id.into_inner();
+    Box::new(
+        web::block(move || -> Result<_, AppError> {
+            let conn = pool.get()?;
+            Ok(Credential::delete_for_user(&conn, &user.user, id)?)
+        })
+        .then(|result| -> actix_web::Result<HttpResponse> {
+            result.map_err(AppError::from)?;
+            Ok(HttpResponse::NoContent().finish())
+        })
+    )
 }
 
-fn verify_credential(session: Session, attestation_response: web::Json<AttestationResponse>) -> impl Responder {
-    let challenge = session.get::<String>("challenge").unwrap().unwrap();
-    let registration_response = RegistrationResponse::new("yo", "localhost:55301", attestation_response.into_inner());
-    let verified = &registration_response.verify(&challenge);
-    HttpResponse::Ok()
+#[derive(Validate, Deserialize)]
+struct DisplayNameForm {
+    #[validate(length(min = 1, max = 32), custom = "validate_name")]
+    display_name: String,
+}
+
+#[derive(Serialize)]
+struct DisplayName {
+    #[serde(rename(serialize = "displayName"))]
+    display_name: String,
+}
+
+// Authenticators may show it in their account picker, so it is guarded like the credentials.
+fn update_display_name(
+    user: AuthenticatedUser,
+    config: web::Data<Config>,
+    pool: web::Data<db::Pool>,
+    display_name_form: web::Json<DisplayNameForm>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    if let Err(e) = display_name_form.validate() {
+        return Box::new(future::err(AppError::from(e).into()))
+    }
+    if let Err(e) = RequireStepUp(config.session.step_up_window()).check(&user) {
+        return Box::new(future::err(e.into()))
+    }
+    let display_name = display_name_form.into_inner().display_name;
+    Box::new(
+        web::block(move || -> Result<_, AppError> {
+            let conn = pool.get()?;
+            Ok(user.user.set_display_name(&conn, &display_name)?)
+        })
+        .then(|result| -> actix_web::Result<HttpResponse> {
+            let user = result.map_err(AppError::from)?;
+            Ok(HttpResponse::Ok().json(DisplayName { display_name: user.display_name }))
+        })
+    )
+}
+
+fn sign_out(
+    req: HttpRequest,
+    session: Session,
+    config: web::Data<Config>,
+    pool: web::Data<db::Pool>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let current = CurrentSession::of(&session, &req, &config);
+    if !current.is_signed_in() {
+        return Box::new(future::ok(HttpResponse::NoContent().finish()))
+    }
+    Box::new(
+        web::block(move || -> Result<_, AppError> {
+            let conn = pool.get()?;
+            Ok(current.end(&conn)?)
+        })
+        .then(move |result| -> actix_web::Result<HttpResponse> {
+            result.map_err(AppError::from)?;
+            session.clear();
+            Ok(HttpResponse::NoContent().finish())
+        })
+    )
+}
+
+#[derive(Serialize)]
+struct SessionSummary {
+    id: i32,
+    // "Firefox on Windows", when the user agent tells.
+    device: Option<String>,
+    #[serde(rename(serialize = "userAgent"))]
+    user_agent: Option<String>,
+    #[serde(rename(serialize = "ipAddress"))]
+    ip_address: Option<String>,
+    #[serde(rename(serialize = "createdAt"))]
+    created_at: NaiveDateTime,
+    #[serde(rename(serialize = "lastSeenAt"))]
+    last_seen_at: NaiveDateTime,
+    // The session of the browser asking.
+    current: bool,
+}
+
+impl SessionSummary {
+    fn new(user_session: UserSession, current_id: i32) -> Self {
+        SessionSummary {
+            id: user_session.id,
+            device: user_session.user_agent.as_ref().and_then(|user_agent| auth_session::describe_device(user_agent)),
+            user_agent: user_session.user_agent,
+            ip_address: user_session.ip_address,
+            created_at: user_session.created_at,
+            last_seen_at: user_session.last_seen_at,
+            current: user_session.id == current_id,
+        }
+    }
+}
+
+fn list_sessions(
+    user: AuthenticatedUser,
+    config: web::Data<Config>,
+    pool: web::Data<db::Pool>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let timeouts = config.session.timeouts();
+    let current_id = user.session.id;
+    Box::new(
+        web::block(move || -> Result<_, AppError> {
+            let conn = pool.get()?;
+            Ok(UserSession::active_for_user(&conn, user.user.id, &timeouts)?)
+        })
+        .then(move |result| -> actix_web::Result<HttpResponse> {
+            let sessions: Vec<SessionSummary> = result.map_err(AppError::from)?.into_iter()
+                .map(|user_session| SessionSummary::new(user_session, current_id))
+                .collect();
+            Ok(HttpResponse::Ok().json(sessions))
+        })
+    )
+}
+
+fn revoke_session(
+    user: AuthenticatedUser,
+    session: Session,
+    pool: web::Data<db::Pool>,
+    id: web::Path<i32>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let id = id.into_inner();
+    // Revoking the current session is signing out.
+    let current = user.session.id == id;
+    Box::new(
+        web::block(move || -> Result<_, AppError> {
+            let conn = pool.get()?;
+            if !UserSession::delete_for_user(&conn, user.user.id, id)? {
+                return Err(AppError::NotFound)
+            }
+            Ok(())
+        })
+        .then(move |result| -> actix_web::Result<HttpResponse> {
+            result.map_err(AppError::from)?;
+            if current {
+                session.clear();
+            }
+            Ok(HttpResponse::NoContent().finish())
+        })
+    )
+}
+
+fn revoke_other_sessions(
+    user: AuthenticatedUser,
+    pool: web::Data<db::Pool>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    Box::new(
+        web::block(move || -> Result<_, AppError> {
+            let conn = pool.get()?;
+            Ok(user.session.delete_others(&conn)?)
+        })
+        .then(|result| -> actix_web::Result<HttpResponse> {
+            result.map_err(AppError::from)?;
+            Ok(HttpResponse::NoContent().finish())
+        })
+    )
+}
+
+#[derive(Debug, Validate, Deserialize, Serialize)]
+struct AssertionOptionsForm {
+    #[validate(length(min = 1, max = 32), custom = "validate_name")]
+    username: String,
+}
+
+fn get_assertion_options(
+    config: web::Data<Config>,
+    pool: web::Data<db::Pool>,
+    challenges: web::Data<Box<dyn ChallengeStore>>,
+    assertion_options_form: web::Json<AssertionOptionsForm>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    if let Err(e) = assertion_options_form.validate() {
+        return Box::new(future::err(AppError::from(e).into()))
+    }
+    let username = assertion_options_form.into_inner().username;
+    let name = username.clone();
+    Box::new(
+        web::block(move || {
+            let conn = pool.get().map_err(CredentialError::Connection)?;
+            Credential::find_by_user_name(&conn, &name).map_err(CredentialError::from)
+        })
+        .map_err(|e| -> actix_web::Error { AppError::from(e).into() })
+        .and_then(move |credentials| {
+            let allow_credentials = credentials.iter().map(allow_credential).collect();
+            let options = request_options(&config, allow_credentials, config.webauthn.user_verification);
+            let ceremony = Ceremony::Authentication { user_name: Some(username) };
+            issue_challenge(&challenges, &config, &options.challenge, ceremony, ceremony_timeout(&config), HttpResponse::Ok().json(&options))
+        })
+    )
+}
+
+fn allow_credential(credential: &Credential) -> AllowCredential {
+    let transports: Vec<AuthenticatorTransport> = credential.transports.iter()
+        .filter_map(|name| AuthenticatorTransport::from_name(name))
+        .collect();
+    AllowCredential::new(
+        base64::encode_config(&credential.credential_id, base64::URL_SAFE_NO_PAD),
+        if transports.is_empty() { None } else { Some(transports) },
+    )
+}
+
+fn request_options(config: &Config, allow_credentials: Vec<AllowCredential>, user_verification: UserVerification) -> PublicKeyCredentialRequestOptions {
+    PublicKeyCredentialRequestOptions::new(
+        32,
+        Some(config.webauthn.timeout),
+        Some(&config.relying_party.id),
+        Some(allow_credentials),
+        Some(user_verification),
+        None,
+    )
+}
+
+// Requested when the login page loads and left pending until the user picks a passkey from the username autofill,
+// so its challenge outlives the ceremony timeout.
+fn get_conditional_assertion_options(
+    config: web::Data<Config>,
+    challenges: web::Data<Box<dyn ChallengeStore>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let options = request_options(&config, vec![], config.webauthn.user_verification);
+    let ceremony = Ceremony::Authentication { user_name: None };
+    issue_challenge(&challenges, &config, &options.challenge, ceremony, config.webauthn.conditional_challenge_ttl(), HttpResponse::Ok().json(&options))
+}
+
+// An empty allowCredentials asks the authenticator for a discoverable credential,
+// the account is then resolved from the user handle it returns.
+fn get_discoverable_assertion_options(
+    config: web::Data<Config>,
+    challenges: web::Data<Box<dyn ChallengeStore>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let options = request_options(&config, vec![], config.webauthn.user_verification);
+    let ceremony = Ceremony::Authentication { user_name: None };
+    issue_challenge(&challenges, &config, &options.challenge, ceremony, ceremony_timeout(&config), HttpResponse::Ok().json(&options))
+}
+
+#[derive(Deserialize)]
+pub struct AssertionResponse {
+    #[serde(rename(deserialize = "credentialId"))]
+    pub credential_id: String,
+    #[serde(rename(deserialize = "authData"))]
+    pub auth_data: String,
+    #[serde(rename(deserialize = "clientData"))]
+    pub client_data: String,
+    pub signature: String,
+    #[serde(rename(deserialize = "userHandle"))]
+    pub user_handle: Option<String>,
+}
+
+// user.id goes to the browser base64url encoded and comes back as the raw user handle.
+fn user_handle_of(webauthn_user_id: &str) -> Vec<u8> {
+    base64::decode_config(webauthn_user_id, base64::URL_SAFE_NO_PAD).unwrap_or_default()
+}
+
+fn webauthn_user_id_of(user_handle: &str) -> Option<String> {
+    base64::decode_config(user_handle, base64::URL_SAFE_NO_PAD).ok()
+        .map(|user_handle| base64::encode_config(&user_handle, base64::URL_SAFE_NO_PAD))
+}
+
+// Also used for conditional mediation, every assertion is looked up by the challenge the browser signed.
+fn verify_assertion(
+    req: HttpRequest,
+    session: Session,
+    config: web::Data<Config>,
+    origin_policy: web::Data<OriginPolicy>,
+    pool: web::Data<db::Pool>,
+    challenges: web::Data<Box<dyn ChallengeStore>>,
+    assertion_response: web::Json<AssertionResponse>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let assertion_response = assertion_response.into_inner();
+    let challenge = match challenge_of(&assertion_response.client_data) {
+        Ok(challenge) => challenge,
+        Err(e) => return Box::new(future::err(e.into())),
+    };
+    Box::new(
+        // The challenge is spent whatever happens next.
+        challenges.take(&challenge)
+            .then(move |result| -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+                // Without a username this is a discoverable credential login.
+                let username = match result.and_then(|binding| binding.into_ceremony(&config.relying_party.id)) {
+                    Ok(Ceremony::Authentication { user_name }) => user_name,
+                    Ok(Ceremony::Registration { .. }) | Ok(Ceremony::StepUp { .. }) | Ok(Ceremony::AddCredential { .. }) => return Box::new(future::err(AppError::from(ChallengeError::Mismatch).into())),
+                    Err(e) => return Box::new(future::err(AppError::from(e).into())),
+                };
+                authenticate(req, session, config, origin_policy, pool, assertion_response, challenge, username)
+            })
+    )
+}
+
+fn authenticate(
+    req: HttpRequest,
+    session: Session,
+    config: web::Data<Config>,
+    origin_policy: web::Data<OriginPolicy>,
+    pool: web::Data<db::Pool>,
+    assertion_response: AssertionResponse,
+    challenge: String,
+    username: Option<String>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let sign_in = SignIn::new(&session, &req);
+    let timeouts = config.session.timeouts();
+    Box::new(
+        web::block(move || {
+            let conn = pool.get()?;
+            conn.transaction::<_, AppError, _>(|| {
+                let policy = AssertionPolicy {
+                    uv_required: config.webauthn.user_verification == UserVerification::Required,
+                    user_handle_required: username.is_none(),
+                    owner_credentials_allowed: username.is_some(),
+                };
+                let user_handle = assertion_response.user_handle.clone();
+                let owner_of = |credential: &Credential| -> Result<models::User, AppError> {
+                    let user = match &username {
+                        Some(username) => models::User::find(&conn, credential.user_id)?.filter(|user| &user.name == username),
+                        None => {
+                            let webauthn_user_id = user_handle.as_ref()
+                                .and_then(|user_handle| webauthn_user_id_of(user_handle))
+                                .ok_or(AppError::UnknownCredential)?;
+                            models::User::find_by_webauthn_user_id(&conn, &webauthn_user_id)?
+                        },
+                    };
+                    user.ok_or(AppError::UnknownCredential)
+                };
+                let (user, credential, user_verified) = match verify_locked_assertion(&conn, &config, &origin_policy, assertion_response, &challenge, policy, owner_of)? {
+                    Ok(verified) => verified,
+                    Err(e) => return Ok(Err(e)),
+                };
+                sign_in.record(&conn, &credential, user_verified, &timeouts)?;
+                Ok(Ok((user, credential, sign_in)))
+            })?
+        })
+        .then(move |result| -> actix_web::Result<HttpResponse> {
+            let (user, credential, sign_in) = result.map_err(AppError::from)?;
+            sign_in.start(&session, &req)?;
+            Ok(HttpResponse::Ok().json(SignedInUser {
+                username: user.name,
+                credential_id: base64::encode_config(&credential.credential_id, base64::URL_SAFE_NO_PAD),
+            }))
+        })
+    )
+}
+
+// What an assertion has to show besides a valid signature, more for a step-up than for signing in.
+struct AssertionPolicy {
+    uv_required: bool,
+    user_handle_required: bool,
+    // Whether the options listed the owner's credentials as allowCredentials, which step 1 checks again.
+    owner_credentials_allowed: bool,
+}
+
+// Verifies an assertion while holding the row of its credential, so that the counter is compared and stored without
+// racing other logins. owner_of tells whom the credential has to belong to. The owner, the credential and whether the
+// user was verified come back in the inner Ok, a rejection in the inner Err, so that the caller's transaction still
+// commits the suspected clone it recorded.
+fn verify_locked_assertion<U, F>(
+    conn: &PgConnection,
+    config: &Config,
+    origin_policy: &OriginPolicy,
+    assertion_response: AssertionResponse,
+    challenge: &str,
+    policy: AssertionPolicy,
+    owner_of: F,
+) -> Result<Result<(U, Credential, bool), AppError>, AppError>
+where
+    U: Borrow<models::User>,
+    F: FnOnce(&Credential) -> Result<U, AppError>,
+{
+    let credential_id = base64::decode_config(&assertion_response.credential_id, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| WebAuthnError::InvalidBase64("credentialId"))?;
+    let credential = Credential::lock_by_credential_id(conn, &credential_id)?.ok_or(AppError::UnknownCredential)?;
+    let owner = owner_of(&credential)?;
+    if owner.borrow().id != credential.user_id {
+        return Err(AppError::UnknownCredential)
+    }
+    let user_handle = user_handle_of(&owner.borrow().webauthn_user_id);
+    let allow_credentials = if policy.owner_credentials_allowed {
+        let credentials = owner.borrow().credentials(conn)?;
+        Some(credentials.iter().map(|credential| base64::encode_config(&credential.credential_id, base64::URL_SAFE_NO_PAD)).collect())
+    } else {
+        None
+    };
+    let public_key = CoseKey::from_cbor(&credential.public_key).map_err(|_| AppError::InvalidStoredPublicKey)?;
+    let stored_sign_count = credential.sign_count as u32;
+    let mut authentication_response = AuthenticationResponse::new(&config.relying_party.id, origin_policy, assertion_response, &public_key, stored_sign_count);
+    authentication_response.user_handle = Some(&user_handle);
+    authentication_response.allow_credentials = allow_credentials;
+    authentication_response.user_handle_required = policy.user_handle_required;
+    authentication_response.uv_required = policy.uv_required;
+    authentication_response.sign_count_policy = config.webauthn.sign_count_policy;
+    match authentication_response.verify(challenge) {
+        Ok(verified) => {
+            if verified.possibly_cloned {
+                NewSuspectedClone {
+                    credential_id: credential.id,
+                    stored_sign_count: credential.sign_count,
+                    reported_sign_count: i64::from(verified.sign_count),
+                    rejected: false,
+                }.insert(conn)?;
+            }
+            let credential = credential.update_sign_count(conn, verified.sign_count, verified.flags.backup_state)?;
+            Ok(Ok((owner, credential, verified.flags.user_verified)))
+        },
+        Err(WebAuthnError::InvalidSignCount(reported_sign_count)) => {
+            NewSuspectedClone {
+                credential_id: credential.id,
+                stored_sign_count: credential.sign_count,
+                reported_sign_count: i64::from(reported_sign_count),
+                rejected: true,
+            }.insert(conn)?;
+            Ok(Err(AppError::SuspectedClone))
+        },
+        Err(e) => Err(AppError::WebAuthn(e)),
+    }
+}
+
+// Sensitive operations ask the signed in user for a fresh user verified assertion with one of their own credentials.
+fn get_step_up_options(
+    user: AuthenticatedUser,
+    config: web::Data<Config>,
+    pool: web::Data<db::Pool>,
+    challenges: web::Data<Box<dyn ChallengeStore>>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let ceremony = Ceremony::StepUp { session_id: user.session.id };
+    Box::new(
+        web::block(move || -> Result<_, AppError> {
+            let conn = pool.get()?;
+            Ok(user.user.credentials(&conn)?)
+        })
+        .map_err(|e| -> actix_web::Error { AppError::from(e).into() })
+        .and_then(move |credentials| {
+            let allow_credentials = credentials.iter().map(allow_credential).collect();
+            let options = request_options(&config, allow_credentials, UserVerification::Required);
+            issue_challenge(&challenges, &config, &options.challenge, ceremony, ceremony_timeout(&config), HttpResponse::Ok().json(&options))
+        })
+    )
+}
+
+// Leaves the user signed in as before, only the time of the last user verified assertion moves.
+fn verify_step_up(
+    user: AuthenticatedUser,
+    config: web::Data<Config>,
+    origin_policy: web::Data<OriginPolicy>,
+    pool: web::Data<db::Pool>,
+    challenges: web::Data<Box<dyn ChallengeStore>>,
+    assertion_response: web::Json<AssertionResponse>,
+) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let assertion_response = assertion_response.into_inner();
+    let challenge = match challenge_of(&assertion_response.client_data) {
+        Ok(challenge) => challenge,
+        Err(e) => return Box::new(future::err(e.into())),
+    };
+    let rp_id = config.relying_party.id.clone();
+    let session_id = user.session.id;
+    Box::new(
+        challenges.take(&challenge)
+            .then(move |result| -> Result<(), AppError> {
+                // A challenge issued to another session, even of the same user, does not count.
+                result.and_then(|binding| binding.expect_ceremony(&rp_id, &Ceremony::StepUp { session_id })).map_err(AppError::from)
+            })
+            .map_err(actix_web::Error::from)
+            .and_then(move |_| {
+                web::block(move || {
+                    let conn = pool.get()?;
+                    conn.transaction::<_, AppError, _>(|| {
+                        let policy = AssertionPolicy {
+                            uv_required: true,
+                            user_handle_required: false,
+                            owner_credentials_allowed: true,
+                        };
+                        let (_, credential, _) = match verify_locked_assertion(&conn, &config, &origin_policy, assertion_response, &challenge, policy, |_| Ok(&user.user))? {
+                            Ok(verified) => verified,
+                            Err(e) => return Ok(Err(e)),
+                        };
+                        user.session.step_up(&conn, credential.id)?;
+                        Ok(Ok(()))
+                    })?
+                })
+                .then(|result| -> actix_web::Result<HttpResponse> {
+                    result.map_err(AppError::from)?;
+                    Ok(HttpResponse::NoContent().finish())
+                })
+            })
+    )
+}
+
+#[derive(Serialize)]
+struct RelatedOrigins {
+    origins: Vec<String>,
+}
+
+// Lets the related origins use this RP ID, fetched by the browser from https://{RP ID}/.well-known/webauthn.
+fn well_known_webauthn(origin_policy: web::Data<OriginPolicy>) -> HttpResponse {
+    HttpResponse::Ok().json(RelatedOrigins {
+        origins: origin_policy.related_origins(),
+    })
+}
+
+fn challenge_store(config: &Config, memory: &MemoryChallengeStore) -> Box<dyn ChallengeStore> {
+    match config.webauthn.challenge_store {
+        ChallengeStoreKind::Memory => Box::new(memory.clone()),
+        // Started on the worker building the App, like the session's own connection.
+        ChallengeStoreKind::Redis => Box::new(RedisChallengeStore::new(RedisActor::start(config.redis.address.as_str()))),
+    }
 }
 
 fn main() {
@@ -103,26 +971,104 @@ fn main() {
     env_logger::init();
     let mut listenfd = ListenFd::from_env();
 
-    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
-    builder
-        .set_private_key_file("key.pem", SslFiletype::PEM)
-        .unwrap();
-    builder.set_certificate_chain_file("cert.pem").unwrap();
+    dotenv::dotenv().ok();
+    let config = or_exit(Config::load(), "invalid configuration");
+    let key_ring = or_exit(KeyRing::load(&config.session, config.server.profile), "invalid session key");
+    let session_key = key_ring.master().to_vec();
+    let cookie = CookieSettings::new(&config.session);
+    let key_rotation = KeyRotation::new(key_ring, cookie.clone());
+    let pool = web::Data::new(or_exit(db::init_pool(&config.database.url), "failed to connect to the database"));
+
+    let mut builder = or_exit(SslAcceptor::mozilla_intermediate(SslMethod::tls()), "failed to set up TLS");
+    or_exit(
+        builder.set_private_key_file(&config.server.tls_private_key, SslFiletype::PEM),
+        &format!("invalid TLS private key {:?}", config.server.tls_private_key),
+    );
+    or_exit(
+        builder.set_certificate_chain_file(&config.server.tls_certificate),
+        &format!("invalid TLS certificate {:?}", config.server.tls_certificate),
+    );
 
-    let mut server = HttpServer::new(|| {
+    let mut trust_anchors = or_exit(TrustAnchorStore::load(&config.webauthn.trust_anchors), "invalid trust anchors");
+    // MDS3 BLOB downloaded from https://mds3.fidoalliance.org/ and the FIDO root it is signed under.
+    let metadata = if config.webauthn.metadata_blob.exists() {
+        let metadata = or_exit(MetadataStore::load(&config.webauthn.metadata_blob, &config.webauthn.metadata_root), "invalid metadata");
+        trust_anchors.add_metadata(&metadata);
+        Some(metadata)
+    } else {
+        eprintln!("{:?} does not exist, authenticators are registered without FIDO metadata", config.webauthn.metadata_blob);
+        None
+    };
+    let trust_anchors = web::Data::new(trust_anchors);
+    let metadata = web::Data::new(metadata);
+    // Only used by the memory store, shared by all workers.
+    let memory_challenges = MemoryChallengeStore::new();
+    let bind = config.server.bind.clone();
+    let origin_policy = web::Data::new(or_exit(config.relying_party.origin_policy(), "invalid origins"));
+    let config = web::Data::new(config);
+
+    let mut server = HttpServer::new(move || {
         App::new()
+            .register_data(config.clone())
+            .register_data(origin_policy.clone())
+            .register_data(pool.clone())
+            .register_data(trust_anchors.clone())
+            .register_data(metadata.clone())
+            .data(challenge_store(&config, &memory_challenges))
             .wrap(middleware::Logger::default())
-            .wrap(RedisSession::new("redis:6379", &[0; 32]))
+            .wrap(
+                // HttpOnly is always set by RedisSession.
+                RedisSession::new(config.redis.address.as_str(), &session_key)
+                    .cookie_name(&cookie.name)
+                    .cookie_secure(true)
+                    .cookie_same_site(cookie.same_site)
+            )
+            // After RedisSession saved the session of a sign in, under the name KeyRotation let through.
+            .wrap(SessionRenewal::new(&session_key, cookie.clone(), RedisActor::start(config.redis.address.as_str())))
+            // Outermost, so that RedisSession only ever sees cookies signed with the current key.
+            .wrap(key_rotation.clone())
             .service(actix_files::Files::new("/assets", "./assets").show_files_listing())
             .route("/", web::get().to(index))
-            .service(web::resource("/create_credential").route(web::post().to(create_credential)))
-            .service(web::resource("/verifiy_credential").route(web::post().to(verify_credential)))
+            .route("/.well-known/webauthn", web::get().to(well_known_webauthn))
+            .service(web::resource("/create_credential").route(web::post().to_async(create_credential)))
+            .service(web::resource("/verifiy_credential").route(web::post().to_async(verify_credential)))
+            .service(web::resource("/get_assertion_options").route(web::post().to_async(get_assertion_options)))
+            .service(web::resource("/get_discoverable_assertion_options").route(web::post().to_async(get_discoverable_assertion_options)))
+            .service(web::resource("/verify_assertion").route(web::post().to_async(verify_assertion)))
+            .service(web::resource("/get_conditional_assertion_options").route(web::post().to_async(get_conditional_assertion_options)))
+            .service(web::resource("/verify_conditional_assertion").route(web::post().to_async(verify_assertion)))
+            .service(web::resource("/credentials").route(web::get().to_async(list_credentials)))
+            .service(web::resource("/get_add_credential_options").route(web::post().to_async(get_add_credential_options)))
+            .service(web::resource("/verify_added_credential").route(web::post().to_async(verify_added_credential)))
+            .service(web::resource("/get_step_up_options").route(web::post().to_async(get_step_up_options)))
+            .service(web::resource("/verify_step_up").route(web::post().to_async(verify_step_up)))
+            .service(web::resource("/user").route(web::patch().to_async(update_display_name)))
+            .service(web::resource("/sign_out").route(web::post().to_async(sign_out)))
+            .service(
+                web::resource("/sessions")
+                    .route(web::get().to_async(list_sessions))
+                    .route(web::delete().to_async(revoke_other_sessions))
+            )
+            .service(web::resource("/sessions/{id}").route(web::delete().to_async(revoke_session)))
+            .service(
+                web::resource("/credentials/{id}")
+                    .route(web::patch().to_async(rename_credential))
+                    .route(web::delete().to_async(delete_credential))
+            )
     });
 
-    server = if let Some(l) = listenfd.take_tcp_listener(0).unwrap() {
-        server.listen_ssl(l, builder).unwrap()
+    server = if let Some(l) = or_exit(listenfd.take_tcp_listener(0), "invalid listen fd") {
+        or_exit(server.listen_ssl(l, builder), "failed to listen")
     } else {
-        server.bind_ssl("0.0.0.0:55301", builder).unwrap()
+        or_exit(server.bind_ssl(&bind, builder), &format!("failed to bind {}", bind))
     };
-    server.run().unwrap();
+    or_exit(server.run(), "server stopped");
+}
+
+// Startup failures, and the server failing to run, are reported on one line rather than as a panic.
+fn or_exit<T, E: std::fmt::Display>(result: Result<T, E>, context: &str) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}: {}", context, e);
+        std::process::exit(1)
+    })
 }
\ No newline at end of file
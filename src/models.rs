@@ -0,0 +1,481 @@
+use std::time::Duration;
+use chrono::NaiveDateTime;
+use diesel::dsl::{now, IntervalDsl};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use crate::schema::{credentials, suspected_clones, user_sessions, users};
+
+#[derive(Debug, Fail)]
+pub enum RegisterUserError {
+    #[fail(display = "username or credential is already registered")]
+    Conflict,
+    #[fail(display = "failed to get a database connection: {}", _0)]
+    Connection(#[cause] diesel::r2d2::PoolError),
+    #[fail(display = "database error: {}", _0)]
+    Database(#[cause] DieselError),
+}
+
+impl From<DieselError> for RegisterUserError {
+    fn from(e: DieselError) -> Self {
+        match e {
+            // Lost a race against another registration between the lookup and the insert.
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => RegisterUserError::Conflict,
+            e => RegisterUserError::Database(e),
+        }
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum CredentialError {
+    #[fail(display = "credential not found")]
+    NotFound,
+    #[fail(display = "the last credential cannot be removed without another way to sign in")]
+    LastCredential,
+    #[fail(display = "failed to get a database connection: {}", _0)]
+    Connection(#[cause] diesel::r2d2::PoolError),
+    #[fail(display = "database error: {}", _0)]
+    Database(#[cause] DieselError),
+}
+
+impl From<DieselError> for CredentialError {
+    fn from(e: DieselError) -> Self {
+        CredentialError::Database(e)
+    }
+}
+
+#[derive(Debug, Queryable, Identifiable)]
+pub struct User {
+    pub id: i32,
+    pub webauthn_user_id: String,
+    pub display_name: String,
+    pub name: String,
+    pub icon_url: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub deleted_at: Option<NaiveDateTime>,
+}
+
+impl User {
+    pub fn find(conn: &PgConnection, id: i32) -> QueryResult<Option<User>> {
+        users::table
+            .find(id)
+            .filter(users::deleted_at.is_null())
+            .first(conn)
+            .optional()
+    }
+
+    pub fn find_by_name(conn: &PgConnection, name: &str) -> QueryResult<Option<User>> {
+        users::table
+            .filter(users::name.eq(name))
+            .filter(users::deleted_at.is_null())
+            .first(conn)
+            .optional()
+    }
+
+    // Resolves the account of a discoverable credential from the user handle it returned.
+    pub fn find_by_webauthn_user_id(conn: &PgConnection, webauthn_user_id: &str) -> QueryResult<Option<User>> {
+        users::table
+            .filter(users::webauthn_user_id.eq(webauthn_user_id))
+            .filter(users::deleted_at.is_null())
+            .first(conn)
+            .optional()
+    }
+
+    pub fn credentials(&self, conn: &PgConnection) -> QueryResult<Vec<Credential>> {
+        Credential::belonging_to(self)
+            .order(credentials::created_at)
+            .load(conn)
+    }
+
+    pub fn set_display_name(&self, conn: &PgConnection, display_name: &str) -> QueryResult<User> {
+        diesel::update(self)
+            .set((
+                users::display_name.eq(display_name),
+                users::updated_at.eq(now),
+            ))
+            .get_result(conn)
+    }
+
+    // Spec step 19 again: the credential id must not be registered to anyone yet, this user included.
+    pub fn add_credential(&self, conn: &PgConnection, credential: NewCredential) -> Result<Credential, RegisterUserError> {
+        conn.transaction(|| {
+            if Credential::exists(conn, &credential.credential_id)? {
+                return Err(RegisterUserError::Conflict)
+            }
+            Ok(NewCredential { user_id: self.id, ..credential }.insert(conn)?)
+        })
+    }
+
+    // Passkeys are the only way to sign in for now, recovery codes or e-mail would be checked here.
+    pub fn has_recovery_method(&self, _conn: &PgConnection) -> QueryResult<bool> {
+        Ok(false)
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "users"]
+pub struct NewUser {
+    pub webauthn_user_id: String,
+    pub display_name: String,
+    pub name: String,
+}
+
+impl NewUser {
+    // Spec step 19: the credential id must not be registered to any other user yet.
+    pub fn register(&self, conn: &PgConnection, credential: NewCredential) -> Result<(User, Credential), RegisterUserError> {
+        conn.transaction(|| {
+            let name_taken = users::table.filter(users::name.eq(&self.name)).count().get_result::<i64>(conn)? > 0;
+            if name_taken || Credential::exists(conn, &credential.credential_id)? {
+                return Err(RegisterUserError::Conflict)
+            }
+            let user: User = diesel::insert_into(users::table)
+                .values(self)
+                .get_result(conn)?;
+            let credential = NewCredential { user_id: user.id, ..credential }.insert(conn)?;
+            Ok((user, credential))
+        })
+    }
+}
+
+#[derive(Debug, Queryable, Identifiable, Associations)]
+#[belongs_to(User)]
+pub struct Credential {
+    pub id: i32,
+    pub user_id: i32,
+    pub credential_id: Vec<u8>,
+    // CBOR encoded COSE_Key.
+    pub public_key: Vec<u8>,
+    // COSE algorithm identifier, None for credentials created before it was recorded.
+    pub alg: Option<i32>,
+    pub sign_count: i64,
+    pub aaguid: Option<Vec<u8>>,
+    pub transports: Vec<String>,
+    pub attestation_format: Option<String>,
+    pub backup_eligible: bool,
+    pub backup_state: bool,
+    pub nickname: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+impl Credential {
+    pub fn find_by_credential_id(conn: &PgConnection, credential_id: &[u8]) -> QueryResult<Option<Credential>> {
+        credentials::table
+            .filter(credentials::credential_id.eq(credential_id))
+            .first(conn)
+            .optional()
+    }
+
+    pub fn exists(conn: &PgConnection, credential_id: &[u8]) -> QueryResult<bool> {
+        credentials::table
+            .filter(credentials::credential_id.eq(credential_id))
+            .count()
+            .get_result::<i64>(conn)
+            .map(|count| count > 0)
+    }
+
+    // Credentials of the (not deleted) user called name, to fill allowCredentials in.
+    pub fn find_by_user_name(conn: &PgConnection, name: &str) -> QueryResult<Vec<Credential>> {
+        credentials::table
+            .inner_join(users::table)
+            .filter(users::name.eq(name))
+            .filter(users::deleted_at.is_null())
+            .select(credentials::all_columns)
+            .order(credentials::created_at)
+            .load(conn)
+    }
+
+    // Locks the row until the end of the transaction, so that concurrent assertions update the counter one after another.
+    pub fn lock_by_credential_id(conn: &PgConnection, credential_id: &[u8]) -> QueryResult<Option<Credential>> {
+        credentials::table
+            .filter(credentials::credential_id.eq(credential_id))
+            .for_update()
+            .first(conn)
+            .optional()
+    }
+
+    pub fn find_for_user(conn: &PgConnection, user_id: i32, id: i32) -> QueryResult<Option<Credential>> {
+        credentials::table
+            .find(id)
+            .filter(credentials::user_id.eq(user_id))
+            .first(conn)
+            .optional()
+    }
+
+    pub fn rename(conn: &PgConnection, user_id: i32, id: i32, nickname: Option<&str>) -> Result<Credential, CredentialError> {
+        let credential = Credential::find_for_user(conn, user_id, id)?.ok_or(CredentialError::NotFound)?;
+        diesel::update(&credential)
+            .set(credentials::nickname.eq(nickname))
+            .get_result(conn)
+            .map_err(CredentialError::from)
+    }
+
+    // Refuses to lock the user out by removing their last credential.
+    pub fn delete_for_user(conn: &PgConnection, user: &User, id: i32) -> Result<(), CredentialError> {
+        conn.transaction(|| {
+            // Locked so that two concurrent deletes can't both see another credential left.
+            let credentials = credentials::table
+                .filter(credentials::user_id.eq(user.id))
+                .for_update()
+                .load::<Credential>(conn)?;
+            let credential = removable(&credentials, id, user.has_recovery_method(conn)?)?;
+            diesel::delete(credential).execute(conn)?;
+            Ok(())
+        })
+    }
+
+    // Records a successful assertion.
+    pub fn update_sign_count(&self, conn: &PgConnection, sign_count: u32, backup_state: bool) -> QueryResult<Credential> {
+        diesel::update(self)
+            .set((
+                credentials::sign_count.eq(i64::from(sign_count)),
+                credentials::backup_state.eq(backup_state),
+                credentials::last_used_at.eq(diesel::dsl::now.nullable()),
+            ))
+            .get_result(conn)
+    }
+}
+
+// Picks the credential to delete out of all the user's credentials, refusing the last one unless they can sign in another way.
+fn removable(credentials: &[Credential], id: i32, has_recovery_method: bool) -> Result<&Credential, CredentialError> {
+    let credential = credentials.iter().find(|c| c.id == id).ok_or(CredentialError::NotFound)?;
+    if credentials.len() <= 1 && !has_recovery_method {
+        return Err(CredentialError::LastCredential)
+    }
+    Ok(credential)
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "credentials"]
+pub struct NewCredential {
+    pub user_id: i32,
+    pub credential_id: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub alg: Option<i32>,
+    pub sign_count: i64,
+    pub aaguid: Option<Vec<u8>>,
+    pub transports: Vec<String>,
+    pub attestation_format: Option<String>,
+    pub backup_eligible: bool,
+    pub backup_state: bool,
+    pub nickname: Option<String>,
+}
+
+impl NewCredential {
+    pub fn insert(&self, conn: &PgConnection) -> QueryResult<Credential> {
+        diesel::insert_into(credentials::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
+
+#[derive(Debug, Queryable, Identifiable, Associations)]
+#[belongs_to(Credential)]
+pub struct SuspectedClone {
+    pub id: i32,
+    pub credential_id: i32,
+    pub stored_sign_count: i64,
+    pub reported_sign_count: i64,
+    // Whether the assertion was rejected, or let through under SignCountPolicy::FlagAndAllow.
+    pub rejected: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "suspected_clones"]
+pub struct NewSuspectedClone {
+    pub credential_id: i32,
+    pub stored_sign_count: i64,
+    pub reported_sign_count: i64,
+    pub rejected: bool,
+}
+
+impl NewSuspectedClone {
+    pub fn insert(&self, conn: &PgConnection) -> QueryResult<SuspectedClone> {
+        diesel::insert_into(suspected_clones::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
+
+// How long a signed in session lasts without requests, and at most since sign in.
+#[derive(Clone, Copy, Debug)]
+pub struct SessionTimeouts {
+    pub idle: Duration,
+    pub absolute: Duration,
+}
+
+impl SessionTimeouts {
+    fn idle_seconds(&self) -> i64 {
+        self.idle.as_secs() as i64
+    }
+
+    fn absolute_seconds(&self) -> i64 {
+        self.absolute.as_secs() as i64
+    }
+}
+
+#[derive(Debug, Queryable, Identifiable, Associations)]
+#[belongs_to(User)]
+pub struct UserSession {
+    pub id: i32,
+    pub user_id: i32,
+    pub token_hash: Vec<u8>,
+    pub user_agent: Option<String>,
+    // As last seen, it follows the user across networks.
+    pub ip_address: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+    // The credential signed in with, None once it has been removed.
+    pub credential_id: Option<i32>,
+    pub user_verified: bool,
+    pub authenticated_at: NaiveDateTime,
+}
+
+impl UserSession {
+    // Expired sessions are never found, they are only deleted on the next sign in of their user.
+    pub fn find_active(conn: &PgConnection, token_hash: &[u8], timeouts: &SessionTimeouts) -> QueryResult<Option<UserSession>> {
+        user_sessions::table
+            .filter(user_sessions::token_hash.eq(token_hash))
+            .filter(user_sessions::last_seen_at.gt(now - timeouts.idle_seconds().seconds()))
+            .filter(user_sessions::created_at.gt(now - timeouts.absolute_seconds().seconds()))
+            .first(conn)
+            .optional()
+    }
+
+    pub fn active_for_user(conn: &PgConnection, user_id: i32, timeouts: &SessionTimeouts) -> QueryResult<Vec<UserSession>> {
+        user_sessions::table
+            .filter(user_sessions::user_id.eq(user_id))
+            .filter(user_sessions::last_seen_at.gt(now - timeouts.idle_seconds().seconds()))
+            .filter(user_sessions::created_at.gt(now - timeouts.absolute_seconds().seconds()))
+            .order(user_sessions::last_seen_at.desc())
+            .load(conn)
+    }
+
+    // Records a request, keeping the session from going idle.
+    pub fn touch(&self, conn: &PgConnection, ip_address: Option<&str>) -> QueryResult<UserSession> {
+        diesel::update(self)
+            .set((
+                user_sessions::last_seen_at.eq(now),
+                user_sessions::ip_address.eq(ip_address),
+            ))
+            .get_result(conn)
+    }
+
+    // A user verified assertion made within the session, which counts as signing in again.
+    pub fn step_up(&self, conn: &PgConnection, credential_id: i32) -> QueryResult<UserSession> {
+        diesel::update(self)
+            .set((
+                user_sessions::credential_id.eq(credential_id),
+                user_sessions::user_verified.eq(true),
+                user_sessions::authenticated_at.eq(now),
+                user_sessions::last_seen_at.eq(now),
+            ))
+            .get_result(conn)
+    }
+
+    pub fn delete_by_token_hash(conn: &PgConnection, token_hash: &[u8]) -> QueryResult<()> {
+        diesel::delete(user_sessions::table.filter(user_sessions::token_hash.eq(token_hash)))
+            .execute(conn)
+            .map(|_| ())
+    }
+
+    // Whether there was such a session of the user to delete.
+    pub fn delete_for_user(conn: &PgConnection, user_id: i32, id: i32) -> QueryResult<bool> {
+        diesel::delete(user_sessions::table.find(id).filter(user_sessions::user_id.eq(user_id)))
+            .execute(conn)
+            .map(|deleted| deleted > 0)
+    }
+
+    // Signs the user out everywhere but in this session.
+    pub fn delete_others(&self, conn: &PgConnection) -> QueryResult<usize> {
+        diesel::delete(
+            user_sessions::table
+                .filter(user_sessions::user_id.eq(self.user_id))
+                .filter(user_sessions::id.ne(self.id))
+        )
+        .execute(conn)
+    }
+
+    pub fn delete_expired(conn: &PgConnection, user_id: i32, timeouts: &SessionTimeouts) -> QueryResult<usize> {
+        diesel::delete(
+            user_sessions::table
+                .filter(user_sessions::user_id.eq(user_id))
+                .filter(
+                    user_sessions::last_seen_at.le(now - timeouts.idle_seconds().seconds())
+                        .or(user_sessions::created_at.le(now - timeouts.absolute_seconds().seconds()))
+                )
+        )
+        .execute(conn)
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "user_sessions"]
+pub struct NewUserSession {
+    pub user_id: i32,
+    pub token_hash: Vec<u8>,
+    pub credential_id: Option<i32>,
+    pub user_verified: bool,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+impl NewUserSession {
+    pub fn insert(&self, conn: &PgConnection) -> QueryResult<UserSession> {
+        diesel::insert_into(user_sessions::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(ids: &[i32]) -> Vec<Credential> {
+        ids.iter().map(|id| Credential {
+            id: *id,
+            user_id: 1,
+            credential_id: vec![*id as u8; 16],
+            public_key: vec![],
+            alg: Some(-7),
+            sign_count: 0,
+            aaguid: None,
+            transports: vec![],
+            attestation_format: Some("none".to_owned()),
+            backup_eligible: false,
+            backup_state: false,
+            nickname: None,
+            created_at: chrono::Utc::now().naive_utc(),
+            last_used_at: None,
+        }).collect()
+    }
+
+    #[test]
+    fn refuses_to_remove_last_credential() {
+        match removable(&credentials(&[1]), 1, false) {
+            Err(CredentialError::LastCredential) => {},
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn removes_credential_when_another_is_left() {
+        assert_eq!(removable(&credentials(&[1, 2]), 2, false).unwrap().id, 2);
+    }
+
+    #[test]
+    fn removes_last_credential_when_user_can_recover() {
+        assert_eq!(removable(&credentials(&[1]), 1, true).unwrap().id, 1);
+    }
+
+    #[test]
+    fn does_not_find_credential_of_other_user() {
+        match removable(&credentials(&[1, 2]), 3, false) {
+            Err(CredentialError::NotFound) => {},
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
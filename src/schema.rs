@@ -1,11 +1,53 @@
+table! {
+    credentials (id) {
+        id -> Int4,
+        user_id -> Int4,
+        credential_id -> Bytea,
+        public_key -> Bytea,
+        alg -> Nullable<Int4>,
+        sign_count -> Int8,
+        aaguid -> Nullable<Bytea>,
+        transports -> Array<Varchar>,
+        attestation_format -> Nullable<Varchar>,
+        backup_eligible -> Bool,
+        backup_state -> Bool,
+        nickname -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    suspected_clones (id) {
+        id -> Int4,
+        credential_id -> Int4,
+        stored_sign_count -> Int8,
+        reported_sign_count -> Int8,
+        rejected -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    user_sessions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Bytea,
+        user_agent -> Nullable<Varchar>,
+        ip_address -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        last_seen_at -> Timestamp,
+        credential_id -> Nullable<Int4>,
+        user_verified -> Bool,
+        authenticated_at -> Timestamp,
+    }
+}
+
 table! {
     users (id) {
         id -> Int4,
         webauthn_user_id -> Varchar,
-        credential_id -> Varchar,
         display_name -> Varchar,
-        public_key -> Nullable<Varchar>,
-        sign_count -> Int4,
         name -> Varchar,
         icon_url -> Nullable<Varchar>,
         created_at -> Timestamp,
@@ -13,3 +55,15 @@ table! {
         deleted_at -> Nullable<Timestamp>,
     }
 }
+
+joinable!(credentials -> users (user_id));
+joinable!(suspected_clones -> credentials (credential_id));
+joinable!(user_sessions -> credentials (credential_id));
+joinable!(user_sessions -> users (user_id));
+
+allow_tables_to_appear_in_same_query!(
+    credentials,
+    suspected_clones,
+    user_sessions,
+    users,
+);
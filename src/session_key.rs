@@ -0,0 +1,244 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use actix_service::{Service, Transform};
+use actix_web::Error;
+use actix_web::cookie::{Cookie, CookieJar, Key, SameSite};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderValue, COOKIE, SET_COOKIE};
+use futures::future::{ok, FutureResult};
+use futures::{Future, Poll};
+use rand::{thread_rng, Rng};
+use crate::auth_session::sets_cookie;
+use crate::config::{Profile, SameSitePolicy, SessionConfig};
+
+// RedisSession derives its signing key from the master key, and refuses anything shorter.
+const MIN_KEY_LENGTH: usize = 32;
+// A random 32 bytes key has about 30 distinct bytes, a typed or zeroed one far fewer.
+const MIN_DISTINCT_BYTES: usize = 16;
+
+#[derive(Debug, Fail)]
+pub enum SessionKeyError {
+    #[fail(display = "no session key is configured, set session.key or session.key_file")]
+    Missing,
+    #[fail(display = "both session.key and session.key_file are set")]
+    Ambiguous,
+    #[fail(display = "failed to read the session key file {:?}: {}", _0, _1)]
+    Io(PathBuf, #[cause] io::Error),
+    #[fail(display = "the session key file {:?} is not valid base64", _0)]
+    InvalidFile(PathBuf),
+    #[fail(display = "{} is too weak: {}", _0, _1)]
+    Weak(String, &'static str),
+}
+
+fn check_strength(key: &[u8]) -> Result<(), &'static str> {
+    if key.len() < MIN_KEY_LENGTH {
+        return Err("it must be at least 32 bytes")
+    }
+    if key.iter().collect::<HashSet<_>>().len() < MIN_DISTINCT_BYTES {
+        return Err("it is not random")
+    }
+    Ok(())
+}
+
+struct RetiredKey {
+    key: Key,
+    accepted_until: SystemTime,
+}
+
+/// The current key signs session cookies, retired keys still verify them until their grace window ends.
+pub struct KeyRing {
+    master: Vec<u8>,
+    current: Key,
+    retired: Vec<RetiredKey>,
+}
+
+impl KeyRing {
+    /// Outside the development profile, a missing or weak key is an error rather than a warning.
+    pub fn load(config: &SessionConfig, profile: Profile) -> Result<Self, SessionKeyError> {
+        let master = match (config.key.is_empty(), &config.key_file) {
+            (false, Some(_)) => return Err(SessionKeyError::Ambiguous),
+            (false, None) => config.key.clone(),
+            (true, Some(path)) => {
+                let content = fs::read_to_string(path).map_err(|e| SessionKeyError::Io(path.clone(), e))?;
+                base64::decode(content.trim()).map_err(|_| SessionKeyError::InvalidFile(path.clone()))?
+            },
+            (true, None) if profile == Profile::Development => {
+                eprintln!("no session key is configured, sessions will not survive a restart");
+                let mut master = vec![0; MIN_KEY_LENGTH];
+                thread_rng().fill(&mut master[..]);
+                master
+            },
+            (true, None) => return Err(SessionKeyError::Missing),
+        };
+        if let Err(reason) = check_strength(&master) {
+            if profile != Profile::Development || master.len() < MIN_KEY_LENGTH {
+                return Err(SessionKeyError::Weak("the session key".to_owned(), reason))
+            }
+            eprintln!("the session key is weak: {}", reason);
+        }
+
+        let grace_period = Duration::from_secs(config.key_grace_period);
+        let mut retired = vec![];
+        for (i, previous) in config.previous_keys.iter().enumerate() {
+            check_strength(&previous.key).map_err(|reason| SessionKeyError::Weak(format!("session.previous_keys[{}]", i), reason))?;
+            retired.push(RetiredKey {
+                key: Key::from_master(&previous.key),
+                accepted_until: previous.retired_at + grace_period,
+            });
+        }
+        Ok(KeyRing {
+            current: Key::from_master(&master),
+            master,
+            retired,
+        })
+    }
+
+    /// What RedisSession is built with.
+    pub fn master(&self) -> &[u8] {
+        &self.master
+    }
+
+    // The value of a cookie signed by a retired key, signed again with the current one.
+    fn resign(&self, cookie: Cookie<'static>) -> Option<Cookie<'static>> {
+        let name = cookie.name().to_owned();
+        let mut current = CookieJar::new();
+        current.add_original(cookie.clone());
+        if current.signed(&self.current).get(&name).is_some() {
+            return None
+        }
+        let now = SystemTime::now();
+        let value = self.retired.iter()
+            .filter(|retired| retired.accepted_until > now)
+            .find_map(|retired| {
+                let mut jar = CookieJar::new();
+                jar.add_original(cookie.clone());
+                jar.signed(&retired.key).get(&name).map(|cookie| cookie.value().to_owned())
+            })?;
+        let mut jar = CookieJar::new();
+        jar.signed(&self.current).add(Cookie::new(name.clone(), value));
+        jar.get(&name).cloned()
+    }
+}
+
+/// Attributes of the session cookie, shared with RedisSession so that a re-signed cookie replaces it as is.
+#[derive(Clone)]
+pub struct CookieSettings {
+    pub name: String,
+    pub same_site: SameSite,
+}
+
+impl CookieSettings {
+    pub fn new(config: &SessionConfig) -> Self {
+        CookieSettings {
+            name: config.cookie_name.clone(),
+            same_site: match config.same_site {
+                SameSitePolicy::Strict => SameSite::Strict,
+                SameSitePolicy::Lax => SameSite::Lax,
+                SameSitePolicy::None => SameSite::None,
+            },
+        }
+    }
+
+    /// The cookie with the given (signed) value, as RedisSession sets it.
+    pub fn build(&self, value: String) -> Cookie<'static> {
+        let mut cookie = Cookie::new(self.name.clone(), value);
+        cookie.set_path("/");
+        cookie.set_secure(true);
+        cookie.set_http_only(true);
+        cookie.set_same_site(self.same_site);
+        cookie
+    }
+}
+
+/// Middleware, wrapped outside of RedisSession, which lets a cookie signed by a retired key through as if signed by
+/// the current one and hands the browser the re-signed cookie.
+#[derive(Clone)]
+pub struct KeyRotation {
+    inner: Arc<(KeyRing, CookieSettings)>,
+}
+
+impl KeyRotation {
+    pub fn new(key_ring: KeyRing, cookie: CookieSettings) -> Self {
+        KeyRotation { inner: Arc::new((key_ring, cookie)) }
+    }
+}
+
+impl<S, B> Transform<S> for KeyRotation
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = KeyRotationMiddleware<S>;
+    type Future = FutureResult<Self::Transform, Self::InitError>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(KeyRotationMiddleware { service, inner: self.inner.clone() })
+    }
+}
+
+pub struct KeyRotationMiddleware<S> {
+    service: S,
+    inner: Arc<(KeyRing, CookieSettings)>,
+}
+
+impl<S, B> Service for KeyRotationMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let (key_ring, settings) = &*self.inner;
+        // Parsed by hand, HttpMessage::cookies() would keep the original for RedisSession to read.
+        let pairs: Vec<String> = req.headers().get_all(COOKIE)
+            .filter_map(|header| header.to_str().ok())
+            .flat_map(|header| header.split(';'))
+            .map(|pair| pair.trim().to_owned())
+            .filter(|pair| !pair.is_empty())
+            .collect();
+        let resigned = pairs.iter()
+            .filter_map(|pair| Cookie::parse_encoded(pair.clone()).ok())
+            .find(|cookie| cookie.name() == settings.name)
+            .and_then(|cookie| key_ring.resign(cookie));
+        let resigned = match resigned {
+            Some(resigned) => resigned,
+            None => return Box::new(self.service.call(req)),
+        };
+        // RedisSession reads the Cookie header after this, so it only ever sees the current key.
+        let prefix = format!("{}=", settings.name);
+        let header = pairs.iter()
+            .map(|pair| if pair.starts_with(&prefix) { resigned.encoded().to_string() } else { pair.clone() })
+            .collect::<Vec<_>>()
+            .join("; ");
+        if let Ok(header) = HeaderValue::from_str(&header) {
+            req.headers_mut().insert(COOKIE, header);
+        }
+        let set_cookie = HeaderValue::from_str(&settings.build(resigned.value().to_owned()).encoded().to_string());
+        let name = settings.name.clone();
+        Box::new(self.service.call(req).map(move |mut res| {
+            // Unless a new session was handed out meanwhile.
+            if let (Ok(set_cookie), false) = (set_cookie, sets_cookie(res.headers(), &name)) {
+                res.headers_mut().append(SET_COOKIE, set_cookie);
+            }
+            res
+        }))
+    }
+}
@@ -0,0 +1,178 @@
+use serde::Deserialize;
+use crate::AssertionResponse;
+use super::attestation_response::{get_client_data, ClientDataType};
+use super::authenticator_data::{AuthenticatorData, AuthenticatorDataFlags};
+use super::cose_key::CoseKey;
+use super::error::WebAuthnError;
+use super::helper::{base64_decode, sha256};
+use super::origin::OriginPolicy;
+
+// What to do when the signature counter did not increase, a sign that the authenticator may be cloned.
+// Counters that are zero on both sides are never compared, the authenticator does not implement one.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SignCountPolicy {
+    Reject,
+    // Accept the assertion, but report the credential as possibly cloned.
+    FlagAndAllow,
+    // Accept the assertion without comparing, for authenticators known to misreport their counter.
+    Ignore,
+}
+
+impl Default for SignCountPolicy {
+    fn default() -> Self {
+        SignCountPolicy::Reject
+    }
+}
+
+pub struct VerifiedAssertion {
+    pub sign_count: u32,
+    pub flags: AuthenticatorDataFlags,
+    pub possibly_cloned: bool,
+}
+
+pub struct AuthenticationResponse<'a> {
+    pub rp_id: &'a str,
+    pub origin_policy: &'a OriginPolicy,
+    pub assertion_response: AssertionResponse,
+    pub credential_public_key: &'a CoseKey,
+    pub stored_sign_count: u32,
+    pub allow_credentials: Option<Vec<String>>,
+    // The user handle (user.id at registration) of the owner of the credential.
+    pub user_handle: Option<&'a [u8]>,
+    // Set when the user was not identified before the ceremony, i.e. a discoverable credential login.
+    pub user_handle_required: bool,
+    pub uv_required: bool,
+    pub sign_count_policy: SignCountPolicy,
+}
+
+impl<'a> AuthenticationResponse<'a> {
+    pub fn new(
+        rp_id: &'a str,
+        origin_policy: &'a OriginPolicy,
+        assertion_response: AssertionResponse,
+        credential_public_key: &'a CoseKey,
+        stored_sign_count: u32,
+    ) -> Self {
+        AuthenticationResponse {
+            rp_id,
+            origin_policy,
+            assertion_response,
+            credential_public_key,
+            stored_sign_count,
+            allow_credentials: None,
+            user_handle: None,
+            user_handle_required: false,
+            uv_required: false,
+            sign_count_policy: SignCountPolicy::default(),
+        }
+    }
+
+    /// Returns the signature counter reported by the authenticator so that the caller can store it.
+    pub fn verify(&self, challenge: &str) -> Result<VerifiedAssertion, WebAuthnError> {
+        // Spec: https://w3c.github.io/webauthn/#sctn-verifying-assertion
+        // 1. If the allowCredentials option was given when this authentication ceremony was initiated, verify that credential.id identifies one of the public key credentials listed in allowCredentials.
+        if let Some(allow_credentials) = &self.allow_credentials {
+            if !allow_credentials.contains(&self.assertion_response.credential_id) {
+                return Err(WebAuthnError::InvalidCredential)
+            }
+        }
+
+        // 2. Identify the user being authenticated and verify that this user is the owner of the public key credential source credentialSource identified by credential.id.
+        // If the user was identified before the authentication ceremony was initiated, verify that the identified user is the owner of credentialSource. If response.userHandle is present, let userHandle be its value. Verify that userHandle also maps to the same user.
+        // If the user was not identified before the authentication ceremony was initiated, verify that response.userHandle is present, and that the user identified by this value is the owner of credentialSource.
+        match (self.user_handle, &self.assertion_response.user_handle) {
+            (Some(expected), Some(user_handle)) => {
+                if expected != base64_decode(user_handle, "userHandle")?.as_slice() {
+                    return Err(WebAuthnError::InvalidUserHandle)
+                }
+            },
+            (_, None) if self.user_handle_required => return Err(WebAuthnError::InvalidUserHandle),
+            _ => {},
+        }
+
+        // 3. Using credential.id, look up the corresponding credential public key and let credentialPublicKey be that credential public key.
+        // - noop, given as self.credential_public_key
+
+        // 4. Let cData, authData and sig denote the value of response's clientDataJSON, authenticatorData, and signature respectively.
+        let decoded_cd = base64_decode(&self.assertion_response.client_data, "clientDataJSON")?;
+        let raw_auth_data = base64_decode(&self.assertion_response.auth_data, "authenticatorData")?;
+        let sig = base64_decode(&self.assertion_response.signature, "signature")?;
+
+        // 5. Let JSONtext be the result of running UTF-8 decode on the value of cData.
+        // 6. Let C, the client data claimed as used for the signature, be the result of running an implementation-specific JSON parser on JSONtext.
+        let c = get_client_data(&decoded_cd)?;
+
+        // 7. Verify that the value of C.type is the string webauthn.get.
+        if &c.r#type != &ClientDataType::Get {
+            return Err(WebAuthnError::InvalidClientDataType)
+        }
+
+        // 8. Verify that the value of C.challenge equals the base64url encoding of options.challenge.
+        if &c.challenge != challenge {
+            return Err(WebAuthnError::InvalidChallenge)
+        }
+
+        // 9. Verify that the value of C.origin matches the Relying Party's origin.
+        if !self.origin_policy.allows(&c.origin) {
+            return Err(WebAuthnError::InvalidOrigin)
+        }
+
+        // 10. Verify that the value of C.tokenBinding.status matches the state of Token Binding for the TLS connection over which the attestation was obtained.
+        // NOTE: NOT SUPPORTED token binding protocol IN THIS VERSION
+
+        let auth_data = AuthenticatorData::parse(&raw_auth_data).map_err(WebAuthnError::InvalidAuthenticatorData)?;
+
+        // 11. Verify that the rpIdHash in authData is the SHA-256 hash of the RP ID expected by the Relying Party.
+        if &auth_data.rp_id_hash[..] != sha256(self.rp_id.as_bytes()).as_slice() {
+            return Err(WebAuthnError::InvalidRpId)
+        }
+
+        // 12. Verify that the User Present bit of the flags in authData is set.
+        if !auth_data.flags.user_present {
+            return Err(WebAuthnError::UserNotPresent)
+        }
+
+        // 13. If user verification is required for this assertion, verify that the User Verified bit of the flags in authData is set.
+        if self.uv_required && !auth_data.flags.user_verified {
+            return Err(WebAuthnError::UserNotVerified)
+        }
+
+        // 14. Verify that the values of the client extension outputs in clientExtensionResults and the authenticator extension outputs in the extensions in authData are as expected.
+        // NOTE: omit implementing(optional)
+
+        // 15. Let hash be the result of computing a hash over the cData using SHA-256.
+        let client_data_hash = sha256(&decoded_cd);
+
+        // 16. Using credentialPublicKey, verify that sig is a valid signature over the binary concatenation of authData and hash.
+        if !self.verify_signature(&raw_auth_data, &client_data_hash, &sig) {
+            return Err(WebAuthnError::InvalidSignature)
+        }
+
+        // 17. Let storedSignCount be the stored signature counter value associated with credential.id.
+        // If authData.signCount is nonzero or storedSignCount is nonzero, then:
+        // - If authData.signCount is greater than storedSignCount: Update storedSignCount to be the value of authData.signCount.
+        // - less than or equal to storedSignCount: This is a signal that the authenticator may be cloned.
+        //   Whether the Relying Party allows the assertion to proceed is up to sign_count_policy.
+        let sign_count = auth_data.sign_count;
+        let regressed = (sign_count != 0 || self.stored_sign_count != 0) && sign_count <= self.stored_sign_count;
+        let possibly_cloned = match self.sign_count_policy {
+            SignCountPolicy::Reject if regressed => return Err(WebAuthnError::InvalidSignCount(sign_count)),
+            SignCountPolicy::Reject | SignCountPolicy::Ignore => false,
+            SignCountPolicy::FlagAndAllow => regressed,
+        };
+
+        // 18. If all the above steps are successful, continue with the authentication ceremony as appropriate.
+        Ok(VerifiedAssertion {
+            // Never move the stored counter backwards.
+            sign_count: sign_count.max(self.stored_sign_count),
+            flags: auth_data.flags,
+            possibly_cloned,
+        })
+    }
+
+    fn verify_signature(&self, auth_data: &[u8], client_data_hash: &[u8], sig: &[u8]) -> bool {
+        let signed = [auth_data, client_data_hash].concat();
+        self.credential_public_key.verify_signature(&signed, sig).unwrap_or(false)
+    }
+}
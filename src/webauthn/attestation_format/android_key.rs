@@ -0,0 +1,210 @@
+use super::{AttestationError, AttestationInput, AttestationResult, AttestationType};
+use super::super::cose_key::verify_signature;
+use super::super::der::{self, Certificate, Tlv};
+
+// Android Key Attestation: 1.3.6.1.4.1.11129.2.1.17
+const OID_ANDROID_KEY_DESCRIPTION: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0xd6, 0x79, 0x02, 0x01, 0x11];
+
+// ref: https://source.android.com/security/keystore/tags
+const TAG_PURPOSE: u32 = 0xa1;
+const TAG_ALL_APPLICATIONS: u32 = 0xbf_8458;
+const TAG_ORIGIN: u32 = 0xbf_853e;
+
+const KM_PURPOSE_SIGN: i64 = 2;
+const KM_ORIGIN_GENERATED: i64 = 0;
+
+// KeyDescription ::= SEQUENCE {
+//     attestationVersion, attestationSecurityLevel, keymasterVersion, keymasterSecurityLevel,
+//     attestationChallenge OCTET STRING, uniqueId, softwareEnforced AuthorizationList, teeEnforced AuthorizationList }
+struct KeyDescription<'a> {
+    attestation_challenge: &'a [u8],
+    software_enforced: Vec<Tlv<'a>>,
+    tee_enforced: Vec<Tlv<'a>>,
+}
+
+impl<'a> KeyDescription<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, AttestationError> {
+        let (sequence, _) = der::parse(data)?;
+        let fields = der::parse_all(sequence.expect(der::TAG_SEQUENCE)?)?;
+        if fields.len() < 8 {
+            return Err(AttestationError::InvalidCertificate("key description"))
+        }
+        Ok(KeyDescription {
+            attestation_challenge: fields[4].expect(der::TAG_OCTET_STRING)?,
+            software_enforced: fields[6].children()?,
+            tee_enforced: fields[7].children()?,
+        })
+    }
+}
+
+fn find<'a>(authorization_list: &[Tlv<'a>], tag: u32) -> Option<Tlv<'a>> {
+    authorization_list.iter().find(|v| v.tag == tag).cloned()
+}
+
+fn has_origin_generated(authorization_list: &[Tlv]) -> Result<bool, AttestationError> {
+    match find(authorization_list, TAG_ORIGIN) {
+        Some(origin) => {
+            let (origin, _) = der::parse(origin.value)?;
+            Ok(der::parse_integer(origin.expect(der::TAG_INTEGER)?)? == KM_ORIGIN_GENERATED)
+        },
+        None => Ok(false),
+    }
+}
+
+fn has_purpose_sign(authorization_list: &[Tlv]) -> Result<bool, AttestationError> {
+    match find(authorization_list, TAG_PURPOSE) {
+        Some(purpose) => {
+            let (purposes, _) = der::parse(purpose.value)?;
+            for purpose in der::parse_all(purposes.expect(der::TAG_SET)?)? {
+                if der::parse_integer(purpose.expect(der::TAG_INTEGER)?)? == KM_PURPOSE_SIGN {
+                    return Ok(true)
+                }
+            }
+            Ok(false)
+        },
+        None => Ok(false),
+    }
+}
+
+// Spec: https://w3c.github.io/webauthn/#sctn-android-key-attestation
+pub fn verify(input: &AttestationInput) -> Result<AttestationResult, AttestationError> {
+    // Verify that attStmt is valid CBOR conforming to the syntax defined above and perform CBOR decoding on it to extract the contained fields.
+    let alg = input.get_algorithm()?;
+    let sig = input.get_bytes("sig")?;
+    let x5c = input.get_x5c()?.ok_or(AttestationError::MalformedStatement("x5c"))?;
+    let cred_cert = &x5c[0];
+
+    // Verify that sig is a valid signature over the concatenation of authenticatorData and clientDataHash using the public key in the first certificate in x5c with the algorithm specified in alg.
+    let public_key = cred_cert.public_key().map_err(|_| AttestationError::InvalidCertificate("public key"))?;
+    if !verify_signature(alg, &public_key, &input.signed_data(), sig) {
+        return Err(AttestationError::InvalidSignature)
+    }
+
+    // Verify that the public key in the first certificate in x5c matches the credentialPublicKey in the attestedCredentialData in authenticatorData.
+    let credential_public_key = input.credential_public_key.to_pkey().map_err(|_| AttestationError::AlgorithmMismatch)?;
+    if !public_key.public_eq(&credential_public_key) {
+        return Err(AttestationError::InvalidCertificate("public key does not match the credential public key"))
+    }
+
+    // Verify that the attestationChallenge field in the attestation certificate extension data is identical to clientDataHash.
+    let der = cred_cert.to_der().map_err(|_| AttestationError::InvalidCertificate("encoding"))?;
+    let certificate = Certificate::parse(&der)?;
+    let extension = certificate.extension(OID_ANDROID_KEY_DESCRIPTION).ok_or(AttestationError::InvalidCertificate("key description"))?;
+    let key_description = KeyDescription::parse(extension.value)?;
+    if key_description.attestation_challenge != input.client_data_hash {
+        return Err(AttestationError::InvalidCertificate("attestation challenge"))
+    }
+
+    // Verify the following using the appropriate authorization list from the attestation certificate extension data:
+    // - The AuthorizationList.allApplications field is not present on either authorization list (softwareEnforced nor teeEnforced), since PublicKeyCredential MUST be scoped to the RP ID.
+    if find(&key_description.software_enforced, TAG_ALL_APPLICATIONS).is_some() || find(&key_description.tee_enforced, TAG_ALL_APPLICATIONS).is_some() {
+        return Err(AttestationError::InvalidCertificate("allApplications"))
+    }
+    // - For the following, use only the teeEnforced authorization list:
+    //   - The value in the AuthorizationList.origin field is equal to KM_ORIGIN_GENERATED.
+    //   - The value in the AuthorizationList.purpose field is equal to KM_PURPOSE_SIGN.
+    if !has_origin_generated(&key_description.tee_enforced)? {
+        return Err(AttestationError::InvalidCertificate("origin"))
+    }
+    if !has_purpose_sign(&key_description.tee_enforced)? {
+        return Err(AttestationError::InvalidCertificate("purpose"))
+    }
+
+    // If successful, return implementation-specific values representing attestation type Basic and attestation trust path x5c.
+    Ok(AttestationResult {
+        attestation_type: AttestationType::Basic,
+        trust_path: x5c,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::pkey::{PKey, Private};
+    use serde_cbor::Value;
+    use super::*;
+    use super::super::super::test_fixture::{self, certificate, der_value, sign, tlv, Attestation};
+
+    const TAG_INTEGER: &[u8] = &[0x02];
+    const TAG_ENUMERATED: &[u8] = &[0x0a];
+    const TAG_OCTET_STRING: &[u8] = &[0x04];
+    const TAG_SEQUENCE: &[u8] = &[0x30];
+
+    // teeEnforced as keymaster puts it for a generated signing key, with extra authorizations appended.
+    fn tee_enforced(extra: &[u8]) -> Vec<u8> {
+        let purpose = tlv(&[0xa1], &tlv(&[0x31], &tlv(TAG_INTEGER, &[KM_PURPOSE_SIGN as u8])));
+        let origin = tlv(&[0xbf, 0x85, 0x3e], &tlv(TAG_INTEGER, &[KM_ORIGIN_GENERATED as u8]));
+        tlv(TAG_SEQUENCE, &[purpose, origin, extra.to_vec()].concat())
+    }
+
+    fn key_description(attestation_challenge: &[u8], tee_enforced: &[u8]) -> Vec<u8> {
+        tlv(TAG_SEQUENCE, &[
+            tlv(TAG_INTEGER, &[3]),
+            tlv(TAG_ENUMERATED, &[1]),
+            tlv(TAG_INTEGER, &[4]),
+            tlv(TAG_ENUMERATED, &[1]),
+            tlv(TAG_OCTET_STRING, attestation_challenge),
+            tlv(TAG_OCTET_STRING, &[]),
+            tlv(TAG_SEQUENCE, &[]),
+            tee_enforced.to_vec(),
+        ].concat())
+    }
+
+    // The credential key signs, and its certificate carries the key description.
+    fn statement(credential_key: &PKey<Private>, attestation: &mut Attestation, key_description: &[u8]) {
+        let certificate = certificate(Some("Android Keystore Key"), credential_key, None, &[
+            ("1.3.6.1.4.1.11129.2.1.17", &der_value(key_description)),
+        ]);
+        attestation.set("alg", Value::Integer(-7));
+        attestation.set("sig", Value::Bytes(sign(credential_key, &attestation.signed_data())));
+        attestation.set_x5c(&[&certificate]);
+    }
+
+    fn attestation() -> (PKey<Private>, Attestation) {
+        let (credential_key, credential_public_key) = test_fixture::ec_key();
+        (credential_key, Attestation::new(credential_public_key, [0; 16]))
+    }
+
+    fn expect_invalid_certificate(result: Result<AttestationResult, AttestationError>, expected: &str) {
+        match result {
+            Err(AttestationError::InvalidCertificate(reason)) if reason == expected => {},
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("verified without a valid {}", expected),
+        }
+    }
+
+    #[test]
+    fn verifies_statement() {
+        let (credential_key, mut attestation) = attestation();
+        let key_description = key_description(&attestation.client_data_hash, &tee_enforced(&[]));
+        statement(&credential_key, &mut attestation, &key_description);
+        let result = verify(&attestation.input()).unwrap();
+        assert_eq!(result.attestation_type, AttestationType::Basic);
+        assert_eq!(result.trust_path.len(), 1);
+    }
+
+    #[test]
+    fn rejects_other_attestation_challenge() {
+        let (credential_key, mut attestation) = attestation();
+        let key_description = key_description(&[0; 32], &tee_enforced(&[]));
+        statement(&credential_key, &mut attestation, &key_description);
+        expect_invalid_certificate(verify(&attestation.input()), "attestation challenge");
+    }
+
+    #[test]
+    fn rejects_all_applications() {
+        let (credential_key, mut attestation) = attestation();
+        let all_applications = tlv(&[0xbf, 0x84, 0x58], &[0x05, 0x00]);
+        let key_description = key_description(&attestation.client_data_hash, &tee_enforced(&all_applications));
+        statement(&credential_key, &mut attestation, &key_description);
+        expect_invalid_certificate(verify(&attestation.input()), "allApplications");
+    }
+
+    #[test]
+    fn rejects_certificate_of_other_key() {
+        let (_, mut attestation) = attestation();
+        let (other_key, _) = test_fixture::ec_key();
+        let key_description = key_description(&attestation.client_data_hash, &tee_enforced(&[]));
+        statement(&other_key, &mut attestation, &key_description);
+        expect_invalid_certificate(verify(&attestation.input()), "public key does not match the credential public key");
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use openssl::nid::Nid;
+use openssl::x509::X509Ref;
+use serde::Deserialize;
+use super::{AttestationError, AttestationInput, AttestationResult, AttestationType};
+use super::super::helper::sha256;
+use super::super::jws::Jws;
+
+const ATTESTATION_HOSTNAME: &str = "attest.android.com";
+// How old a SafetyNet response may be, and how far ahead of our clock it may claim to be.
+const TIMESTAMP_MAX_AGE_MS: u64 = 60 * 1000;
+const TIMESTAMP_MAX_SKEW_MS: u64 = 5 * 1000;
+
+#[derive(Deserialize)]
+struct SafetyNetPayload {
+    nonce: String,
+    #[serde(rename(deserialize = "timestampMs"))]
+    timestamp_ms: u64,
+    #[serde(rename(deserialize = "ctsProfileMatch"))]
+    cts_profile_match: bool,
+}
+
+fn is_issued_to(certificate: &X509Ref, hostname: &str) -> bool {
+    let in_alt_names = certificate.subject_alt_names()
+        .map_or(false, |names| names.iter().any(|name| name.dnsname() == Some(hostname)));
+    in_alt_names || certificate.subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .any(|e| e.data().as_slice() == hostname.as_bytes())
+}
+
+// Spec: https://w3c.github.io/webauthn/#sctn-android-safetynet-attestation
+pub fn verify(input: &AttestationInput) -> Result<AttestationResult, AttestationError> {
+    // Verify that attStmt is valid CBOR conforming to the syntax defined above and perform CBOR decoding on it to extract the contained fields.
+    let _ver = input.get_text("ver")?;
+    let response = std::str::from_utf8(input.get_bytes("response")?).map_err(|_| AttestationError::MalformedStatement("response"))?;
+
+    // Verify that response is a valid SafetyNet response of version ver by following the steps indicated by the SafetyNet online documentation.
+    let jws = Jws::<SafetyNetPayload>::parse(response).map_err(|_| AttestationError::MalformedStatement("response"))?;
+    let x5c = jws.certificates().map_err(|_| AttestationError::MalformedStatement("x5c"))?;
+    let leaf = x5c.first().ok_or(AttestationError::MalformedStatement("x5c"))?;
+
+    // The attestation certificate must be issued to attest.android.com and chain up through the certificates in x5c.
+    if !is_issued_to(leaf, ATTESTATION_HOSTNAME) {
+        return Err(AttestationError::InvalidCertificate("not issued to attest.android.com"))
+    }
+    for pair in x5c.windows(2) {
+        let issuer_key = pair[1].public_key().map_err(|_| AttestationError::InvalidCertificate("public key"))?;
+        if !pair[0].verify(&issuer_key).unwrap_or(false) {
+            return Err(AttestationError::InvalidCertificate("broken certificate chain"))
+        }
+    }
+
+    let leaf_key = leaf.public_key().map_err(|_| AttestationError::InvalidCertificate("public key"))?;
+    match jws.verify(&leaf_key) {
+        Ok(true) => {},
+        Ok(false) => return Err(AttestationError::InvalidSignature),
+        Err(_) => return Err(AttestationError::MalformedStatement("response header")),
+    }
+    let payload = jws.payload;
+
+    // Verify that the nonce attribute in the payload of response is identical to the Base64 encoding of the SHA-256 hash of the concatenation of authenticatorData and clientDataHash.
+    if payload.nonce != base64::encode(&sha256(&input.signed_data())) {
+        return Err(AttestationError::MalformedStatement("nonce"))
+    }
+
+    // Verify that the ctsProfileMatch attribute in the payload of response is true.
+    if !payload.cts_profile_match {
+        return Err(AttestationError::MalformedStatement("ctsProfileMatch"))
+    }
+
+    // The timestampMs must be recent, SafetyNet responses can otherwise be replayed.
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+    if payload.timestamp_ms > now + TIMESTAMP_MAX_SKEW_MS || payload.timestamp_ms + TIMESTAMP_MAX_AGE_MS < now {
+        return Err(AttestationError::MalformedStatement("timestampMs"))
+    }
+
+    // If successful, return implementation-specific values representing attestation type Basic and attestation trust path x5c.
+    Ok(AttestationResult {
+        attestation_type: AttestationType::Basic,
+        trust_path: x5c,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::test_fixture::{self, Attestation, Ca};
+
+    fn attestation() -> Attestation {
+        let (_, credential_public_key) = test_fixture::ec_key();
+        Attestation::new(credential_public_key, [0; 16])
+    }
+
+    fn expect_error(result: Result<AttestationResult, AttestationError>, expected: &str) {
+        match result {
+            Err(e) => assert_eq!(e.to_string(), expected),
+            Ok(_) => panic!("verified, expected {}", expected),
+        }
+    }
+
+    #[test]
+    fn verifies_response() {
+        let (mut attestation, root) = (attestation(), Ca::root("Fixture Root CA"));
+        let payload = attestation.safetynet_payload();
+        attestation.set_safetynet_response(ATTESTATION_HOSTNAME, &root, &root.certificate, &payload);
+        let result = verify(&attestation.input()).unwrap();
+        assert_eq!(result.attestation_type, AttestationType::Basic);
+        assert_eq!(result.trust_path.len(), 2);
+        assert_eq!(result.trust_path[1].to_der().unwrap(), root.certificate.to_der().unwrap());
+    }
+
+    #[test]
+    fn rejects_other_nonce() {
+        let (mut attestation, root) = (attestation(), Ca::root("Fixture Root CA"));
+        let mut payload = attestation.safetynet_payload();
+        payload["nonce"] = serde_json::json!(base64::encode(&sha256(b"other")));
+        attestation.set_safetynet_response(ATTESTATION_HOSTNAME, &root, &root.certificate, &payload);
+        expect_error(verify(&attestation.input()), "attestation statement is malformed: nonce");
+    }
+
+    #[test]
+    fn rejects_stale_timestamp() {
+        let (mut attestation, root) = (attestation(), Ca::root("Fixture Root CA"));
+        let mut payload = attestation.safetynet_payload();
+        let stale = payload["timestampMs"].as_u64().unwrap() - TIMESTAMP_MAX_AGE_MS - 1000;
+        payload["timestampMs"] = serde_json::json!(stale);
+        attestation.set_safetynet_response(ATTESTATION_HOSTNAME, &root, &root.certificate, &payload);
+        expect_error(verify(&attestation.input()), "attestation statement is malformed: timestampMs");
+    }
+
+    #[test]
+    fn rejects_certificate_for_other_host() {
+        let (mut attestation, root) = (attestation(), Ca::root("Fixture Root CA"));
+        let payload = attestation.safetynet_payload();
+        attestation.set_safetynet_response("attest.example.com", &root, &root.certificate, &payload);
+        expect_error(verify(&attestation.input()), "attestation certificate is invalid: not issued to attest.android.com");
+    }
+
+    #[test]
+    fn rejects_broken_chain() {
+        let (mut attestation, root, other_root) = (attestation(), Ca::root("Fixture Root CA"), Ca::root("Fixture Root CA"));
+        let payload = attestation.safetynet_payload();
+        attestation.set_safetynet_response(ATTESTATION_HOSTNAME, &root, &other_root.certificate, &payload);
+        expect_error(verify(&attestation.input()), "attestation certificate is invalid: broken certificate chain");
+    }
+}
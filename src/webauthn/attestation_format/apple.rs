@@ -0,0 +1,99 @@
+use super::{AttestationError, AttestationInput, AttestationResult, AttestationType};
+use super::super::der::{self, Certificate};
+use super::super::helper::sha256;
+
+// Apple anonymous attestation nonce: 1.2.840.113635.100.8.2
+const OID_APPLE_NONCE: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x63, 0x64, 0x08, 0x02];
+const TAG_NONCE: u32 = 0xa1;
+
+// Spec: https://w3c.github.io/webauthn/#sctn-apple-anonymous-attestation
+pub fn verify(input: &AttestationInput) -> Result<AttestationResult, AttestationError> {
+    // Verify that attStmt is valid CBOR conforming to the syntax defined above and perform CBOR decoding on it to extract the contained fields.
+    let x5c = input.get_x5c()?.ok_or(AttestationError::MalformedStatement("x5c"))?;
+    let cred_cert = &x5c[0];
+
+    // Concatenate authenticatorData and clientDataHash to form nonceToHash.
+    // Perform SHA-256 hash of nonceToHash to produce nonce.
+    let nonce = sha256(&input.signed_data());
+
+    // Verify that nonce equals the value of the extension with OID 1.2.840.113635.100.8.2 in credCert.
+    // The extension is a SEQUENCE containing the nonce as an explicitly tagged [1] OCTET STRING.
+    let der = cred_cert.to_der().map_err(|_| AttestationError::InvalidCertificate("encoding"))?;
+    let certificate = Certificate::parse(&der)?;
+    let extension = certificate.extension(OID_APPLE_NONCE).ok_or(AttestationError::InvalidCertificate("nonce extension"))?;
+    let (sequence, _) = der::parse(extension.value)?;
+    let tagged = der::parse_all(sequence.expect(der::TAG_SEQUENCE)?)?;
+    let tagged = tagged.first().ok_or(AttestationError::InvalidCertificate("nonce extension"))?;
+    let (octet_string, _) = der::parse(tagged.expect(TAG_NONCE)?)?;
+    if octet_string.expect(der::TAG_OCTET_STRING)? != nonce.as_slice() {
+        return Err(AttestationError::InvalidCertificate("nonce"))
+    }
+
+    // Verify that the credential public key equals the Subject Public Key of credCert.
+    let public_key = cred_cert.public_key().map_err(|_| AttestationError::InvalidCertificate("public key"))?;
+    let credential_public_key = input.credential_public_key.to_pkey().map_err(|_| AttestationError::AlgorithmMismatch)?;
+    if !public_key.public_eq(&credential_public_key) {
+        return Err(AttestationError::InvalidCertificate("public key does not match the credential public key"))
+    }
+
+    // If successful, return implementation-specific values representing attestation type Anonymization CA and attestation trust path x5c.
+    Ok(AttestationResult {
+        attestation_type: AttestationType::AnonCA,
+        trust_path: x5c,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::pkey::{PKey, Private};
+    use super::*;
+    use super::super::super::test_fixture::{self, der_value, tlv, Attestation, Ca};
+
+    // credCert for key under a fixture Apple WebAuthn CA, carrying nonce in its extension.
+    fn statement(attestation: &mut Attestation, key: &PKey<Private>, nonce: &[u8]) {
+        let ca = Ca::root("Fixture Apple WebAuthn CA 1");
+        let extension = tlv(&[0x30], &tlv(&[0xa1], &tlv(&[0x04], nonce)));
+        let cred_cert = ca.issue(Some("credential"), key, &[("1.2.840.113635.100.8.2", &der_value(&extension))]);
+        attestation.set_x5c(&[&cred_cert, &ca.certificate]);
+    }
+
+    fn expect_invalid_certificate(result: Result<AttestationResult, AttestationError>, expected: &str) {
+        match result {
+            Err(AttestationError::InvalidCertificate(reason)) if reason == expected => {},
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("verified without a valid {}", expected),
+        }
+    }
+
+    #[test]
+    fn verifies_statement() {
+        let (credential_key, credential_public_key) = test_fixture::ec_key();
+        let mut attestation = Attestation::new(credential_public_key, [0; 16]);
+        let nonce = sha256(&attestation.signed_data());
+        statement(&mut attestation, &credential_key, &nonce);
+        let result = verify(&attestation.input()).unwrap();
+        assert_eq!(result.attestation_type, AttestationType::AnonCA);
+        assert_eq!(result.trust_path.len(), 2);
+    }
+
+    #[test]
+    fn rejects_mismatched_nonce() {
+        let (credential_key, credential_public_key) = test_fixture::ec_key();
+        let mut attestation = Attestation::new(credential_public_key, [0; 16]);
+        let mut other_client_data = attestation.client_data.clone();
+        other_client_data.push(b' ');
+        let nonce = sha256(&[&attestation.raw_auth_data[..], &sha256(&other_client_data)].concat());
+        statement(&mut attestation, &credential_key, &nonce);
+        expect_invalid_certificate(verify(&attestation.input()), "nonce");
+    }
+
+    #[test]
+    fn rejects_certificate_of_other_key() {
+        let (_, credential_public_key) = test_fixture::ec_key();
+        let (other_key, _) = test_fixture::ec_key();
+        let mut attestation = Attestation::new(credential_public_key, [0; 16]);
+        let nonce = sha256(&attestation.signed_data());
+        statement(&mut attestation, &other_key, &nonce);
+        expect_invalid_certificate(verify(&attestation.input()), "public key does not match the credential public key");
+    }
+}
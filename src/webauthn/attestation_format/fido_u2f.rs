@@ -0,0 +1,131 @@
+use openssl::nid::Nid;
+use super::{AttestationError, AttestationInput, AttestationResult, AttestationType};
+use super::super::cose_key::{verify_signature, CoseKeyParameters, Curve};
+use super::super::credential_option::Algorithm;
+
+// Spec: https://w3c.github.io/webauthn/#sctn-fido-u2f-attestation
+pub fn verify(input: &AttestationInput) -> Result<AttestationResult, AttestationError> {
+    // Verify that attStmt is valid CBOR conforming to the syntax defined above and perform CBOR decoding on it to extract the contained fields.
+    let sig = input.get_bytes("sig")?;
+
+    // Check that x5c has exactly one element and let attCert be that element.
+    let x5c = match input.get_x5c()? {
+        Some(x5c) if x5c.len() == 1 => x5c,
+        _ => return Err(AttestationError::MalformedStatement("x5c")),
+    };
+    let att_cert = &x5c[0];
+
+    // Let certificate public key be the public key conveyed by attCert.
+    // If certificate public key is not an Elliptic Curve (EC) public key over the P-256 curve, terminate this algorithm and return an appropriate error.
+    let certificate_public_key = att_cert.public_key().map_err(|_| AttestationError::InvalidCertificate("public key"))?;
+    let curve = certificate_public_key.ec_key().ok().and_then(|k| k.group().curve_name());
+    if curve != Some(Nid::X9_62_PRIME256V1) {
+        return Err(AttestationError::InvalidCertificate("public key is not P-256"))
+    }
+
+    // Extract the claimed rpIdHash from authenticatorData, and the claimed credentialId and credentialPublicKey from authenticatorData.attestedCredentialData.
+    // Convert the COSE_KEY formatted credentialPublicKey to Raw ANSI X9.62 public key format.
+    let public_key_u2f = match &input.credential_public_key.parameters {
+        CoseKeyParameters::Ec2 { crv: Curve::P256, x, y } => [&[0x04][..], x, y].concat(),
+        _ => return Err(AttestationError::AlgorithmMismatch),
+    };
+
+    // Let verificationData be the concatenation of (0x00 || rpIdHash || clientDataHash || credentialId || publicKeyU2F).
+    let verification_data = [
+        &[0x00][..],
+        &input.auth_data.rp_id_hash,
+        input.client_data_hash,
+        &input.credential.credential_id,
+        &public_key_u2f,
+    ].concat();
+
+    // Verify the sig using verificationData and the certificate public key per section 4.1.4 of [SEC1] with SHA-256 as the hash function used in step two.
+    if !verify_signature(Algorithm::ES256, &certificate_public_key, &verification_data, sig) {
+        return Err(AttestationError::InvalidSignature)
+    }
+
+    // If successful, return implementation-specific values representing attestation type Basic, AttCA or uncertainty, and attestation trust path x5c.
+    Ok(AttestationResult {
+        attestation_type: AttestationType::Basic,
+        trust_path: x5c,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use serde::Deserialize;
+    use serde_cbor::Value;
+    use crate::AttestationResponse;
+    use super::*;
+    use super::super::AttestationType;
+    use super::super::super::attestation_response::AttestationObject;
+    use super::super::super::cose_key::CoseKey;
+    use super::super::super::helper::{base64_decode, sha256};
+
+    // A synthetic registration, generated with openssl rather than captured from a U2F token: P-256 keys and a
+    // self-signed attestation certificate, in the shape the browser posts it to /verifiy_credential.
+    const REGISTRATION: &str = include_str!("../test_vectors/fido_u2f.json");
+
+    #[derive(Deserialize)]
+    struct Registration {
+        response: AttestationResponse,
+    }
+
+    fn vector() -> (AttestationObject, BTreeMap<Value, Value>, Vec<u8>) {
+        let registration: Registration = serde_json::from_str(REGISTRATION).unwrap();
+        let att_obj = base64_decode(&registration.response.att_obj, "attestationObject").unwrap();
+        let client_data = base64_decode(&registration.response.client_data, "clientDataJSON").unwrap();
+        let attestation_object: AttestationObject = serde_cbor::from_slice(&att_obj).unwrap();
+        let att_stmt = match &attestation_object.att_stmt {
+            Value::Map(map) => map.clone(),
+            _ => panic!("attStmt is not a map"),
+        };
+        (attestation_object, att_stmt, sha256(&client_data))
+    }
+
+    fn verify_vector(attestation_object: &AttestationObject, att_stmt: &BTreeMap<Value, Value>, client_data_hash: &[u8]) -> Result<AttestationResult, AttestationError> {
+        let auth_data = attestation_object.get_authenticator_data().unwrap();
+        let credential = auth_data.attested_credential_data.as_ref().unwrap();
+        let credential_public_key = CoseKey::from_cbor(&credential.credential_public_key).unwrap();
+        verify(&AttestationInput {
+            att_stmt,
+            raw_auth_data: &attestation_object.auth_data,
+            auth_data: &auth_data,
+            credential,
+            credential_public_key: &credential_public_key,
+            client_data_hash,
+        })
+    }
+
+    #[test]
+    fn verifies_attestation() {
+        let (attestation_object, att_stmt, client_data_hash) = vector();
+        let result = verify_vector(&attestation_object, &att_stmt, &client_data_hash).unwrap();
+        assert_eq!(result.attestation_type, AttestationType::Basic);
+        assert_eq!(result.trust_path.len(), 1);
+    }
+
+    #[test]
+    fn rejects_tampered_signature() {
+        let (attestation_object, mut att_stmt, client_data_hash) = vector();
+        if let Some(Value::Bytes(sig)) = att_stmt.get_mut(&Value::Text("sig".to_owned())) {
+            *sig.last_mut().unwrap() ^= 0x01;
+        }
+        match verify_vector(&attestation_object, &att_stmt, &client_data_hash) {
+            Err(AttestationError::InvalidSignature) => {},
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("tampered signature verified"),
+        }
+    }
+
+    #[test]
+    fn rejects_other_client_data() {
+        let (attestation_object, att_stmt, _) = vector();
+        match verify_vector(&attestation_object, &att_stmt, &sha256(b"{}")) {
+            Err(AttestationError::InvalidSignature) => {},
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("signature verified over other client data"),
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+use openssl::x509::X509;
+use serde_cbor::Value;
+use super::authenticator_data::{AttestedCredentialData, AuthenticatorData};
+use super::cose_key::CoseKey;
+use super::credential_option::Algorithm;
+use super::der::{self, Certificate, DerError};
+
+pub mod packed;
+pub mod fido_u2f;
+pub mod tpm;
+pub mod android_key;
+pub mod android_safetynet;
+pub mod apple;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AttestationType {
+    Basic,
+    SelfAttestation,
+    AttCA,
+    AnonCA,
+    None,
+}
+
+// ref: https://www.iana.org/assignments/webauthn/webauthn.xhtml#webauthn-attestation-statement-format-ids
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum AttestationFormat {
+    Packed,
+    FidoU2F,
+    Tpm,
+    AndroidKey,
+    AndroidSafetyNet,
+    Apple,
+    None,
+}
+
+impl AttestationFormat {
+    pub fn from_identifier(fmt: &str) -> Option<Self> {
+        match fmt {
+            "packed" => Some(Self::Packed),
+            "fido-u2f" => Some(Self::FidoU2F),
+            "tpm" => Some(Self::Tpm),
+            "android-key" => Some(Self::AndroidKey),
+            "android-safetynet" => Some(Self::AndroidSafetyNet),
+            "apple" => Some(Self::Apple),
+            "none" => Some(Self::None),
+            _ => None,
+        }
+    }
+
+    pub fn identifier(&self) -> &'static str {
+        match self {
+            Self::Packed => "packed",
+            Self::FidoU2F => "fido-u2f",
+            Self::Tpm => "tpm",
+            Self::AndroidKey => "android-key",
+            Self::AndroidSafetyNet => "android-safetynet",
+            Self::Apple => "apple",
+            Self::None => "none",
+        }
+    }
+
+    // SafetyNet only names attest.android.com in its certificate, which a chain of any origin can claim,
+    // so it is only trusted once the chain reaches a configured Google root.
+    pub fn requires_trust_anchor(&self) -> bool {
+        *self == Self::AndroidSafetyNet
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum AttestationError {
+    #[fail(display = "attestation statement is malformed: {}", _0)]
+    MalformedStatement(&'static str),
+    #[fail(display = "attestation algorithm does not match the credential public key")]
+    AlgorithmMismatch,
+    #[fail(display = "attestation signature is invalid")]
+    InvalidSignature,
+    #[fail(display = "attestation certificate is invalid: {}", _0)]
+    InvalidCertificate(&'static str),
+    #[fail(display = "attestation certificate AAGUID does not match authenticator data")]
+    AaguidMismatch,
+}
+
+impl From<DerError> for AttestationError {
+    fn from(_: DerError) -> Self {
+        AttestationError::InvalidCertificate("malformed DER")
+    }
+}
+
+pub struct AttestationInput<'a> {
+    pub att_stmt: &'a BTreeMap<Value, Value>,
+    pub raw_auth_data: &'a [u8],
+    pub auth_data: &'a AuthenticatorData,
+    pub credential: &'a AttestedCredentialData,
+    pub credential_public_key: &'a CoseKey,
+    pub client_data_hash: &'a [u8],
+}
+
+impl<'a> AttestationInput<'a> {
+    pub fn signed_data(&self) -> Vec<u8> {
+        [self.raw_auth_data, self.client_data_hash].concat()
+    }
+
+    fn get(&self, key: &'static str) -> Option<&'a Value> {
+        self.att_stmt.get(&Value::Text(key.to_owned()))
+    }
+
+    pub fn get_bytes(&self, key: &'static str) -> Result<&'a [u8], AttestationError> {
+        match self.get(key) {
+            Some(Value::Bytes(v)) => Ok(v),
+            _ => Err(AttestationError::MalformedStatement(key)),
+        }
+    }
+
+    pub fn get_text(&self, key: &'static str) -> Result<&'a str, AttestationError> {
+        match self.get(key) {
+            Some(Value::Text(v)) => Ok(v),
+            _ => Err(AttestationError::MalformedStatement(key)),
+        }
+    }
+
+    pub fn get_algorithm(&self) -> Result<Algorithm, AttestationError> {
+        match self.get("alg") {
+            Some(Value::Integer(v)) => Algorithm::from_code(*v as i64).ok_or(AttestationError::MalformedStatement("alg")),
+            _ => Err(AttestationError::MalformedStatement("alg")),
+        }
+    }
+
+    // Returns None when the statement has no x5c at all, an empty or undecodable chain is an error.
+    pub fn get_x5c(&self) -> Result<Option<Vec<X509>>, AttestationError> {
+        match self.get("x5c") {
+            None => Ok(None),
+            Some(Value::Array(certificates)) if !certificates.is_empty() => {
+                certificates.iter()
+                    .map(|c| match c {
+                        Value::Bytes(der) => X509::from_der(der).map_err(|_| AttestationError::MalformedStatement("x5c")),
+                        _ => Err(AttestationError::MalformedStatement("x5c")),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(Some)
+            },
+            _ => Err(AttestationError::MalformedStatement("x5c")),
+        }
+    }
+}
+
+pub struct AttestationResult {
+    pub attestation_type: AttestationType,
+    pub trust_path: Vec<X509>,
+}
+
+// id-fido-gen-ce-aaguid: 1.3.6.1.4.1.45724.1.1.4
+const OID_FIDO_GEN_CE_AAGUID: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xe5, 0x1c, 0x01, 0x01, 0x04];
+
+// If the attestation certificate contains id-fido-gen-ce-aaguid, it must not be critical and must match the aaguid in authenticatorData.
+pub fn verify_aaguid_extension(certificate: &Certificate, aaguid: &[u8]) -> Result<(), AttestationError> {
+    if let Some(extension) = certificate.extension(OID_FIDO_GEN_CE_AAGUID) {
+        if extension.critical {
+            return Err(AttestationError::InvalidCertificate("critical aaguid extension"))
+        }
+        let (value, _) = der::parse(extension.value)?;
+        if value.expect(der::TAG_OCTET_STRING)? != aaguid {
+            return Err(AttestationError::AaguidMismatch)
+        }
+    }
+    Ok(())
+}
+
+pub fn verify(format: AttestationFormat, input: &AttestationInput) -> Result<AttestationResult, AttestationError> {
+    match format {
+        AttestationFormat::Packed => packed::verify(input),
+        AttestationFormat::FidoU2F => fido_u2f::verify(input),
+        AttestationFormat::Tpm => tpm::verify(input),
+        AttestationFormat::AndroidKey => android_key::verify(input),
+        AttestationFormat::AndroidSafetyNet => android_safetynet::verify(input),
+        AttestationFormat::Apple => apple::verify(input),
+        AttestationFormat::None => verify_none(input),
+    }
+}
+
+// Spec: https://w3c.github.io/webauthn/#sctn-none-attestation
+fn verify_none(input: &AttestationInput) -> Result<AttestationResult, AttestationError> {
+    if !input.att_stmt.is_empty() {
+        return Err(AttestationError::MalformedStatement("attStmt"))
+    }
+    Ok(AttestationResult {
+        attestation_type: AttestationType::None,
+        trust_path: vec![],
+    })
+}
@@ -0,0 +1,89 @@
+use openssl::nid::Nid;
+use openssl::x509::X509Ref;
+use super::{verify_aaguid_extension, AttestationError, AttestationInput, AttestationResult, AttestationType};
+use super::super::cose_key::verify_signature;
+use super::super::der::Certificate;
+
+// Spec: https://w3c.github.io/webauthn/#sctn-packed-attestation
+pub fn verify(input: &AttestationInput) -> Result<AttestationResult, AttestationError> {
+    // Verify that attStmt is valid CBOR conforming to the syntax defined above and perform CBOR decoding on it to extract the contained fields.
+    let alg = input.get_algorithm()?;
+    let sig = input.get_bytes("sig")?;
+
+    match input.get_x5c()? {
+        // If x5c is present:
+        Some(x5c) => {
+            // Verify that sig is a valid signature over the concatenation of authenticatorData and clientDataHash using the attestation public key in attestnCert with the algorithm specified in alg.
+            let attestn_cert = &x5c[0];
+            let public_key = attestn_cert.public_key().map_err(|_| AttestationError::InvalidCertificate("public key"))?;
+            if !verify_signature(alg, &public_key, &input.signed_data(), sig) {
+                return Err(AttestationError::InvalidSignature)
+            }
+
+            // Verify that attestnCert meets the requirements in § 8.2.1 Packed Attestation Statement Certificate Requirements.
+            // If attestnCert contains an extension with OID 1.3.6.1.4.1.45724.1.1.4 (id-fido-gen-ce-aaguid) verify that the value of this extension matches the aaguid in authenticatorData.
+            verify_certificate(attestn_cert, &input.credential.aaguid)?;
+
+            // If successful, return implementation-specific values representing attestation type Basic, AttCA or uncertainty, and attestation trust path x5c.
+            Ok(AttestationResult {
+                attestation_type: AttestationType::Basic,
+                trust_path: x5c,
+            })
+        },
+        // If x5c is not present, self attestation is in use.
+        None => {
+            // Validate that alg matches the algorithm of the credentialPublicKey in authenticatorData.
+            if alg != input.credential_public_key.alg {
+                return Err(AttestationError::AlgorithmMismatch)
+            }
+
+            // Verify that sig is a valid signature over the concatenation of authenticatorData and clientDataHash using the credential public key with alg.
+            match input.credential_public_key.verify_signature(&input.signed_data(), sig) {
+                Ok(true) => {},
+                _ => return Err(AttestationError::InvalidSignature),
+            }
+
+            // If successful, return implementation-specific values representing attestation type Self and an empty attestation trust path.
+            Ok(AttestationResult {
+                attestation_type: AttestationType::SelfAttestation,
+                trust_path: vec![],
+            })
+        },
+    }
+}
+
+// Spec: https://w3c.github.io/webauthn/#sctn-packed-attestation-cert-requirements
+fn verify_certificate(certificate: &X509Ref, aaguid: &[u8]) -> Result<(), AttestationError> {
+    let der = certificate.to_der().map_err(|_| AttestationError::InvalidCertificate("encoding"))?;
+    let parsed = Certificate::parse(&der)?;
+
+    // Version MUST be set to 3 (which is indicated by an ASN.1 INTEGER with value 2).
+    if parsed.version != 2 {
+        return Err(AttestationError::InvalidCertificate("version"))
+    }
+
+    // Subject field MUST be set to C, O, OU and CN.
+    let subject = certificate.subject_name();
+    for nid in &[Nid::COUNTRYNAME, Nid::ORGANIZATIONNAME, Nid::COMMONNAME] {
+        if subject.entries_by_nid(*nid).next().is_none() {
+            return Err(AttestationError::InvalidCertificate("subject"))
+        }
+    }
+    let is_authenticator_attestation = subject.entries_by_nid(Nid::ORGANIZATIONALUNITNAME)
+        .any(|e| e.data().as_slice() == b"Authenticator Attestation");
+    if !is_authenticator_attestation {
+        return Err(AttestationError::InvalidCertificate("subject organizational unit"))
+    }
+
+    // If the related attestation root certificate is used for multiple authenticator models,
+    // the Extension OID 1.3.6.1.4.1.45724.1.1.4 (id-fido-gen-ce-aaguid) MUST be present, containing the AAGUID as a 16-byte OCTET STRING.
+    // The extension MUST NOT be marked as critical.
+    verify_aaguid_extension(&parsed, aaguid)?;
+
+    // The Basic Constraints extension MUST have the CA component set to false.
+    if parsed.is_ca()? {
+        return Err(AttestationError::InvalidCertificate("basic constraints"))
+    }
+
+    Ok(())
+}
@@ -0,0 +1,532 @@
+use openssl::hash::{hash, MessageDigest};
+use openssl::x509::X509Ref;
+use super::{verify_aaguid_extension, AttestationError, AttestationInput, AttestationResult, AttestationType};
+use super::super::cose_key::{verify_signature, CoseKeyParameters, Curve};
+use super::super::der::{self, Certificate};
+
+// ref: https://trustedcomputinggroup.org/resource/tpm-library-specification/ (Part 2: Structures)
+const TPM_GENERATED_VALUE: u32 = 0xff54_4347;
+const TPM_ST_ATTEST_CERTIFY: u16 = 0x8017;
+
+const TPM_ALG_RSA: u16 = 0x0001;
+const TPM_ALG_SHA1: u16 = 0x0004;
+const TPM_ALG_SHA256: u16 = 0x000b;
+const TPM_ALG_SHA384: u16 = 0x000c;
+const TPM_ALG_SHA512: u16 = 0x000d;
+const TPM_ALG_NULL: u16 = 0x0010;
+const TPM_ALG_ECC: u16 = 0x0023;
+
+const TPM_ECC_NIST_P256: u16 = 0x0003;
+const TPM_ECC_NIST_P384: u16 = 0x0004;
+const TPM_ECC_NIST_P521: u16 = 0x0005;
+
+const RSA_DEFAULT_EXPONENT: u32 = 65537;
+
+// tcg-kp-AIKCertificate: 2.23.133.8.3
+const OID_TCG_KP_AIK_CERTIFICATE: &[u8] = &[0x67, 0x81, 0x05, 0x08, 0x03];
+// tcg-at-tpmManufacturer: 2.23.133.2.1
+const OID_TCG_AT_TPM_MANUFACTURER: &[u8] = &[0x67, 0x81, 0x05, 0x02, 0x01];
+// tcg-at-tpmModel: 2.23.133.2.2
+const OID_TCG_AT_TPM_MODEL: &[u8] = &[0x67, 0x81, 0x05, 0x02, 0x02];
+// tcg-at-tpmVersion: 2.23.133.2.3
+const OID_TCG_AT_TPM_VERSION: &[u8] = &[0x67, 0x81, 0x05, 0x02, 0x03];
+
+// ref: https://trustedcomputinggroup.org/resource/vendor-id-registry/
+const TPM_MANUFACTURERS: &[&str] = &[
+    "id:414D4400", // AMD
+    "id:41544D4C", // Atmel
+    "id:4252434D", // Broadcom
+    "id:4353434F", // Cisco
+    "id:464C5953", // Flyslice Technologies
+    "id:474F4F47", // Google
+    "id:48504500", // HPE
+    "id:48495349", // Huawei
+    "id:49424D00", // IBM
+    "id:49465800", // Infineon
+    "id:494E5443", // Intel
+    "id:4C454E00", // Lenovo
+    "id:4D534654", // Microsoft
+    "id:4E534D20", // National Semiconductor
+    "id:4E545A00", // Nationz
+    "id:4E544300", // Nuvoton Technology
+    "id:51434F4D", // Qualcomm
+    "id:524F4343", // Fuzhou Rockchip
+    "id:534D5343", // SMSC
+    "id:534D534E", // Samsung
+    "id:534E5300", // Sinosun
+    "id:53544D20", // ST Microelectronics
+    "id:54584E00", // Texas Instruments
+    "id:57454300", // Winbond
+];
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize, field: &'static str) -> Result<&'a [u8], AttestationError> {
+        if self.data.len() < length {
+            return Err(AttestationError::MalformedStatement(field))
+        }
+        let (value, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(value)
+    }
+
+    fn u16(&mut self, field: &'static str) -> Result<u16, AttestationError> {
+        let v = self.take(2, field)?;
+        Ok(u16::from_be_bytes([v[0], v[1]]))
+    }
+
+    fn u32(&mut self, field: &'static str) -> Result<u32, AttestationError> {
+        let v = self.take(4, field)?;
+        Ok(u32::from_be_bytes([v[0], v[1], v[2], v[3]]))
+    }
+
+    // TPM2B_* structures are a 2 bytes size followed by that many bytes.
+    fn sized(&mut self, field: &'static str) -> Result<&'a [u8], AttestationError> {
+        let length = self.u16(field)? as usize;
+        self.take(length, field)
+    }
+
+    fn finish(&self, field: &'static str) -> Result<(), AttestationError> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(AttestationError::MalformedStatement(field))
+        }
+    }
+}
+
+enum TpmPublicKey<'a> {
+    Rsa { exponent: u32, modulus: &'a [u8] },
+    Ecc { curve_id: u16, x: &'a [u8], y: &'a [u8] },
+}
+
+// TPMT_PUBLIC
+struct PubArea<'a> {
+    name_alg: u16,
+    public_key: TpmPublicKey<'a>,
+}
+
+impl<'a> PubArea<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, AttestationError> {
+        const FIELD: &str = "pubArea";
+        let mut reader = Reader { data };
+        let r#type = reader.u16(FIELD)?;
+        let name_alg = reader.u16(FIELD)?;
+        let _object_attributes = reader.u32(FIELD)?;
+        let _auth_policy = reader.sized(FIELD)?;
+        // TPMT_SYM_DEF_OBJECT, followed by keyBits and mode unless it is TPM_ALG_NULL.
+        if reader.u16(FIELD)? != TPM_ALG_NULL {
+            reader.take(4, FIELD)?;
+        }
+        // TPMT_RSA_SCHEME / TPMT_ECC_SCHEME, followed by the hash algorithm unless it is TPM_ALG_NULL.
+        if reader.u16(FIELD)? != TPM_ALG_NULL {
+            reader.u16(FIELD)?;
+        }
+        let public_key = match r#type {
+            TPM_ALG_RSA => {
+                let _key_bits = reader.u16(FIELD)?;
+                let exponent = match reader.u32(FIELD)? {
+                    0 => RSA_DEFAULT_EXPONENT,
+                    e => e,
+                };
+                let modulus = reader.sized(FIELD)?;
+                TpmPublicKey::Rsa { exponent, modulus }
+            },
+            TPM_ALG_ECC => {
+                let curve_id = reader.u16(FIELD)?;
+                // TPMT_KDF_SCHEME
+                if reader.u16(FIELD)? != TPM_ALG_NULL {
+                    reader.u16(FIELD)?;
+                }
+                let x = reader.sized(FIELD)?;
+                let y = reader.sized(FIELD)?;
+                TpmPublicKey::Ecc { curve_id, x, y }
+            },
+            _ => return Err(AttestationError::MalformedStatement(FIELD)),
+        };
+        reader.finish(FIELD)?;
+        Ok(PubArea { name_alg, public_key })
+    }
+}
+
+// TPMS_ATTEST
+struct CertInfo<'a> {
+    magic: u32,
+    r#type: u16,
+    extra_data: &'a [u8],
+    attested_name: &'a [u8],
+}
+
+impl<'a> CertInfo<'a> {
+    const CLOCK_INFO_LENGTH: usize = 17;
+    const FIRMWARE_VERSION_LENGTH: usize = 8;
+
+    fn parse(data: &'a [u8]) -> Result<Self, AttestationError> {
+        const FIELD: &str = "certInfo";
+        let mut reader = Reader { data };
+        let magic = reader.u32(FIELD)?;
+        let r#type = reader.u16(FIELD)?;
+        let _qualified_signer = reader.sized(FIELD)?;
+        let extra_data = reader.sized(FIELD)?;
+        reader.take(Self::CLOCK_INFO_LENGTH, FIELD)?;
+        reader.take(Self::FIRMWARE_VERSION_LENGTH, FIELD)?;
+        // TPMS_CERTIFY_INFO
+        let attested_name = reader.sized(FIELD)?;
+        let _attested_qualified_name = reader.sized(FIELD)?;
+        reader.finish(FIELD)?;
+        Ok(CertInfo { magic, r#type, extra_data, attested_name })
+    }
+}
+
+fn name_digest(name_alg: u16) -> Option<MessageDigest> {
+    match name_alg {
+        TPM_ALG_SHA1 => Some(MessageDigest::sha1()),
+        TPM_ALG_SHA256 => Some(MessageDigest::sha256()),
+        TPM_ALG_SHA384 => Some(MessageDigest::sha384()),
+        TPM_ALG_SHA512 => Some(MessageDigest::sha512()),
+        _ => None,
+    }
+}
+
+fn trim_leading_zeros(v: &[u8]) -> &[u8] {
+    let start = v.iter().position(|b| *b != 0).unwrap_or(v.len());
+    &v[start..]
+}
+
+// Spec: https://w3c.github.io/webauthn/#sctn-tpm-attestation
+pub fn verify(input: &AttestationInput) -> Result<AttestationResult, AttestationError> {
+    // Verify that attStmt is valid CBOR conforming to the syntax defined above and perform CBOR decoding on it to extract the contained fields.
+    if input.get_text("ver")? != "2.0" {
+        return Err(AttestationError::MalformedStatement("ver"))
+    }
+    let alg = input.get_algorithm()?;
+    let sig = input.get_bytes("sig")?;
+    let raw_cert_info = input.get_bytes("certInfo")?;
+    let raw_pub_area = input.get_bytes("pubArea")?;
+
+    // Verify that the public key specified by the parameters and unique fields of pubArea is identical to the credentialPublicKey in the attestedCredentialData in authenticatorData.
+    let pub_area = PubArea::parse(raw_pub_area)?;
+    let identical = match (&pub_area.public_key, &input.credential_public_key.parameters) {
+        (TpmPublicKey::Rsa { exponent, modulus }, CoseKeyParameters::Rsa { n, e }) => {
+            trim_leading_zeros(modulus) == trim_leading_zeros(n) && trim_leading_zeros(&exponent.to_be_bytes()) == trim_leading_zeros(e)
+        },
+        (TpmPublicKey::Ecc { curve_id, x: tpm_x, y: tpm_y }, CoseKeyParameters::Ec2 { crv, x, y }) => {
+            let curve_matches = match crv {
+                Curve::P256 => *curve_id == TPM_ECC_NIST_P256,
+                Curve::P384 => *curve_id == TPM_ECC_NIST_P384,
+                Curve::P521 => *curve_id == TPM_ECC_NIST_P521,
+                Curve::Ed25519 => false,
+            };
+            curve_matches && tpm_x == x && tpm_y == y
+        },
+        _ => false,
+    };
+    if !identical {
+        return Err(AttestationError::MalformedStatement("pubArea does not match the credential public key"))
+    }
+
+    // Concatenate authenticatorData and clientDataHash to form attToBeSigned.
+    let att_to_be_signed = input.signed_data();
+
+    // Validate that certInfo is valid:
+    let cert_info = CertInfo::parse(raw_cert_info)?;
+    // - Verify that magic is set to TPM_GENERATED_VALUE.
+    if cert_info.magic != TPM_GENERATED_VALUE {
+        return Err(AttestationError::MalformedStatement("certInfo.magic"))
+    }
+    // - Verify that type is set to TPM_ST_ATTEST_CERTIFY.
+    if cert_info.r#type != TPM_ST_ATTEST_CERTIFY {
+        return Err(AttestationError::MalformedStatement("certInfo.type"))
+    }
+    // - Verify that extraData is set to the hash of attToBeSigned using the hash algorithm employed in "alg".
+    let expected_extra_data = hash(alg.message_digest(), &att_to_be_signed).map_err(|_| AttestationError::MalformedStatement("certInfo.extraData"))?;
+    if cert_info.extra_data != &expected_extra_data[..] {
+        return Err(AttestationError::MalformedStatement("certInfo.extraData"))
+    }
+    // - Verify that attested contains a TPMS_CERTIFY_INFO structure as specified in [TPMv2-Part2] section 10.12.3,
+    //   whose name field contains a valid Name for pubArea, as computed using the algorithm in the nameAlg field of pubArea.
+    let name_digest = name_digest(pub_area.name_alg).ok_or(AttestationError::MalformedStatement("pubArea.nameAlg"))?;
+    let pub_area_hash = hash(name_digest, raw_pub_area).map_err(|_| AttestationError::MalformedStatement("pubArea.nameAlg"))?;
+    let expected_name = [&pub_area.name_alg.to_be_bytes()[..], &pub_area_hash].concat();
+    if cert_info.attested_name != &expected_name[..] {
+        return Err(AttestationError::MalformedStatement("certInfo.attested.name"))
+    }
+
+    // If x5c is present:
+    // NOTE: ECDAA is deprecated, so x5c is required.
+    let x5c = input.get_x5c()?.ok_or(AttestationError::MalformedStatement("x5c"))?;
+    let aik_cert = &x5c[0];
+
+    // - Verify the sig is a valid signature over certInfo using the attestation public key in aikCert with the algorithm specified in alg.
+    let public_key = aik_cert.public_key().map_err(|_| AttestationError::InvalidCertificate("public key"))?;
+    if !verify_signature(alg, &public_key, raw_cert_info, sig) {
+        return Err(AttestationError::InvalidSignature)
+    }
+
+    // - Verify that aikCert meets the requirements in § 8.3.1 TPM Attestation Statement Certificate Requirements.
+    // - If aikCert contains an extension with OID 1.3.6.1.4.1.45724.1.1.4 (id-fido-gen-ce-aaguid) verify that the value of this extension matches the aaguid in authenticatorData.
+    verify_certificate(aik_cert, &input.credential.aaguid)?;
+
+    // - If successful, return implementation-specific values representing attestation type AttCA and attestation trust path x5c.
+    Ok(AttestationResult {
+        attestation_type: AttestationType::AttCA,
+        trust_path: x5c,
+    })
+}
+
+// Spec: https://w3c.github.io/webauthn/#sctn-tpm-cert-requirements
+fn verify_certificate(certificate: &X509Ref, aaguid: &[u8]) -> Result<(), AttestationError> {
+    let der = certificate.to_der().map_err(|_| AttestationError::InvalidCertificate("encoding"))?;
+    let parsed = Certificate::parse(&der)?;
+
+    // Version MUST be set to 3.
+    if parsed.version != 2 {
+        return Err(AttestationError::InvalidCertificate("version"))
+    }
+
+    // Subject field MUST be set to empty.
+    if !parsed.subject.expect(der::TAG_SEQUENCE)?.is_empty() {
+        return Err(AttestationError::InvalidCertificate("subject must be empty"))
+    }
+
+    // The Subject Alternative Name extension MUST be set as defined in [TPMv2-EK-Profile] section 3.2.9.
+    let san = parsed.extension(der::OID_SUBJECT_ALT_NAME).ok_or(AttestationError::InvalidCertificate("subject alternative name"))?;
+    let manufacturer = tpm_manufacturer(san.value)?;
+    if !TPM_MANUFACTURERS.iter().any(|m| m.eq_ignore_ascii_case(&manufacturer)) {
+        return Err(AttestationError::InvalidCertificate("unknown TPM manufacturer"))
+    }
+
+    // The Extended Key Usage extension MUST contain the OID 2.23.133.8.3.
+    let eku = parsed.extension(der::OID_EXT_KEY_USAGE).ok_or(AttestationError::InvalidCertificate("extended key usage"))?;
+    let (eku, _) = der::parse(eku.value)?;
+    let has_aik_usage = eku.children()?.iter().any(|usage| usage.tag == der::TAG_OID && usage.value == OID_TCG_KP_AIK_CERTIFICATE);
+    if !has_aik_usage {
+        return Err(AttestationError::InvalidCertificate("extended key usage"))
+    }
+
+    // The Basic Constraints extension MUST have the CA component set to false.
+    if parsed.is_ca()? {
+        return Err(AttestationError::InvalidCertificate("basic constraints"))
+    }
+
+    verify_aaguid_extension(&parsed, aaguid)
+}
+
+// Walks GeneralNames looking for the directoryName carrying tcg-at-tpmManufacturer, tcg-at-tpmModel and tcg-at-tpmVersion.
+fn tpm_manufacturer(san: &[u8]) -> Result<String, AttestationError> {
+    const TAG_DIRECTORY_NAME: u32 = 0xa4;
+    let (general_names, _) = der::parse(san)?;
+    for general_name in general_names.children()? {
+        if general_name.tag != TAG_DIRECTORY_NAME {
+            continue
+        }
+        let (name, _) = der::parse(general_name.value)?;
+        let mut manufacturer = None;
+        let mut has_model = false;
+        let mut has_version = false;
+        for rdn in name.children()? {
+            for attribute in der::parse_all(rdn.expect(der::TAG_SET)?)? {
+                let fields = attribute.children()?;
+                match (fields.get(0), fields.get(1)) {
+                    (Some(oid), Some(value)) if oid.value == OID_TCG_AT_TPM_MANUFACTURER => {
+                        manufacturer = Some(String::from_utf8_lossy(value.value).into_owned());
+                    },
+                    (Some(oid), Some(_)) if oid.value == OID_TCG_AT_TPM_MODEL => has_model = true,
+                    (Some(oid), Some(_)) if oid.value == OID_TCG_AT_TPM_VERSION => has_version = true,
+                    _ => {},
+                }
+            }
+        }
+        if let (Some(manufacturer), true, true) = (manufacturer, has_model, has_version) {
+            return Ok(manufacturer)
+        }
+    }
+    Err(AttestationError::InvalidCertificate("subject alternative name"))
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::hash::{hash, MessageDigest};
+    use openssl::pkey::{PKey, Private};
+    use openssl::x509::X509;
+    use serde_cbor::Value;
+    use super::*;
+    use super::super::super::cose_key::CoseKey;
+    use super::super::super::test_fixture::{self, certificate, der_value, sign, tlv, Attestation};
+
+    // A statement as a TPM makes it, from the AIK and the credential public key, certified by pubArea and certInfo.
+    struct Tpm {
+        aik: PKey<Private>,
+        aik_certificate: X509,
+        attestation: Attestation,
+        pub_area: Vec<u8>,
+    }
+
+    fn aik_certificate(aik: &PKey<Private>) -> X509 {
+        let attribute = |oid: &[u8], value: &str| tlv(&[0x31], &tlv(&[0x30], &[tlv(&[0x06], oid), tlv(&[0x0c], value.as_bytes())].concat()));
+        let directory_name = tlv(&[0x30], &[
+            attribute(OID_TCG_AT_TPM_MANUFACTURER, "id:494E5443"),
+            attribute(OID_TCG_AT_TPM_MODEL, "NPCT75x"),
+            attribute(OID_TCG_AT_TPM_VERSION, "id:7"),
+        ].concat());
+        let san = tlv(&[0x30], &tlv(&[0xa4], &directory_name));
+        certificate(None, aik, None, &[
+            ("subjectAltName", &format!("critical,{}", der_value(&san))),
+            ("extendedKeyUsage", "2.23.133.8.3"),
+        ])
+    }
+
+    fn pub_area(key: &CoseKey) -> Vec<u8> {
+        let sized = |value: &[u8]| [&(value.len() as u16).to_be_bytes()[..], value].concat();
+        let (r#type, parameters) = match &key.parameters {
+            CoseKeyParameters::Rsa { n, .. } => {
+                // keyBits, then the default exponent
+                let parameters = [&2048u16.to_be_bytes()[..], &0u32.to_be_bytes(), &sized(n)].concat();
+                (TPM_ALG_RSA, parameters)
+            },
+            CoseKeyParameters::Ec2 { x, y, .. } => {
+                // curveID, then kdf TPM_ALG_NULL
+                let parameters = [&TPM_ECC_NIST_P256.to_be_bytes()[..], &TPM_ALG_NULL.to_be_bytes(), &sized(x), &sized(y)].concat();
+                (TPM_ALG_ECC, parameters)
+            },
+            CoseKeyParameters::Okp { .. } => panic!("a TPM has no EdDSA keys"),
+        };
+        [
+            &r#type.to_be_bytes()[..],
+            &TPM_ALG_SHA256.to_be_bytes(),
+            // objectAttributes, an empty authPolicy, then symmetric and scheme TPM_ALG_NULL
+            &0x0006_0072u32.to_be_bytes(),
+            &0u16.to_be_bytes(),
+            &TPM_ALG_NULL.to_be_bytes(),
+            &TPM_ALG_NULL.to_be_bytes(),
+            &parameters,
+        ].concat()
+    }
+
+    fn cert_info(magic: u32, extra_data: &[u8], pub_area: &[u8]) -> Vec<u8> {
+        let name = [&TPM_ALG_SHA256.to_be_bytes()[..], &hash(MessageDigest::sha256(), pub_area).unwrap()].concat();
+        [
+            &magic.to_be_bytes()[..],
+            &TPM_ST_ATTEST_CERTIFY.to_be_bytes(),
+            // an empty qualifiedSigner
+            &0u16.to_be_bytes(),
+            &(extra_data.len() as u16).to_be_bytes(),
+            extra_data,
+            &[0; CertInfo::CLOCK_INFO_LENGTH],
+            &[0; CertInfo::FIRMWARE_VERSION_LENGTH],
+            &(name.len() as u16).to_be_bytes(),
+            &name,
+            // an empty qualifiedName
+            &0u16.to_be_bytes(),
+        ].concat()
+    }
+
+    impl Tpm {
+        fn new(aik: PKey<Private>, alg: i128, credential_public_key: CoseKey) -> Self {
+            let pub_area = pub_area(&credential_public_key);
+            let mut attestation = Attestation::new(credential_public_key, [0; 16]);
+            attestation.set("ver", Value::Text("2.0".to_owned()));
+            attestation.set("alg", Value::Integer(alg));
+            attestation.set("pubArea", Value::Bytes(pub_area.clone()));
+            let aik_certificate = aik_certificate(&aik);
+            attestation.set_x5c(&[&aik_certificate]);
+            let mut tpm = Tpm { aik, aik_certificate, attestation, pub_area };
+            tpm.certify(TPM_GENERATED_VALUE);
+            tpm
+        }
+
+        fn ecc() -> Self {
+            let (aik, _) = test_fixture::ec_key();
+            let (_, credential_public_key) = test_fixture::ec_key();
+            Tpm::new(aik, -7, credential_public_key)
+        }
+
+        fn rsa() -> Self {
+            let (aik, _) = test_fixture::rsa_key();
+            let (_, credential_public_key) = test_fixture::rsa_key();
+            Tpm::new(aik, -257, credential_public_key)
+        }
+
+        // Sets certInfo over pubArea and attToBeSigned, signed by the AIK.
+        fn certify(&mut self, magic: u32) {
+            let extra_data = hash(MessageDigest::sha256(), &self.attestation.signed_data()).unwrap();
+            let cert_info = cert_info(magic, &extra_data, &self.pub_area);
+            self.attestation.set("sig", Value::Bytes(sign(&self.aik, &cert_info)));
+            self.attestation.set("certInfo", Value::Bytes(cert_info));
+        }
+
+        fn verify(&self) -> Result<AttestationResult, AttestationError> {
+            verify(&self.attestation.input())
+        }
+    }
+
+    fn expect_malformed(result: Result<AttestationResult, AttestationError>, expected: &str) {
+        match result {
+            Err(AttestationError::MalformedStatement(field)) if field == expected => {},
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("verified without a valid {}", expected),
+        }
+    }
+
+    #[test]
+    fn verifies_ecc_statement() {
+        let tpm = Tpm::ecc();
+        let result = tpm.verify().unwrap();
+        assert_eq!(result.attestation_type, AttestationType::AttCA);
+        assert_eq!(result.trust_path[0].to_der().unwrap(), tpm.aik_certificate.to_der().unwrap());
+    }
+
+    #[test]
+    fn verifies_rsa_statement() {
+        let result = Tpm::rsa().verify().unwrap();
+        assert_eq!(result.attestation_type, AttestationType::AttCA);
+    }
+
+    #[test]
+    fn rejects_wrong_magic() {
+        let mut tpm = Tpm::ecc();
+        tpm.certify(TPM_GENERATED_VALUE ^ 1);
+        expect_malformed(tpm.verify(), "certInfo.magic");
+    }
+
+    #[test]
+    fn rejects_pub_area_of_other_key() {
+        let mut tpm = Tpm::ecc();
+        let (_, other_key) = test_fixture::ec_key();
+        tpm.attestation.credential_public_key = other_key;
+        expect_malformed(tpm.verify(), "pubArea does not match the credential public key");
+
+        let mut tpm = Tpm::rsa();
+        let (_, other_key) = test_fixture::rsa_key();
+        tpm.attestation.credential_public_key = other_key;
+        expect_malformed(tpm.verify(), "pubArea does not match the credential public key");
+    }
+
+    #[test]
+    fn rejects_certified_name_of_other_pub_area() {
+        let mut tpm = Tpm::ecc();
+        let (_, other_key) = test_fixture::ec_key();
+        tpm.pub_area = pub_area(&other_key);
+        tpm.certify(TPM_GENERATED_VALUE);
+        expect_malformed(tpm.verify(), "certInfo.attested.name");
+    }
+
+    #[test]
+    fn rejects_truncated_structures() {
+        let mut tpm = Tpm::rsa();
+        let cert_info = tpm.attestation.input().get_bytes("certInfo").unwrap().to_vec();
+        let pub_area = tpm.pub_area.clone();
+        for length in 0..pub_area.len() {
+            tpm.attestation.set("pubArea", Value::Bytes(pub_area[..length].to_vec()));
+            expect_malformed(tpm.verify(), "pubArea");
+        }
+        tpm.attestation.set("pubArea", Value::Bytes(pub_area));
+        for length in 0..cert_info.len() {
+            tpm.attestation.set("certInfo", Value::Bytes(cert_info[..length].to_vec()));
+            expect_malformed(tpm.verify(), "certInfo");
+        }
+    }
+}
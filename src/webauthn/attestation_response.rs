@@ -1,7 +1,16 @@
 use crate::AttestationResponse;
+use openssl::x509::X509;
 use serde::Deserialize;
-use sha2::{Sha256, Digest};
-use super::helper::base64_decode;
+use serde_cbor::Value;
+use super::attestation_format::{self, AttestationError, AttestationFormat, AttestationInput, AttestationType};
+use super::authenticator_data::{AuthenticatorData, AuthenticatorDataError, AuthenticatorDataFlags};
+use super::cose_key::CoseKey;
+use super::credential_option::Algorithm;
+use super::error::WebAuthnError;
+use super::metadata::{AuthenticatorStatus, MetadataStore};
+use super::origin::OriginPolicy;
+use super::trust_anchor::TrustAnchorStore;
+use super::helper::{base64_decode, sha256};
 
 pub struct ClientExtension {
     pub appid: Option<String>,
@@ -34,9 +43,9 @@ struct TokenBinding {
 
 #[derive(Deserialize)]
 pub struct ClientData {
-    challenge: String,
-    origin: String,
-    r#type: ClientDataType,
+    pub challenge: String,
+    pub origin: String,
+    pub r#type: ClientDataType,
     #[serde(rename(deserialize = "tokenBinding"))]
     token_binding: Option<TokenBinding>
 }
@@ -44,55 +53,46 @@ pub struct ClientData {
 #[derive(Deserialize)]
 pub struct AttestationObject {
     #[serde(rename(deserialize = "authData"))]
-    pub auth_data: String,
+    #[serde(with = "serde_bytes")]
+    pub auth_data: Vec<u8>,
     pub fmt: String,
     #[serde(rename(deserialize = "attStmt"))]
-    pub att_stmt: String,
+    pub att_stmt: Value,
 }
 
 impl AttestationObject {
-    const RP_ID_HASH_LENGTH: usize = 32;
-    const FLAGS_LENGTH: usize = 1;
-    const SIGN_COUNT_LENGTH: usize = 4;
-    const AUTHENTICATOR_DATA_LENGTH: usize = Self::RP_ID_HASH_LENGTH + Self::FLAGS_LENGTH + Self::SIGN_COUNT_LENGTH;
-    const AAUID_LENGTH: usize = 16;
-    const CREDENTIAL_ID_LENGTH_LENGTH: usize = 2;
-
-    pub fn get_auth_data_rp_id_hash(&self) -> Vec<u8> {
-        self.auth_data[0..32].as_bytes().to_owned()
-    }
-
-    pub fn get_flag_bit(&self) -> u8 {
-        self.auth_data[Self::RP_ID_HASH_LENGTH..Self::RP_ID_HASH_LENGTH + Self::FLAGS_LENGTH].as_bytes().to_owned()[0]
-    }
-
-    pub fn get_authenticator_data(&self) -> Option<Vec<u8>> {
-        if self.auth_data.len() <= Self::RP_ID_HASH_LENGTH + Self::FLAGS_LENGTH + Self::SIGN_COUNT_LENGTH {
-            None
-        } else {
-            let credential_id_length = self.auth_data[Self::AUTHENTICATOR_DATA_LENGTH..Self::AUTHENTICATOR_DATA_LENGTH + Self::AAUID_LENGTH + Self::CREDENTIAL_ID_LENGTH_LENGTH].as_bytes();
-            Some(vec![])
-        }
+    pub fn get_authenticator_data(&self) -> Result<AuthenticatorData, AuthenticatorDataError> {
+        AuthenticatorData::parse(&self.auth_data)
     }
 }
 
-pub enum RegistrationResponseError {
-    InvalidClientDataType,
-    InvalidChallenge,
-    InvalidOrigin,
-    InvalidRpId,
-    InvalidFlag,
+pub struct VerifiedCredential {
+    pub credential_id: Vec<u8>,
+    // CBOR encoded COSE_Key as found in authData.
+    pub credential_public_key: Vec<u8>,
+    pub algorithm: Algorithm,
+    pub sign_count: u32,
+    pub aaguid: [u8; 16],
+    pub flags: AuthenticatorDataFlags,
+    pub attestation_format: AttestationFormat,
+    pub attestation_type: AttestationType,
+    pub trust_path: Vec<X509>,
+    // Whether trust_path chains up to one of the configured trust anchors.
+    pub trusted: bool,
 }
 
 pub struct RegistrationResponse<'a> {
     pub rp_id: &'a str,
-    pub origin: &'a str,
+    pub origin_policy: &'a OriginPolicy,
     pub attestation_response: AttestationResponse,
-    pub trust_anchor_dir: &'a str,
+    pub trust_anchors: Option<&'a TrustAnchorStore>,
+    pub metadata: Option<&'a MetadataStore>,
+    pub metadata_statement_required: bool,
     pub trusted_attestaion_cert_required: bool,
     pub self_attestation_permitted: bool,
     pub none_attestation_permitted: bool,
     pub uv_required: bool,
+    pub pub_key_cred_algs: Vec<Algorithm>,
     pub expected_registration_client_extensions: Option<ClientExtension>,
     pub expected_registration_authenticator_extensions: Option<AuthenticatorExtension>,
 }
@@ -100,46 +100,49 @@ pub struct RegistrationResponse<'a> {
 impl<'a> RegistrationResponse<'a> {
     pub fn new(
         rp_id: &'a str,
-        origin: &'a str,
+        origin_policy: &'a OriginPolicy,
         attestation_response: AttestationResponse,
     ) -> Self {
         RegistrationResponse {
             rp_id,
-            origin,
+            origin_policy,
             attestation_response,
-            trust_anchor_dir: "",
+            trust_anchors: None,
+            metadata: None,
+            metadata_statement_required: false,
             trusted_attestaion_cert_required: false,
             self_attestation_permitted: false,
             none_attestation_permitted: false,
             uv_required: false,
+            pub_key_cred_algs: Algorithm::all(),
             expected_registration_client_extensions: None,
             expected_registration_authenticator_extensions: None,
         }
     }
 
-    pub fn verify(&self, challenge: &str) -> Result<(), RegistrationResponseError> {
+    pub fn verify(&self, challenge: &str) -> Result<VerifiedCredential, WebAuthnError> {
         // Spec: https://w3c.github.io/webauthn/#sctn-registering-a-new-credential
         // 1.  Let options be the PublicKeyCredentialCreationOptions that was passed as the publicKey option in the create() call.
         // - noop...
         // 2. Let JSONtext be the result of running UTF-8 decode on the value of response.clientDataJSON.
-        let decoded_cd = base64_decode(&self.attestation_response.client_data);
+        let decoded_cd = base64_decode(&self.attestation_response.client_data, "clientDataJSON")?;
 
         // 3. Let C, the client data claimed as collected during the credential creation, be the result of running an implementation-specific JSON parser on JSONtext.
-        let c = self.get_client_data(&decoded_cd);
+        let c = get_client_data(&decoded_cd)?;
 
         // 4. Verify that the value of C.type is webauthn.create.
         if &c.r#type != &ClientDataType::Create {
-            return Err(RegistrationResponseError::InvalidClientDataType)
+            return Err(WebAuthnError::InvalidClientDataType)
         }
 
         // 5. Verify that the value of C.challenge equals the base64url encoding of options.challenge.
         if &c.challenge != challenge {
-            return Err(RegistrationResponseError::InvalidChallenge)
+            return Err(WebAuthnError::InvalidChallenge)
         }
 
         // 6. Verify that the value of C.origin matches the Relying Party's origin.
-        if &c.origin != &self.origin {
-            return Err(RegistrationResponseError::InvalidOrigin)
+        if !self.origin_policy.allows(&c.origin) {
+            return Err(WebAuthnError::InvalidOrigin)
         }
 
         // 7. Verify that the value of C.tokenBinding.status matches the state of Token Binding for the TLS connection over which the assertion was obtained.
@@ -157,30 +160,35 @@ impl<'a> RegistrationResponse<'a> {
         // }
 
         // 8. Let hash be the result of computing a hash over response.clientDataJSON using SHA-256.
-        let client_data_hash = self.get_client_data_hash(&decoded_cd);
+        let client_data_hash = sha256(&decoded_cd);
 
         // 9. Perform CBOR decoding on the attestationObject field of the AuthenticatorAttestationResponse structure to obtain the attestation statement format fmt, the authenticator data authData, and the attestation statement attStmt.
-        let attestation_object = self.get_attestation_object();
+        let attestation_object = self.get_attestation_object()?;
+
+        let auth_data = attestation_object.get_authenticator_data().map_err(WebAuthnError::InvalidAuthenticatorData)?;
 
         // 10. Verify that the rpIdHash in authData is the SHA-256 hash of the RP ID expected by the Relying Party.
-        let auth_data_rp_id_hash = &attestation_object.get_auth_data_rp_id_hash();
-        if &self.rp_id.as_bytes().to_owned() != auth_data_rp_id_hash {
-            return Err(RegistrationResponseError::InvalidRpId)
+        if &auth_data.rp_id_hash[..] != sha256(self.rp_id.as_bytes()).as_slice() {
+            return Err(WebAuthnError::InvalidRpId)
         }
 
         // 11. Verify that the User Present bit of the flags in authData is set.
-        let flag_bit = &attestation_object.get_flag_bit();
-        if *flag_bit & 1 << 0 != 0x01 {
-            return Err(RegistrationResponseError::InvalidFlag)
+        if !auth_data.flags.user_present {
+            return Err(WebAuthnError::UserNotPresent)
         }
 
         // 12. If user verification is required for this registration, verify that the User Verified bit of the flags in authData is set.
-        if self.uv_required && *flag_bit & 1 << 2 != 0x04 {
-            return Err(RegistrationResponseError::InvalidFlag)
+        if self.uv_required && !auth_data.flags.user_verified {
+            return Err(WebAuthnError::UserNotVerified)
         }
 
+        let attested_credential_data = auth_data.attested_credential_data.as_ref().ok_or(WebAuthnError::MissingAttestedCredentialData)?;
+        let credential_public_key = CoseKey::from_cbor(&attested_credential_data.credential_public_key)?;
+
         // 13. Verify that the "alg" parameter in the credential public key in authData matches the alg attribute of one of the items in options.pubKeyCredParams.
-        // NOTE: omit implementing(optional)
+        if !self.pub_key_cred_algs.contains(&credential_public_key.alg) {
+            return Err(WebAuthnError::UnexpectedAlgorithm(credential_public_key.alg))
+        }
 
         // 14. Verify that the values of the client extension outputs in clientExtensionResults and the authenticator extension outputs in the extensions in authData are as expected,
         // considering the client extension input values that were given in options.extensions and any specific policy of the Relying Party regarding unsolicited extensions,
@@ -190,25 +198,256 @@ impl<'a> RegistrationResponse<'a> {
 
         // 15. Determine the attestation statement format by performing a USASCII case-sensitive match on fmt against the set of supported WebAuthn Attestation Statement Format Identifier values.
         // An up-to-date list of registered WebAuthn Attestation Statement Format Identifier values is maintained in the IANA registry of the same name [WebAuthn-Registries].
-        // NOTE: omit implementing(option)
+        let format = AttestationFormat::from_identifier(&attestation_object.fmt)
+            .ok_or_else(|| WebAuthnError::UnsupportedAttestationFormat(attestation_object.fmt.clone()))?;
 
         // 16. Verify that attStmt is a correct attestation statement, conveying a valid attestation signature, by using the attestation statement format fmt’s verification procedure given attStmt, authData and hash.
-        Ok(())
+        let att_stmt = match &attestation_object.att_stmt {
+            Value::Map(map) => map,
+            _ => return Err(WebAuthnError::InvalidAttestationStatement(AttestationError::MalformedStatement("attStmt"))),
+        };
+        let input = AttestationInput {
+            att_stmt,
+            raw_auth_data: &attestation_object.auth_data,
+            auth_data: &auth_data,
+            credential: attested_credential_data,
+            credential_public_key: &credential_public_key,
+            client_data_hash: &client_data_hash,
+        };
+        let attestation = attestation_format::verify(format, &input).map_err(WebAuthnError::InvalidAttestationStatement)?;
+
+        // 17. If validation is successful, obtain a list of acceptable trust anchors (attestation root certificates or ECDAA-Issuer public keys) for that attestation type and attestation statement format fmt, from a trusted source or from policy.
+        // The FIDO metadata is such a trusted source, its attestation roots are part of trust_anchors and its status reports rule out compromised authenticators.
+        if let Some(metadata) = self.metadata {
+            match metadata.find(&attested_credential_data.aaguid, &attestation.trust_path) {
+                Some(entry) => {
+                    if let Some(status) = entry.latest_status().filter(AuthenticatorStatus::is_compromised) {
+                        return Err(WebAuthnError::AuthenticatorStatusNotAllowed(status))
+                    }
+                },
+                None if self.metadata_statement_required => return Err(WebAuthnError::MissingMetadataStatement),
+                None => {},
+            }
+        }
+        let trusted = match self.trust_anchors {
+            Some(trust_anchors) => {
+                trust_anchors.verify_chain(&attestation.trust_path, &attested_credential_data.aaguid, format)
+                    .map_err(WebAuthnError::InvalidTrustAnchor)?
+            },
+            None => None,
+        };
+
+        // 18. Using the attestation statement format’s verification procedure and the trust anchors, assess the attestation trustworthiness.
+        // - If no attestation was provided, verify that None attestation is acceptable under Relying Party policy.
+        // - If self attestation was used, verify that self attestation is acceptable under Relying Party policy.
+        let permitted = match attestation.attestation_type {
+            AttestationType::None => self.none_attestation_permitted,
+            AttestationType::SelfAttestation => self.self_attestation_permitted,
+            _ => true,
+        };
+        if !permitted {
+            return Err(WebAuthnError::AttestationTypeNotPermitted(attestation.attestation_type))
+        }
+        // - Otherwise, use the X.509 certificates returned as the attestation trust path from the verification procedure to verify that the attestation public key either correctly chains up to an acceptable root certificate, or is itself an acceptable certificate.
+        // A chain that does not validate against the anchors we have for it is always rejected,
+        // having no anchors at all is only rejected when a trusted attestation is required.
+        let trusted = match trusted {
+            Some(false) => return Err(WebAuthnError::UntrustedAttestation),
+            Some(true) => true,
+            None if format.requires_trust_anchor() => return Err(WebAuthnError::UntrustedAttestation),
+            None => false,
+        };
+        if self.trusted_attestaion_cert_required && !trusted {
+            return Err(WebAuthnError::UntrustedAttestation)
+        }
+
+        // 19. Check that the credentialId is not yet registered to any other user.
+        // - the caller's responsibility, see VerifiedCredential.credential_id
+
+        // 20. If the attestation statement attStmt verified successfully and is found to be trustworthy, then register the new credential.
+        Ok(VerifiedCredential {
+            credential_id: attested_credential_data.credential_id.clone(),
+            credential_public_key: attested_credential_data.credential_public_key.clone(),
+            algorithm: credential_public_key.alg,
+            sign_count: auth_data.sign_count,
+            aaguid: attested_credential_data.aaguid,
+            flags: auth_data.flags,
+            attestation_format: format,
+            attestation_type: attestation.attestation_type,
+            trust_path: attestation.trust_path,
+            trusted,
+        })
+    }
+
+    fn get_attestation_object(&self) -> Result<AttestationObject, WebAuthnError> {
+        let decoded = base64_decode(&self.attestation_response.att_obj, "attestationObject")?;
+        serde_cbor::from_slice::<AttestationObject>(&decoded).map_err(|_| WebAuthnError::InvalidAttestationObject)
+    }
+}
+
+// Shared with the assertion, clientDataJSON has the same shape in both ceremonies.
+pub fn get_client_data(decoded_cd: &[u8]) -> Result<ClientData, WebAuthnError> {
+    std::str::from_utf8(decoded_cd).ok()
+        .and_then(|s| serde_json::from_str::<ClientData>(s).ok())
+        .ok_or(WebAuthnError::InvalidClientData)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use super::*;
+    use super::super::test_fixture::{self, Attestation, Ca, CHALLENGE, ORIGIN, RP_ID};
+    use super::super::trust_anchor::TrustAnchorKey;
+
+    // A synthetic registration, generated with openssl rather than captured from an authenticator: packed self
+    // attestation (ES256, user verified), in the shape the browser posts it to /verifiy_credential.
+    const REGISTRATION: &str = include_str!("test_vectors/packed_self.json");
+
+    #[derive(Deserialize)]
+    struct Registration {
+        challenge: String,
+        response: AttestationResponse,
+    }
+
+    fn registration() -> (Registration, OriginPolicy) {
+        let registration: Registration = serde_json::from_str(REGISTRATION).unwrap();
+        let origin_policy = OriginPolicy::new("localhost", &["https://localhost:55301".to_owned()]).unwrap();
+        (registration, origin_policy)
+    }
+
+    fn expect_error(result: Result<VerifiedCredential, WebAuthnError>, expected: &str) {
+        match result {
+            Err(e) => assert_eq!(e.code(), expected, "{}", e),
+            Ok(_) => panic!("verified, expected {}", expected),
+        }
+    }
+
+    #[test]
+    fn registers_credential() {
+        let (registration, origin_policy) = registration();
+        let mut registration_response = RegistrationResponse::new("localhost", &origin_policy, registration.response);
+        registration_response.self_attestation_permitted = true;
+        registration_response.uv_required = true;
+        let verified = registration_response.verify(&registration.challenge).unwrap();
+        assert_eq!(verified.credential_id.len(), 16);
+        assert_eq!(verified.algorithm, Algorithm::ES256);
+        assert_eq!(verified.attestation_format, AttestationFormat::Packed);
+        assert_eq!(verified.attestation_type, AttestationType::SelfAttestation);
+        assert!(verified.trust_path.is_empty());
+        assert!(!verified.trusted);
+    }
+
+    #[test]
+    fn rejects_other_challenge() {
+        let (registration, origin_policy) = registration();
+        let mut registration_response = RegistrationResponse::new("localhost", &origin_policy, registration.response);
+        registration_response.self_attestation_permitted = true;
+        expect_error(registration_response.verify("58q9gTru_TLuaLMHJoR-Ho1jaD3v3_dp9sBfEj1zqXd"), "invalid_challenge");
+    }
+
+    #[test]
+    fn rejects_other_origin() {
+        let (registration, _) = registration();
+        let origin_policy = OriginPolicy::new("localhost", &["https://localhost:8443".to_owned()]).unwrap();
+        let mut registration_response = RegistrationResponse::new("localhost", &origin_policy, registration.response);
+        registration_response.self_attestation_permitted = true;
+        expect_error(registration_response.verify(&registration.challenge), "invalid_origin");
+    }
+
+    #[test]
+    fn rejects_other_rp_id() {
+        let (registration, origin_policy) = registration();
+        let mut registration_response = RegistrationResponse::new("example.localhost", &origin_policy, registration.response);
+        registration_response.self_attestation_permitted = true;
+        expect_error(registration_response.verify(&registration.challenge), "invalid_rp_id");
+    }
+
+    #[test]
+    fn rejects_self_attestation_unless_permitted() {
+        let (registration, origin_policy) = registration();
+        let registration_response = RegistrationResponse::new("localhost", &origin_policy, registration.response);
+        expect_error(registration_response.verify(&registration.challenge), "attestation_type_not_permitted");
+    }
+
+    #[test]
+    fn rejects_untrusted_attestation_when_required() {
+        let (registration, origin_policy) = registration();
+        let mut registration_response = RegistrationResponse::new("localhost", &origin_policy, registration.response);
+        registration_response.self_attestation_permitted = true;
+        registration_response.trusted_attestaion_cert_required = true;
+        expect_error(registration_response.verify(&registration.challenge), "untrusted_attestation");
+    }
+
+    // An android-safetynet registration whose response is signed under root, built by test_fixture.
+    fn safetynet_registration(root: &Ca) -> AttestationResponse {
+        let (_, credential_public_key) = test_fixture::ec_key();
+        let mut attestation = Attestation::new(credential_public_key, [0; 16]);
+        let payload = attestation.safetynet_payload();
+        attestation.set_safetynet_response("attest.android.com", root, &root.certificate, &payload);
+        attestation.response("android-safetynet")
+    }
+
+    fn safetynet_trust_anchors(root: &Ca) -> TrustAnchorStore {
+        let mut trust_anchors = TrustAnchorStore::new();
+        trust_anchors.add(TrustAnchorKey::Format(AttestationFormat::AndroidSafetyNet), root.certificate.clone());
+        trust_anchors
+    }
+
+    #[test]
+    fn registers_safetynet_chained_to_trust_anchor() {
+        let origin_policy = OriginPolicy::new(RP_ID, &[ORIGIN.to_owned()]).unwrap();
+        let root = Ca::root("Fixture SafetyNet Root");
+        let trust_anchors = safetynet_trust_anchors(&root);
+        let mut registration_response = RegistrationResponse::new(RP_ID, &origin_policy, safetynet_registration(&root));
+        registration_response.trust_anchors = Some(&trust_anchors);
+        let verified = registration_response.verify(CHALLENGE).unwrap();
+        assert_eq!(verified.attestation_format, AttestationFormat::AndroidSafetyNet);
+        assert!(verified.trusted);
+    }
+
+    #[test]
+    fn rejects_safetynet_chained_to_other_root() {
+        let origin_policy = OriginPolicy::new(RP_ID, &[ORIGIN.to_owned()]).unwrap();
+        let trust_anchors = safetynet_trust_anchors(&Ca::root("Fixture SafetyNet Root"));
+        let untrusted_root = Ca::root("Fixture SafetyNet Root");
+        let mut registration_response = RegistrationResponse::new(RP_ID, &origin_policy, safetynet_registration(&untrusted_root));
+        registration_response.trust_anchors = Some(&trust_anchors);
+        expect_error(registration_response.verify(CHALLENGE), "untrusted_attestation");
+    }
+
+    #[test]
+    fn rejects_safetynet_without_trust_anchor() {
+        let origin_policy = OriginPolicy::new(RP_ID, &[ORIGIN.to_owned()]).unwrap();
+        let trust_anchors = TrustAnchorStore::new();
+        let mut registration_response = RegistrationResponse::new(RP_ID, &origin_policy, safetynet_registration(&Ca::root("Fixture SafetyNet Root")));
+        registration_response.trust_anchors = Some(&trust_anchors);
+        expect_error(registration_response.verify(CHALLENGE), "untrusted_attestation");
     }
 
-    fn get_client_data(&self, decoded_cd: &[u8]) -> ClientData {
-        let s = std::str::from_utf8(decoded_cd).unwrap();
-        serde_json::from_str::<ClientData>(s).unwrap()
+    // A none attestation from an authenticator which the metadata BLOB lists with statuses.
+    fn verify_with_metadata(statuses: &[&str]) -> Result<VerifiedCredential, WebAuthnError> {
+        const AAGUID: [u8; 16] = [0xcb, 0x69, 0x48, 0x1e, 0x8f, 0xf7, 0x40, 0x39, 0x93, 0xec, 0x0a, 0x27, 0x29, 0xa1, 0x54, 0xa8];
+        let origin_policy = OriginPolicy::new(RP_ID, &[ORIGIN.to_owned()]).unwrap();
+        let root = Ca::root("Fixture Metadata Root");
+        let blob = test_fixture::metadata_blob(&root, serde_json::json!([
+            test_fixture::metadata_entry("cb69481e-8ff7-4039-93ec-0a2729a154a8", statuses),
+        ]));
+        let metadata = MetadataStore::from_blob(&blob, root.certificate).unwrap();
+        let (_, credential_public_key) = test_fixture::ec_key();
+        let attestation = Attestation::new(credential_public_key, AAGUID);
+        let mut registration_response = RegistrationResponse::new(RP_ID, &origin_policy, attestation.response("none"));
+        registration_response.none_attestation_permitted = true;
+        registration_response.metadata = Some(&metadata);
+        registration_response.verify(CHALLENGE)
     }
 
-    fn get_attestation_object(&self) -> AttestationObject {
-        let decoded = base64_decode(&self.attestation_response.att_obj);
-        serde_cbor::from_slice::<AttestationObject>(&decoded).unwrap()
+    #[test]
+    fn registers_authenticator_in_good_standing() {
+        verify_with_metadata(&["FIDO_CERTIFIED_L1"]).unwrap();
     }
 
-    fn get_client_data_hash(&self, client_data: &[u8]) -> Vec<u8> {
-        let mut hasher = Sha256::new();
-        hasher.input(client_data);
-        hasher.result().as_slice().to_vec()
+    #[test]
+    fn rejects_revoked_authenticator() {
+        expect_error(verify_with_metadata(&["FIDO_CERTIFIED_L1", "REVOKED"]), "authenticator_status_not_allowed");
     }
 }
@@ -0,0 +1,245 @@
+use serde_cbor::Value;
+use serde_cbor::de::Deserializer;
+
+#[derive(Debug, Fail)]
+pub enum AuthenticatorDataError {
+    #[fail(display = "authenticator data truncated: {} needs {} bytes but only {} left", field, expected, actual)]
+    Truncated {
+        field: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    #[fail(display = "authenticator data has {} trailing bytes", _0)]
+    TrailingData(usize),
+    #[fail(display = "credential public key is not valid CBOR")]
+    InvalidCredentialPublicKey,
+    #[fail(display = "extensions are not a valid CBOR map")]
+    InvalidExtensions,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AuthenticatorDataFlags {
+    pub user_present: bool,
+    pub user_verified: bool,
+    pub backup_eligible: bool,
+    pub backup_state: bool,
+    pub attested_credential_data: bool,
+    pub extension_data: bool,
+}
+
+impl AuthenticatorDataFlags {
+    const UP: u8 = 1 << 0;
+    const UV: u8 = 1 << 2;
+    const BE: u8 = 1 << 3;
+    const BS: u8 = 1 << 4;
+    const AT: u8 = 1 << 6;
+    const ED: u8 = 1 << 7;
+}
+
+impl From<u8> for AuthenticatorDataFlags {
+    fn from(bits: u8) -> Self {
+        AuthenticatorDataFlags {
+            user_present: bits & Self::UP != 0,
+            user_verified: bits & Self::UV != 0,
+            backup_eligible: bits & Self::BE != 0,
+            backup_state: bits & Self::BS != 0,
+            attested_credential_data: bits & Self::AT != 0,
+            extension_data: bits & Self::ED != 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AttestedCredentialData {
+    pub aaguid: [u8; 16],
+    pub credential_id: Vec<u8>,
+    // CBOR encoded COSE_Key, kept as is so that it can be stored and decoded later.
+    pub credential_public_key: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct AuthenticatorData {
+    pub rp_id_hash: [u8; 32],
+    pub flags: AuthenticatorDataFlags,
+    pub sign_count: u32,
+    pub attested_credential_data: Option<AttestedCredentialData>,
+    pub extensions: Option<Value>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> &'a [u8] {
+        &self.data[self.position..]
+    }
+
+    fn take(&mut self, field: &'static str, length: usize) -> Result<&'a [u8], AuthenticatorDataError> {
+        let remaining = self.remaining();
+        if remaining.len() < length {
+            return Err(AuthenticatorDataError::Truncated { field, expected: length, actual: remaining.len() })
+        }
+        self.position += length;
+        Ok(&remaining[..length])
+    }
+
+    fn take_cbor(&mut self, error: AuthenticatorDataError) -> Result<(&'a [u8], Value), AuthenticatorDataError> {
+        let remaining = self.remaining();
+        let mut stream = Deserializer::from_slice(remaining).into_iter::<Value>();
+        match stream.next() {
+            Some(Ok(value)) => {
+                let length = stream.byte_offset();
+                self.position += length;
+                Ok((&remaining[..length], value))
+            },
+            _ => Err(error),
+        }
+    }
+}
+
+impl AuthenticatorData {
+    const RP_ID_HASH_LENGTH: usize = 32;
+    const FLAGS_LENGTH: usize = 1;
+    const SIGN_COUNT_LENGTH: usize = 4;
+    const AAGUID_LENGTH: usize = 16;
+    const CREDENTIAL_ID_LENGTH_LENGTH: usize = 2;
+
+    // Spec: https://w3c.github.io/webauthn/#sctn-authenticator-data
+    pub fn parse(data: &[u8]) -> Result<Self, AuthenticatorDataError> {
+        let mut reader = Reader { data, position: 0 };
+
+        let mut rp_id_hash = [0; Self::RP_ID_HASH_LENGTH];
+        rp_id_hash.copy_from_slice(reader.take("rpIdHash", Self::RP_ID_HASH_LENGTH)?);
+
+        let flags = AuthenticatorDataFlags::from(reader.take("flags", Self::FLAGS_LENGTH)?[0]);
+
+        let mut sign_count = [0; Self::SIGN_COUNT_LENGTH];
+        sign_count.copy_from_slice(reader.take("signCount", Self::SIGN_COUNT_LENGTH)?);
+        let sign_count = u32::from_be_bytes(sign_count);
+
+        let attested_credential_data = if flags.attested_credential_data {
+            let mut aaguid = [0; Self::AAGUID_LENGTH];
+            aaguid.copy_from_slice(reader.take("aaguid", Self::AAGUID_LENGTH)?);
+            let credential_id_length = reader.take("credentialIdLength", Self::CREDENTIAL_ID_LENGTH_LENGTH)?;
+            let credential_id_length = u16::from_be_bytes([credential_id_length[0], credential_id_length[1]]) as usize;
+            let credential_id = reader.take("credentialId", credential_id_length)?.to_vec();
+            let (credential_public_key, _) = reader.take_cbor(AuthenticatorDataError::InvalidCredentialPublicKey)?;
+            Some(AttestedCredentialData {
+                aaguid,
+                credential_id,
+                credential_public_key: credential_public_key.to_vec(),
+            })
+        } else {
+            None
+        };
+
+        let extensions = if flags.extension_data {
+            match reader.take_cbor(AuthenticatorDataError::InvalidExtensions)? {
+                (_, map @ Value::Map(_)) => Some(map),
+                _ => return Err(AuthenticatorDataError::InvalidExtensions),
+            }
+        } else {
+            None
+        };
+
+        match reader.remaining().len() {
+            0 => Ok(AuthenticatorData {
+                rp_id_hash,
+                flags,
+                sign_count,
+                attested_credential_data,
+                extensions,
+            }),
+            trailing => Err(AuthenticatorDataError::TrailingData(trailing)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A COSE_Key stand-in, parse only needs well-formed CBOR.
+    const CREDENTIAL_PUBLIC_KEY: &[u8] = &[0xa1, 0x01, 0x02];
+
+    // rpIdHash, flags (UP and AT) and signCount, then aaguid, a 4 byte credentialId and the key.
+    fn attested() -> Vec<u8> {
+        let mut data = vec![0x11; 32];
+        data.push(0x41);
+        data.extend_from_slice(&[0x00, 0x00, 0x00, 0x07]);
+        data.extend_from_slice(&[0x22; 16]);
+        data.extend_from_slice(&[0x00, 0x04, 0xca, 0xfe, 0xba, 0xbe]);
+        data.extend_from_slice(CREDENTIAL_PUBLIC_KEY);
+        data
+    }
+
+    #[test]
+    fn parses_attested_credential_data() {
+        let auth_data = AuthenticatorData::parse(&attested()).unwrap();
+        assert!(auth_data.flags.user_present);
+        assert!(!auth_data.flags.user_verified);
+        assert_eq!(auth_data.sign_count, 7);
+        let attested_credential_data = auth_data.attested_credential_data.unwrap();
+        assert_eq!(attested_credential_data.aaguid, [0x22; 16]);
+        assert_eq!(attested_credential_data.credential_id, vec![0xca, 0xfe, 0xba, 0xbe]);
+        assert_eq!(attested_credential_data.credential_public_key, CREDENTIAL_PUBLIC_KEY);
+        assert!(auth_data.extensions.is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_fields() {
+        let data = attested();
+        for &(length, field, expected, actual) in &[
+            (0, "rpIdHash", 32, 0),
+            (31, "rpIdHash", 32, 31),
+            (32, "flags", 1, 0),
+            (36, "signCount", 4, 3),
+            (40, "aaguid", 16, 3),
+            (54, "credentialIdLength", 2, 1),
+            (57, "credentialId", 4, 2),
+        ] {
+            match AuthenticatorData::parse(&data[..length]) {
+                Err(AuthenticatorDataError::Truncated { field: f, expected: e, actual: a }) => {
+                    assert_eq!((f, e, a), (field, expected, actual), "truncated to {} bytes", length)
+                },
+                Err(e) => panic!("truncated to {} bytes: unexpected error: {}", length, e),
+                Ok(_) => panic!("truncated to {} bytes: parsed", length),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_credential_public_key() {
+        let data = attested();
+        match AuthenticatorData::parse(&data[..data.len() - 1]) {
+            Err(AuthenticatorDataError::InvalidCredentialPublicKey) => {},
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("parsed a truncated credential public key"),
+        }
+    }
+
+    #[test]
+    fn rejects_trailing_data() {
+        let mut data = attested();
+        data.extend_from_slice(&[0x00, 0x00]);
+        match AuthenticatorData::parse(&data) {
+            Err(AuthenticatorDataError::TrailingData(2)) => {},
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("parsed trailing data"),
+        }
+    }
+
+    #[test]
+    fn rejects_extensions_that_are_not_a_map() {
+        let mut data = attested();
+        data[32] |= 0x80;
+        data.push(0x01);
+        match AuthenticatorData::parse(&data) {
+            Err(AuthenticatorDataError::InvalidExtensions) => {},
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("parsed extensions that are not a map"),
+        }
+    }
+}
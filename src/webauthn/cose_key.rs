@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, PKeyRef, Public};
+use openssl::rsa::{Padding, Rsa};
+use openssl::sign::{RsaPssSaltlen, Verifier};
+use serde_cbor::Value;
+use super::credential_option::Algorithm;
+use super::error::WebAuthnError;
+
+// Spec: https://tools.ietf.org/html/rfc8152#section-13
+const KTY: i128 = 1;
+const ALG: i128 = 3;
+const CRV: i128 = -1;
+const X: i128 = -2;
+const Y: i128 = -3;
+const N: i128 = -1;
+const E: i128 = -2;
+
+const KTY_OKP: i128 = 1;
+const KTY_EC2: i128 = 2;
+const KTY_RSA: i128 = 3;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Curve {
+    P256,
+    P384,
+    P521,
+    Ed25519,
+}
+
+impl Curve {
+    fn from_code(code: i128) -> Option<Self> {
+        match code {
+            1 => Some(Self::P256),
+            2 => Some(Self::P384),
+            3 => Some(Self::P521),
+            6 => Some(Self::Ed25519),
+            _ => None,
+        }
+    }
+
+    fn coordinate_length(&self) -> usize {
+        match self {
+            Self::P256 | Self::Ed25519 => 32,
+            Self::P384 => 48,
+            Self::P521 => 66,
+        }
+    }
+
+    fn nid(&self) -> Nid {
+        match self {
+            Self::P256 => Nid::X9_62_PRIME256V1,
+            Self::P384 => Nid::SECP384R1,
+            Self::P521 => Nid::SECP521R1,
+            Self::Ed25519 => Nid::UNDEF,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum CoseKeyParameters {
+    Ec2 { crv: Curve, x: Vec<u8>, y: Vec<u8> },
+    Okp { crv: Curve, x: Vec<u8> },
+    Rsa { n: Vec<u8>, e: Vec<u8> },
+}
+
+#[derive(Clone, Debug)]
+pub struct CoseKey {
+    pub alg: Algorithm,
+    pub parameters: CoseKeyParameters,
+}
+
+impl CoseKey {
+    // SubjectPublicKeyInfo header of an Ed25519 key (RFC 8410), the raw 32 bytes key follows.
+    const ED25519_SPKI_PREFIX: [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+
+    pub fn from_cbor(data: &[u8]) -> Result<Self, WebAuthnError> {
+        match serde_cbor::from_slice::<Value>(data) {
+            Ok(Value::Map(map)) => Self::from_map(&map),
+            _ => Err(WebAuthnError::InvalidCOSEKey),
+        }
+    }
+
+    fn from_map(map: &BTreeMap<Value, Value>) -> Result<Self, WebAuthnError> {
+        let kty = Self::get_integer(map, KTY)?;
+        let alg = Self::get_integer(map, ALG)?;
+        let alg = Algorithm::from_code(alg as i64).ok_or(WebAuthnError::UnsupportedAlgorithm(alg as i64))?;
+        let parameters = match kty {
+            KTY_EC2 => {
+                let crv = Curve::from_code(Self::get_integer(map, CRV)?).ok_or(WebAuthnError::InvalidCOSEKey)?;
+                let x = Self::get_bytes(map, X)?;
+                let y = Self::get_bytes(map, Y)?;
+                if x.len() != crv.coordinate_length() || y.len() != crv.coordinate_length() {
+                    return Err(WebAuthnError::InvalidCOSEKey)
+                }
+                CoseKeyParameters::Ec2 { crv, x, y }
+            },
+            KTY_OKP => {
+                let crv = Curve::from_code(Self::get_integer(map, CRV)?).ok_or(WebAuthnError::InvalidCOSEKey)?;
+                let x = Self::get_bytes(map, X)?;
+                if x.len() != crv.coordinate_length() {
+                    return Err(WebAuthnError::InvalidCOSEKey)
+                }
+                CoseKeyParameters::Okp { crv, x }
+            },
+            KTY_RSA => CoseKeyParameters::Rsa {
+                n: Self::get_bytes(map, N)?,
+                e: Self::get_bytes(map, E)?,
+            },
+            _ => return Err(WebAuthnError::InvalidCOSEKey),
+        };
+        let key = CoseKey { alg, parameters };
+        key.validate()?;
+        Ok(key)
+    }
+
+    fn get_integer(map: &BTreeMap<Value, Value>, label: i128) -> Result<i128, WebAuthnError> {
+        match map.get(&Value::Integer(label)) {
+            Some(Value::Integer(v)) => Ok(*v),
+            _ => Err(WebAuthnError::InvalidCOSEKey),
+        }
+    }
+
+    fn get_bytes(map: &BTreeMap<Value, Value>, label: i128) -> Result<Vec<u8>, WebAuthnError> {
+        match map.get(&Value::Integer(label)) {
+            Some(Value::Bytes(v)) => Ok(v.clone()),
+            _ => Err(WebAuthnError::InvalidCOSEKey),
+        }
+    }
+
+    fn validate(&self) -> Result<(), WebAuthnError> {
+        let valid = match (&self.alg, &self.parameters) {
+            (Algorithm::ES256, CoseKeyParameters::Ec2 { crv: Curve::P256, .. }) => true,
+            (Algorithm::ES384, CoseKeyParameters::Ec2 { crv: Curve::P384, .. }) => true,
+            (Algorithm::ES512, CoseKeyParameters::Ec2 { crv: Curve::P521, .. }) => true,
+            (Algorithm::EdDSA, CoseKeyParameters::Okp { crv: Curve::Ed25519, .. }) => true,
+            (Algorithm::PS256, CoseKeyParameters::Rsa { .. }) => true,
+            (Algorithm::RS256, CoseKeyParameters::Rsa { .. }) => true,
+            _ => false,
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(WebAuthnError::InvalidCOSEKey)
+        }
+    }
+
+    pub fn to_pkey(&self) -> Result<PKey<Public>, WebAuthnError> {
+        let pkey = match &self.parameters {
+            CoseKeyParameters::Ec2 { crv, x, y } => {
+                EcGroup::from_curve_name(crv.nid())
+                    .and_then(|group| {
+                        let x = BigNum::from_slice(x)?;
+                        let y = BigNum::from_slice(y)?;
+                        EcKey::from_public_key_affine_coordinates(&group, &x, &y)
+                    })
+                    .and_then(PKey::from_ec_key)
+            },
+            CoseKeyParameters::Okp { x, .. } => {
+                let der = [&Self::ED25519_SPKI_PREFIX[..], x].concat();
+                PKey::public_key_from_der(&der)
+            },
+            CoseKeyParameters::Rsa { n, e } => {
+                BigNum::from_slice(n)
+                    .and_then(|n| Ok((n, BigNum::from_slice(e)?)))
+                    .and_then(|(n, e)| Rsa::from_public_components(n, e))
+                    .and_then(PKey::from_rsa)
+            },
+        };
+        pkey.map_err(|_| WebAuthnError::InvalidCOSEKey)
+    }
+
+    pub fn verify_signature(&self, data: &[u8], signature: &[u8]) -> Result<bool, WebAuthnError> {
+        let pkey = self.to_pkey()?;
+        Ok(verify_signature(self.alg, &pkey, data, signature))
+    }
+}
+
+pub fn verify_signature(alg: Algorithm, pkey: &PKeyRef<Public>, data: &[u8], signature: &[u8]) -> bool {
+    let verified = match alg {
+        Algorithm::EdDSA => {
+            Verifier::new_without_digest(pkey)
+                .and_then(|mut verifier| verifier.verify_oneshot(signature, data))
+        },
+        Algorithm::PS256 => {
+            Verifier::new(MessageDigest::sha256(), pkey)
+                .and_then(|mut verifier| {
+                    verifier.set_rsa_padding(Padding::PKCS1_PSS)?;
+                    verifier.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+                    verifier.update(data)?;
+                    verifier.verify(signature)
+                })
+        },
+        alg => {
+            Verifier::new(alg.message_digest(), pkey)
+                .and_then(|mut verifier| {
+                    verifier.update(data)?;
+                    verifier.verify(signature)
+                })
+        },
+    };
+    // openssl reports malformed signatures as errors, which are just invalid signatures for us.
+    verified.unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An ES256 credential public key, as authenticators put it in attested credential data.
+    const ES256_KEY: &[u8] = &[
+        0xa5, 0x01, 0x02, 0x03, 0x26, 0x20, 0x01,
+        0x21, 0x58, 0x20,
+        0x40, 0x69, 0x63, 0x5f, 0x88, 0x06, 0x85, 0x38, 0xa0, 0xda, 0xe9, 0x62, 0x2b, 0xb6, 0xca, 0x47,
+        0xd2, 0x01, 0x99, 0xf5, 0xcb, 0xa5, 0x3f, 0xc6, 0x16, 0xfd, 0xc0, 0x48, 0x9e, 0xdf, 0xf1, 0x96,
+        0x22, 0x58, 0x20,
+        0xb7, 0x3d, 0xe3, 0xc6, 0x7e, 0x29, 0xd6, 0xf5, 0x65, 0x89, 0x2f, 0x26, 0x12, 0x0e, 0x6f, 0x86,
+        0xc3, 0x14, 0xfb, 0x5b, 0x23, 0x67, 0x56, 0x49, 0x05, 0x91, 0xa0, 0x2a, 0x8d, 0x95, 0xcd, 0x40,
+    ];
+
+    fn expect_invalid(data: &[u8]) {
+        match CoseKey::from_cbor(data) {
+            Err(WebAuthnError::InvalidCOSEKey) => {},
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("decoded an invalid key"),
+        }
+    }
+
+    #[test]
+    fn decodes_es256_key() {
+        let key = CoseKey::from_cbor(ES256_KEY).unwrap();
+        assert_eq!(key.alg, Algorithm::ES256);
+    }
+
+    #[test]
+    fn rejects_truncated_key() {
+        for length in 0..ES256_KEY.len() {
+            expect_invalid(&ES256_KEY[..length]);
+        }
+    }
+
+    #[test]
+    fn rejects_trailing_data() {
+        let mut data = ES256_KEY.to_vec();
+        data.push(0x00);
+        expect_invalid(&data);
+    }
+
+    #[test]
+    fn rejects_short_coordinate() {
+        // y as a 31 byte string, the map still being well-formed CBOR
+        let mut data = ES256_KEY[..ES256_KEY.len() - 32].to_vec();
+        let y_header = data.len() - 1;
+        data[y_header] = 0x1f;
+        data.extend_from_slice(&ES256_KEY[ES256_KEY.len() - 31..]);
+        expect_invalid(&data);
+    }
+}
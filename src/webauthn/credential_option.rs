@@ -1,26 +1,96 @@
-use serde::{Serialize, Serializer};
+use openssl::hash::MessageDigest;
+use serde::{Deserialize, Serialize, Serializer};
 use crate::helper::generate_random;
 
-#[derive(Clone, Copy, Serialize)]
-#[serde(rename(serialize = "lowercase"))]
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Attestation {
     None,
     Indirect,
     Direct,
 }
 
-#[derive(Clone, Copy, Serialize)]
-#[serde(rename(serialize = "lowercase"))]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum UserVerification {
     Required,
     Preferred,
     Discouraged,
 }
 
+// ref: https://w3c.github.io/webauthn/#enum-residentKeyRequirement
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all(serialize = "lowercase"))]
+pub enum ResidentKeyRequirement {
+    Discouraged,
+    Preferred,
+    Required,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Algorithm {
     ES256,
+    ES384,
+    ES512,
+    EdDSA,
     PS256,
     RS256,
+    // Only used by TPM attestation statements, never offered for new credentials.
+    RS1,
+}
+
+impl Algorithm {
+    pub fn all() -> Vec<Self> {
+        vec![Self::ES256, Self::ES384, Self::ES512, Self::EdDSA, Self::PS256, Self::RS256]
+    }
+
+    // ref: https://www.iana.org/assignments/cose/cose.xhtml#algorithms
+    pub fn code(&self) -> i64 {
+        match self {
+            Self::ES256 => -7,
+            Self::ES384 => -35,
+            Self::ES512 => -36,
+            Self::EdDSA => -8,
+            Self::PS256 => -37,
+            Self::RS256 => -257,
+            Self::RS1 => -65535,
+        }
+    }
+
+    pub fn from_code(code: i64) -> Option<Self> {
+        match code {
+            -7 => Some(Self::ES256),
+            -35 => Some(Self::ES384),
+            -36 => Some(Self::ES512),
+            -8 => Some(Self::EdDSA),
+            -37 => Some(Self::PS256),
+            -257 => Some(Self::RS256),
+            -65535 => Some(Self::RS1),
+            _ => None,
+        }
+    }
+
+    // The names used in configuration, RS1 is left out as it is never offered.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ES256" => Some(Self::ES256),
+            "ES384" => Some(Self::ES384),
+            "ES512" => Some(Self::ES512),
+            "EdDSA" => Some(Self::EdDSA),
+            "PS256" => Some(Self::PS256),
+            "RS256" => Some(Self::RS256),
+            _ => None,
+        }
+    }
+
+    pub fn message_digest(&self) -> MessageDigest {
+        match self {
+            Self::ES384 => MessageDigest::sha384(),
+            Self::ES512 => MessageDigest::sha512(),
+            Self::RS1 => MessageDigest::sha1(),
+            _ => MessageDigest::sha256(),
+        }
+    }
 }
 
 impl Serialize for Algorithm {
@@ -28,12 +98,7 @@ impl Serialize for Algorithm {
         where
             S: Serializer,
     {
-        let code: i16 = match self {
-            Self::ES256 => -7,
-            Self::PS256 => -37,
-            Self::RS256 => -257,
-        };
-        serializer.serialize_i16(code)
+        serializer.serialize_i64(self.code())
     }
 }
 
@@ -157,7 +222,10 @@ pub struct AuthenticatorSelection {
     authenticator_attachment: Option<AuthenticatorAttachment>,
     #[serde(rename(serialize = "requireResidentKey"))]
     #[serde(skip_serializing_if = "Option::is_none")]
-    require_resident_key: Option<bool>
+    require_resident_key: Option<bool>,
+    #[serde(rename(serialize = "residentKey"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resident_key: Option<ResidentKeyRequirement>,
 }
 
 impl AuthenticatorSelection {
@@ -166,21 +234,48 @@ impl AuthenticatorSelection {
             user_verification,
             authenticator_attachment,
             require_resident_key,
+            resident_key: None,
+        }
+    }
+
+    // requireResidentKey is kept for Level 1 clients, which only know it: true if and only if residentKey is required.
+    pub fn with_resident_key(user_verification: Option<UserVerification>, authenticator_attachment: Option<AuthenticatorAttachment>, resident_key: ResidentKeyRequirement) -> Self {
+        AuthenticatorSelection {
+            user_verification,
+            authenticator_attachment,
+            require_resident_key: Some(match resident_key {
+                ResidentKeyRequirement::Required => true,
+                _ => false,
+            }),
+            resident_key: Some(resident_key),
         }
     }
 }
 
 
 #[derive(Clone, Copy)]
-pub enum ExcludeCredentialTransport {
+pub enum AuthenticatorTransport {
     USB,
     NFC,
     BLE,
     INTERNAL,
 }
 
+impl AuthenticatorTransport {
+    // Unknown transports are to be ignored, ref: https://w3c.github.io/webauthn/#enum-transport
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "usb" => Some(Self::USB),
+            "nfc" => Some(Self::NFC),
+            "ble" => Some(Self::BLE),
+            "internal" => Some(Self::INTERNAL),
+            _ => None,
+        }
+    }
+}
+
 
-impl Serialize for ExcludeCredentialTransport {
+impl Serialize for AuthenticatorTransport {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where
             S: Serializer
@@ -200,11 +295,11 @@ pub struct ExcludeCredential {
     r#type: String,
     id: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    transports: Option<ExcludeCredentialTransport>
+    transports: Option<AuthenticatorTransport>
 }
 
 impl ExcludeCredential {
-    pub fn new(id: String, transports: Option<ExcludeCredentialTransport>) -> Self {
+    pub fn new(id: String, transports: Option<AuthenticatorTransport>) -> Self {
         ExcludeCredential {
             r#type: "public-key".to_owned(),  // https://developer.mozilla.org/en-US/docs/Web/API/PublicKeyCredentialCreationOptions/excludeCredentials#Value
             id,
@@ -261,3 +356,62 @@ impl PublicKeyCredentialCreationOptions {
         }
     }
 }
+
+
+#[derive(Serialize)]
+pub struct AllowCredential {
+    r#type: String,
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transports: Option<Vec<AuthenticatorTransport>>
+}
+
+impl AllowCredential {
+    pub fn new(id: String, transports: Option<Vec<AuthenticatorTransport>>) -> Self {
+        AllowCredential {
+            r#type: "public-key".to_owned(),
+            id,
+            transports,
+        }
+    }
+}
+
+
+#[derive(Serialize)]
+pub struct PublicKeyCredentialRequestOptions {
+    // ref: https://developer.mozilla.org/en-US/docs/Web/API/PublicKeyCredentialRequestOptions
+    pub challenge: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<usize>,
+    #[serde(rename(serialize = "rpId"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rp_id: Option<String>,
+    #[serde(rename(serialize = "allowCredentials"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_credentials: Option<Vec<AllowCredential>>,
+    #[serde(rename(serialize = "userVerification"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_verification: Option<UserVerification>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Extension>,
+}
+
+impl PublicKeyCredentialRequestOptions {
+    pub fn new(
+        challenge_length: usize,
+        timeout: Option<usize>,
+        rp_id: Option<&str>,
+        allow_credentials: Option<Vec<AllowCredential>>,
+        user_verification: Option<UserVerification>,
+        extensions: Option<Extension>,
+    ) -> Self {
+        PublicKeyCredentialRequestOptions {
+            challenge: generate_random(challenge_length),
+            timeout,
+            rp_id: rp_id.map(|v| v.to_owned()),
+            allow_credentials,
+            user_verification,
+            extensions,
+        }
+    }
+}
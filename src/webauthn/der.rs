@@ -0,0 +1,209 @@
+// Just enough of a DER reader to look into the certificate extensions the attestation formats care about,
+// which openssl does not expose.
+// ref: https://www.itu.int/rec/T-REC-X.690
+
+pub const TAG_BOOLEAN: u32 = 0x01;
+pub const TAG_INTEGER: u32 = 0x02;
+pub const TAG_OCTET_STRING: u32 = 0x04;
+pub const TAG_OID: u32 = 0x06;
+pub const TAG_SEQUENCE: u32 = 0x30;
+pub const TAG_SET: u32 = 0x31;
+pub const TAG_VERSION: u32 = 0xa0;
+pub const TAG_EXTENSIONS: u32 = 0xa3;
+
+// id-ce-basicConstraints: 2.5.29.19
+pub const OID_BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1d, 0x13];
+// id-ce-subjectAltName: 2.5.29.17
+pub const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+// id-ce-extKeyUsage: 2.5.29.37
+pub const OID_EXT_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+
+#[derive(Debug, Fail)]
+#[fail(display = "malformed DER structure")]
+pub struct DerError;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Tlv<'a> {
+    // High tag number forms keep all identifier octets, e.g. [600] is 0xbf8458.
+    pub tag: u32,
+    pub value: &'a [u8],
+}
+
+impl<'a> Tlv<'a> {
+    pub fn expect(&self, tag: u32) -> Result<&'a [u8], DerError> {
+        if self.tag == tag {
+            Ok(self.value)
+        } else {
+            Err(DerError)
+        }
+    }
+
+    pub fn children(&self) -> Result<Vec<Tlv<'a>>, DerError> {
+        parse_all(self.value)
+    }
+}
+
+pub fn parse<'a>(data: &'a [u8]) -> Result<(Tlv<'a>, &'a [u8]), DerError> {
+    let mut tag = *data.first().ok_or(DerError)? as u32;
+    let mut position = 1;
+    if tag & 0x1f == 0x1f {
+        loop {
+            let b = *data.get(position).ok_or(DerError)?;
+            position += 1;
+            if position > 4 {
+                return Err(DerError)
+            }
+            tag = (tag << 8) | b as u32;
+            if b & 0x80 == 0 {
+                break
+            }
+        }
+    }
+    let (length, header_length) = match *data.get(position).ok_or(DerError)? {
+        l if l & 0x80 == 0 => (l as usize, position + 1),
+        l => {
+            let length_length = (l & 0x7f) as usize;
+            let start = position + 1;
+            if length_length == 0 || length_length > 4 || data.len() < start + length_length {
+                return Err(DerError)
+            }
+            let length = data[start..start + length_length].iter().fold(0, |acc, b| (acc << 8) | *b as usize);
+            (length, start + length_length)
+        },
+    };
+    if data.len() < header_length + length {
+        return Err(DerError)
+    }
+    let value = &data[header_length..header_length + length];
+    Ok((Tlv { tag, value }, &data[header_length + length..]))
+}
+
+pub fn parse_all<'a>(mut data: &'a [u8]) -> Result<Vec<Tlv<'a>>, DerError> {
+    let mut items = vec![];
+    while !data.is_empty() {
+        let (item, rest) = parse(data)?;
+        items.push(item);
+        data = rest;
+    }
+    Ok(items)
+}
+
+pub fn parse_integer(value: &[u8]) -> Result<i64, DerError> {
+    if value.is_empty() || value.len() > 8 {
+        return Err(DerError)
+    }
+    let initial = if value[0] & 0x80 != 0 { -1 } else { 0 };
+    Ok(value.iter().fold(initial, |acc, b| (acc << 8) | *b as i64))
+}
+
+#[derive(Debug)]
+pub struct Extension<'a> {
+    pub oid: &'a [u8],
+    pub critical: bool,
+    pub value: &'a [u8],
+}
+
+pub struct Certificate<'a> {
+    pub version: i64,
+    pub subject: Tlv<'a>,
+    pub extensions: Vec<Extension<'a>>,
+}
+
+impl<'a> Certificate<'a> {
+    // Certificate ::= SEQUENCE { tbsCertificate, signatureAlgorithm, signatureValue }
+    // TBSCertificate ::= SEQUENCE { [0] version, serialNumber, signature, issuer, validity, subject, subjectPublicKeyInfo, [1], [2], [3] extensions }
+    pub fn parse(der: &'a [u8]) -> Result<Self, DerError> {
+        let (certificate, _) = parse(der)?;
+        let certificate = parse_all(certificate.expect(TAG_SEQUENCE)?)?;
+        let tbs = certificate.first().ok_or(DerError)?.children()?;
+        let (version, rest) = match tbs.first() {
+            Some(v) if v.tag == TAG_VERSION => {
+                let (version, _) = parse(v.value)?;
+                (parse_integer(version.expect(TAG_INTEGER)?)?, &tbs[1..])
+            },
+            // v1 certificates omit the version field.
+            _ => (0, &tbs[..]),
+        };
+        let subject = *rest.get(4).ok_or(DerError)?;
+        let mut extensions = vec![];
+        if let Some(e) = rest.iter().find(|v| v.tag == TAG_EXTENSIONS) {
+            let (sequence, _) = parse(e.value)?;
+            for extension in parse_all(sequence.expect(TAG_SEQUENCE)?)? {
+                let fields = extension.children()?;
+                let oid = fields.first().ok_or(DerError)?.expect(TAG_OID)?;
+                let (critical, value) = match (fields.get(1), fields.get(2)) {
+                    (Some(c), Some(v)) => (c.expect(TAG_BOOLEAN)?.first() != Some(&0), v.expect(TAG_OCTET_STRING)?),
+                    (Some(v), None) => (false, v.expect(TAG_OCTET_STRING)?),
+                    _ => return Err(DerError),
+                };
+                extensions.push(Extension { oid, critical, value });
+            }
+        }
+        Ok(Certificate { version, subject, extensions })
+    }
+
+    pub fn extension(&self, oid: &[u8]) -> Option<&Extension<'a>> {
+        self.extensions.iter().find(|e| e.oid == oid)
+    }
+
+    // BasicConstraints ::= SEQUENCE { cA BOOLEAN DEFAULT FALSE, pathLenConstraint INTEGER OPTIONAL }
+    pub fn is_ca(&self) -> Result<bool, DerError> {
+        match self.extension(OID_BASIC_CONSTRAINTS) {
+            None => Ok(false),
+            Some(e) => {
+                let (constraints, _) = parse(e.value)?;
+                match constraints.children()?.first() {
+                    Some(ca) if ca.tag == TAG_BOOLEAN => Ok(ca.value.first().map_or(false, |v| *v != 0)),
+                    _ => Ok(false),
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_short_and_long_form_lengths() {
+        let (tlv, rest) = parse(&[0x04, 0x02, 0xaa, 0xbb]).unwrap();
+        assert_eq!(tlv.tag, TAG_OCTET_STRING);
+        assert_eq!(tlv.value, &[0xaa, 0xbb]);
+        assert!(rest.is_empty());
+
+        let mut long = vec![0x04, 0x81, 0x80];
+        long.extend_from_slice(&[0x55; 0x80]);
+        let (tlv, rest) = parse(&long).unwrap();
+        assert_eq!(tlv.value.len(), 0x80);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn parses_high_tag_number_form() {
+        let (tlv, _) = parse(&[0xbf, 0x84, 0x58, 0x00]).unwrap();
+        assert_eq!(tlv.tag, 0xbf8458);
+        assert!(tlv.value.is_empty());
+    }
+
+    #[test]
+    fn leaves_trailing_data_to_the_caller() {
+        let (tlv, rest) = parse(&[0x02, 0x01, 0x05, 0xff]).unwrap();
+        assert_eq!(parse_integer(tlv.expect(TAG_INTEGER).unwrap()).unwrap(), 5);
+        assert_eq!(rest, &[0xff]);
+        assert!(parse_all(&[0x02, 0x01, 0x05, 0xff]).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        assert!(parse(&[]).is_err());
+        // identifier octets
+        assert!(parse(&[0xbf, 0x84]).is_err());
+        // length octets
+        assert!(parse(&[0x04]).is_err());
+        assert!(parse(&[0x04, 0x82, 0x01]).is_err());
+        // contents octets
+        assert!(parse(&[0x04, 0x03, 0xaa, 0xbb]).is_err());
+        assert!(parse(&[0x04, 0x81, 0x80, 0xaa]).is_err());
+    }
+}
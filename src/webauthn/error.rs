@@ -1,11 +1,137 @@
+use serde::Serialize;
+use super::attestation_format::{AttestationError, AttestationType};
+use super::authenticator_data::AuthenticatorDataError;
+use super::credential_option::Algorithm;
+use super::metadata::AuthenticatorStatus;
+use super::trust_anchor::TrustAnchorError;
+
+// Reported as the kind of a problem document, so that clients can tell a broken client from a refused authenticator.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorKind {
+    // The response could not be decoded, it is malformed rather than wrong.
+    Decode,
+    // The response is well-formed but does not satisfy the ceremony or the Relying Party policy.
+    Policy,
+    // A signature, key or certificate chain did not verify.
+    Crypto,
+}
+
 #[derive(Debug, Fail)]
 pub enum WebAuthnError {
+    #[fail(display = "{} is not valid base64url", _0)]
+    InvalidBase64(&'static str),
+    #[fail(display = "clientDataJSON is not valid UTF-8 JSON client data")]
+    InvalidClientData,
+    #[fail(display = "attestationObject is not a valid CBOR attestation object")]
+    InvalidAttestationObject,
+    #[fail(display = "authenticator data is malformed: {}", _0)]
+    InvalidAuthenticatorData(#[cause] AuthenticatorDataError),
+    #[fail(display = "authenticator data has no attested credential data")]
+    MissingAttestedCredentialData,
     #[fail(display = "invalid COSE key")]
     InvalidCOSEKey,
-    #[fail(display = "authentication rejected")]
-    AuthenticationRejected,
-    #[fail(display = "registration rejected")]
-    RegistrationRejected,
-    #[fail(display = "webauthn user data missing")]
-    WebAuthnUserDataMissing,
+    #[fail(display = "unsupported COSE algorithm: {}", _0)]
+    UnsupportedAlgorithm(i64),
+    #[fail(display = "unsupported attestation statement format: {}", _0)]
+    UnsupportedAttestationFormat(String),
+
+    #[fail(display = "client data type does not match the ceremony")]
+    InvalidClientDataType,
+    #[fail(display = "challenge does not match")]
+    InvalidChallenge,
+    #[fail(display = "origin is not allowed")]
+    InvalidOrigin,
+    #[fail(display = "rpIdHash does not match the RP ID")]
+    InvalidRpId,
+    #[fail(display = "user was not present")]
+    UserNotPresent,
+    #[fail(display = "user was not verified")]
+    UserNotVerified,
+    #[fail(display = "credential algorithm {:?} was not requested", _0)]
+    UnexpectedAlgorithm(Algorithm),
+    #[fail(display = "attestation type {:?} is not permitted", _0)]
+    AttestationTypeNotPermitted(AttestationType),
+    #[fail(display = "authenticator has no metadata statement")]
+    MissingMetadataStatement,
+    #[fail(display = "authenticator status {:?} is not allowed", _0)]
+    AuthenticatorStatusNotAllowed(AuthenticatorStatus),
+    #[fail(display = "credential is not allowed")]
+    InvalidCredential,
+    #[fail(display = "user handle does not match the credential owner")]
+    InvalidUserHandle,
+    #[fail(display = "signature counter {} did not increase", _0)]
+    InvalidSignCount(u32),
+
+    #[fail(display = "{}", _0)]
+    InvalidAttestationStatement(#[cause] AttestationError),
+    #[fail(display = "attestation trust anchors are unusable: {}", _0)]
+    InvalidTrustAnchor(#[cause] TrustAnchorError),
+    #[fail(display = "attestation does not chain up to a trusted root")]
+    UntrustedAttestation,
+    #[fail(display = "assertion signature is invalid")]
+    InvalidSignature,
+}
+
+impl WebAuthnError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            WebAuthnError::InvalidBase64(_)
+            | WebAuthnError::InvalidClientData
+            | WebAuthnError::InvalidAttestationObject
+            | WebAuthnError::InvalidAuthenticatorData(_)
+            | WebAuthnError::MissingAttestedCredentialData
+            | WebAuthnError::InvalidCOSEKey
+            | WebAuthnError::UnsupportedAlgorithm(_)
+            | WebAuthnError::UnsupportedAttestationFormat(_) => ErrorKind::Decode,
+            WebAuthnError::InvalidClientDataType
+            | WebAuthnError::InvalidChallenge
+            | WebAuthnError::InvalidOrigin
+            | WebAuthnError::InvalidRpId
+            | WebAuthnError::UserNotPresent
+            | WebAuthnError::UserNotVerified
+            | WebAuthnError::UnexpectedAlgorithm(_)
+            | WebAuthnError::AttestationTypeNotPermitted(_)
+            | WebAuthnError::MissingMetadataStatement
+            | WebAuthnError::AuthenticatorStatusNotAllowed(_)
+            | WebAuthnError::InvalidCredential
+            | WebAuthnError::InvalidUserHandle
+            | WebAuthnError::InvalidSignCount(_) => ErrorKind::Policy,
+            WebAuthnError::InvalidAttestationStatement(_)
+            | WebAuthnError::InvalidTrustAnchor(_)
+            | WebAuthnError::UntrustedAttestation
+            | WebAuthnError::InvalidSignature => ErrorKind::Crypto,
+        }
+    }
+
+    /// A stable identifier for clients to match on, the display message may change.
+    pub fn code(&self) -> &'static str {
+        match self {
+            WebAuthnError::InvalidBase64(_) => "invalid_base64",
+            WebAuthnError::InvalidClientData => "invalid_client_data",
+            WebAuthnError::InvalidAttestationObject => "invalid_attestation_object",
+            WebAuthnError::InvalidAuthenticatorData(_) => "invalid_authenticator_data",
+            WebAuthnError::MissingAttestedCredentialData => "missing_attested_credential_data",
+            WebAuthnError::InvalidCOSEKey => "invalid_cose_key",
+            WebAuthnError::UnsupportedAlgorithm(_) => "unsupported_algorithm",
+            WebAuthnError::UnsupportedAttestationFormat(_) => "unsupported_attestation_format",
+            WebAuthnError::InvalidClientDataType => "invalid_client_data_type",
+            WebAuthnError::InvalidChallenge => "invalid_challenge",
+            WebAuthnError::InvalidOrigin => "invalid_origin",
+            WebAuthnError::InvalidRpId => "invalid_rp_id",
+            WebAuthnError::UserNotPresent => "user_not_present",
+            WebAuthnError::UserNotVerified => "user_not_verified",
+            WebAuthnError::UnexpectedAlgorithm(_) => "unexpected_algorithm",
+            WebAuthnError::AttestationTypeNotPermitted(_) => "attestation_type_not_permitted",
+            WebAuthnError::MissingMetadataStatement => "missing_metadata_statement",
+            WebAuthnError::AuthenticatorStatusNotAllowed(_) => "authenticator_status_not_allowed",
+            WebAuthnError::InvalidCredential => "invalid_credential",
+            WebAuthnError::InvalidUserHandle => "invalid_user_handle",
+            WebAuthnError::InvalidSignCount(_) => "invalid_sign_count",
+            WebAuthnError::InvalidAttestationStatement(_) => "invalid_attestation_statement",
+            WebAuthnError::InvalidTrustAnchor(_) => "invalid_trust_anchor",
+            WebAuthnError::UntrustedAttestation => "untrusted_attestation",
+            WebAuthnError::InvalidSignature => "invalid_signature",
+        }
+    }
 }
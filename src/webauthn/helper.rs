@@ -1,3 +1,31 @@
-pub fn base64_decode(s: &str) -> Vec<u8> {
-    base64::decode_config(s, base64::URL_SAFE).unwrap()
+use sha2::{Digest, Sha256};
+use super::error::WebAuthnError;
+
+// field names the member of the response for the error, the input comes straight from the client.
+pub fn base64_decode(s: &str, field: &'static str) -> Result<Vec<u8>, WebAuthnError> {
+    base64::decode_config(s, base64::URL_SAFE).map_err(|_| WebAuthnError::InvalidBase64(field))
+}
+
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+// Accepts both "0123456789abcdef0123456789abcdef" and the hyphenated UUID form.
+pub fn parse_aaguid(s: &str) -> Option<[u8; 16]> {
+    let hex: Vec<char> = s.chars().filter(|c| *c != '-').collect();
+    if hex.len() != 32 {
+        return None
+    }
+    let mut aaguid = [0; 16];
+    for (i, pair) in hex.chunks(2).enumerate() {
+        let high = pair[0].to_digit(16)?;
+        let low = pair[1].to_digit(16)?;
+        aaguid[i] = (high * 16 + low) as u8;
+    }
+    Some(aaguid)
+}
+
+pub fn format_aaguid(aaguid: &[u8; 16]) -> String {
+    let hex: String = aaguid.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
 }
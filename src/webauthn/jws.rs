@@ -0,0 +1,178 @@
+use openssl::bn::BigNum;
+use openssl::ecdsa::EcdsaSig;
+use openssl::pkey::{PKeyRef, Public};
+use openssl::x509::X509;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use super::cose_key::verify_signature;
+use super::credential_option::Algorithm;
+
+// ref: https://tools.ietf.org/html/rfc7515#section-7.1
+#[derive(Debug, Fail)]
+pub enum JwsError {
+    #[fail(display = "JWS is not in compact serialization")]
+    Malformed,
+    #[fail(display = "JWS {} is not valid: {}", _0, _1)]
+    InvalidSegment(&'static str, String),
+    #[fail(display = "unsupported JWS algorithm: {}", _0)]
+    UnsupportedAlgorithm(String),
+    #[fail(display = "JWS x5c certificate is invalid")]
+    InvalidCertificate,
+}
+
+#[derive(Deserialize)]
+pub struct JwsHeader {
+    pub alg: String,
+    #[serde(default)]
+    pub x5c: Vec<String>,
+}
+
+pub struct Jws<'a, T> {
+    pub header: JwsHeader,
+    pub payload: T,
+    signing_input: &'a str,
+    signature: Vec<u8>,
+}
+
+fn decode_segment(segment: &str, name: &'static str) -> Result<Vec<u8>, JwsError> {
+    base64::decode_config(segment, base64::URL_SAFE_NO_PAD).map_err(|e| JwsError::InvalidSegment(name, e.to_string()))
+}
+
+impl<'a, T: DeserializeOwned> Jws<'a, T> {
+    pub fn parse(compact: &'a str) -> Result<Self, JwsError> {
+        let mut segments = compact.rsplitn(2, '.');
+        let (signature, signing_input) = match (segments.next(), segments.next()) {
+            (Some(signature), Some(signing_input)) => (signature, signing_input),
+            _ => return Err(JwsError::Malformed),
+        };
+        let mut parts = signing_input.splitn(2, '.');
+        let (header, payload) = match (parts.next(), parts.next()) {
+            (Some(header), Some(payload)) if !payload.contains('.') => (header, payload),
+            _ => return Err(JwsError::Malformed),
+        };
+        let header = serde_json::from_slice(&decode_segment(header, "header")?)
+            .map_err(|e| JwsError::InvalidSegment("header", e.to_string()))?;
+        let payload = serde_json::from_slice(&decode_segment(payload, "payload")?)
+            .map_err(|e| JwsError::InvalidSegment("payload", e.to_string()))?;
+        Ok(Jws {
+            header,
+            payload,
+            signing_input,
+            signature: decode_segment(signature, "signature")?,
+        })
+    }
+
+    pub fn algorithm(&self) -> Result<Algorithm, JwsError> {
+        match self.header.alg.as_str() {
+            "RS256" => Ok(Algorithm::RS256),
+            "PS256" => Ok(Algorithm::PS256),
+            "ES256" => Ok(Algorithm::ES256),
+            "ES384" => Ok(Algorithm::ES384),
+            "ES512" => Ok(Algorithm::ES512),
+            alg => Err(JwsError::UnsupportedAlgorithm(alg.to_owned())),
+        }
+    }
+
+    // x5c entries are standard base64, not base64url.
+    pub fn certificates(&self) -> Result<Vec<X509>, JwsError> {
+        self.header.x5c.iter()
+            .map(|c| base64::decode(c).ok().and_then(|der| X509::from_der(&der).ok()).ok_or(JwsError::InvalidCertificate))
+            .collect()
+    }
+
+    pub fn verify(&self, public_key: &PKeyRef<Public>) -> Result<bool, JwsError> {
+        let alg = self.algorithm()?;
+        let signature = match alg {
+            // JWS carries ECDSA signatures as r || s, openssl wants them DER encoded.
+            Algorithm::ES256 | Algorithm::ES384 | Algorithm::ES512 => {
+                let (r, s) = self.signature.split_at(self.signature.len() / 2);
+                let der = BigNum::from_slice(r)
+                    .and_then(|r| Ok((r, BigNum::from_slice(s)?)))
+                    .and_then(|(r, s)| EcdsaSig::from_private_components(r, s))
+                    .and_then(|sig| sig.to_der());
+                match der {
+                    Ok(der) => der,
+                    Err(_) => return Ok(false),
+                }
+            },
+            _ => self.signature.clone(),
+        };
+        Ok(verify_signature(alg, public_key, self.signing_input.as_bytes(), &signature))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_fixture;
+    use openssl::pkey::{PKey, Private};
+
+    type Payload = serde_json::Value;
+
+    fn public_key(key: &PKeyRef<Private>) -> PKey<Public> {
+        PKey::public_key_from_der(&key.public_key_to_der().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn verifies_rs256() {
+        let (key, _) = test_fixture::rsa_key();
+        let compact = test_fixture::jws(&key, &[], &serde_json::json!({ "no": 1 }));
+        let jws = Jws::<Payload>::parse(&compact).unwrap();
+        assert_eq!(jws.payload["no"], 1);
+        assert!(jws.verify(&public_key(&key)).unwrap());
+    }
+
+    #[test]
+    fn verifies_es256_as_r_s() {
+        let (key, _) = test_fixture::ec_key();
+        let encode = |value: &[u8]| base64::encode_config(value, base64::URL_SAFE_NO_PAD);
+        let signing_input = format!("{}.{}", encode(br#"{"alg":"ES256"}"#), encode(br#"{"no":1}"#));
+        let der = EcdsaSig::from_der(&test_fixture::sign(&key, signing_input.as_bytes())).unwrap();
+        let mut signature = der.r().to_vec_padded(32).unwrap();
+        signature.extend(der.s().to_vec_padded(32).unwrap());
+        let compact = format!("{}.{}", signing_input, encode(&signature));
+        assert!(Jws::<Payload>::parse(&compact).unwrap().verify(&public_key(&key)).unwrap());
+    }
+
+    #[test]
+    fn rejects_bad_signature() {
+        let (key, _) = test_fixture::rsa_key();
+        let compact = test_fixture::jws(&key, &[], &serde_json::json!({ "no": 1 }));
+        let (signing_input, signature) = compact.split_at(compact.rfind('.').unwrap());
+        let mut signature = base64::decode_config(&signature[1..], base64::URL_SAFE_NO_PAD).unwrap();
+        signature[0] ^= 0x01;
+        let tampered = format!("{}.{}", signing_input, base64::encode_config(&signature, base64::URL_SAFE_NO_PAD));
+        assert!(!Jws::<Payload>::parse(&tampered).unwrap().verify(&public_key(&key)).unwrap());
+        // Signed by another key.
+        let (other_key, _) = test_fixture::rsa_key();
+        assert!(!Jws::<Payload>::parse(&compact).unwrap().verify(&public_key(&other_key)).unwrap());
+    }
+
+    #[test]
+    fn reads_x5c() {
+        let root = test_fixture::Ca::root("Fixture Root CA");
+        let (key, _) = test_fixture::rsa_key();
+        let compact = test_fixture::jws(&key, &[&root.certificate], &serde_json::json!({}));
+        let certificates = Jws::<Payload>::parse(&compact).unwrap().certificates().unwrap();
+        assert_eq!(certificates.len(), 1);
+        assert_eq!(certificates[0].to_der().unwrap(), root.certificate.to_der().unwrap());
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        match Jws::<Payload>::parse("e30.e30") {
+            Err(JwsError::Malformed) => {},
+            _ => panic!("parsed a JWS without a signature"),
+        }
+        match Jws::<Payload>::parse("e30.e30.e30.e30") {
+            Err(JwsError::Malformed) => {},
+            _ => panic!("parsed a JWS with four segments"),
+        }
+        let unsigned = Jws::<Payload>::parse("eyJhbGciOiJub25lIn0.e30.").unwrap();
+        let (key, _) = test_fixture::rsa_key();
+        match unsigned.verify(&public_key(&key)) {
+            Err(JwsError::UnsupportedAlgorithm(ref alg)) if alg == "none" => {},
+            _ => panic!("verified an unsigned JWS"),
+        }
+    }
+}
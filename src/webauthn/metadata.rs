@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use openssl::error::ErrorStack;
+use openssl::hash::{hash, MessageDigest};
+use openssl::stack::Stack;
+use openssl::x509::{X509, X509Ref, X509StoreContext};
+use openssl::x509::store::X509StoreBuilder;
+use serde::Deserialize;
+use super::der;
+use super::helper::parse_aaguid;
+use super::jws::{Jws, JwsError};
+
+// FIDO Metadata Service v3, read from a BLOB downloaded beforehand so that it works offline.
+// Spec: https://fidoalliance.org/specs/mds/fido-metadata-service-v3.0-ps-20210518.html
+
+#[derive(Debug, Fail)]
+pub enum MetadataError {
+    #[fail(display = "failed to read {:?}: {}", _0, _1)]
+    Io(PathBuf, #[cause] io::Error),
+    #[fail(display = "{:?} is not a PEM or DER certificate", _0)]
+    InvalidRootCertificate(PathBuf),
+    #[fail(display = "metadata BLOB is malformed: {}", _0)]
+    MalformedBlob(#[cause] JwsError),
+    #[fail(display = "metadata BLOB certificate chain does not lead to the configured root")]
+    UntrustedBlob,
+    #[fail(display = "metadata BLOB signature is invalid")]
+    InvalidSignature,
+    #[fail(display = "failed to validate metadata BLOB certificate chain: {}", _0)]
+    Store(#[cause] ErrorStack),
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all(deserialize = "SCREAMING_SNAKE_CASE"))]
+pub enum AuthenticatorStatus {
+    NotFidoCertified,
+    FidoCertified,
+    UserVerificationBypass,
+    AttestationKeyCompromise,
+    UserKeyRemoteCompromise,
+    UserKeyPhysicalCompromise,
+    UpdateAvailable,
+    Revoked,
+    SelfAssertionSubmitted,
+    #[serde(rename(deserialize = "FIDO_CERTIFIED_L1"))]
+    FidoCertifiedL1,
+    #[serde(rename(deserialize = "FIDO_CERTIFIED_L1plus"))]
+    FidoCertifiedL1Plus,
+    #[serde(rename(deserialize = "FIDO_CERTIFIED_L2"))]
+    FidoCertifiedL2,
+    #[serde(rename(deserialize = "FIDO_CERTIFIED_L2plus"))]
+    FidoCertifiedL2Plus,
+    #[serde(rename(deserialize = "FIDO_CERTIFIED_L3"))]
+    FidoCertifiedL3,
+    #[serde(rename(deserialize = "FIDO_CERTIFIED_L3plus"))]
+    FidoCertifiedL3Plus,
+    #[serde(other)]
+    Unknown,
+}
+
+impl AuthenticatorStatus {
+    // Statuses after which credentials from the authenticator can no longer be trusted.
+    pub fn is_compromised(&self) -> bool {
+        match self {
+            Self::UserVerificationBypass
+            | Self::AttestationKeyCompromise
+            | Self::UserKeyRemoteCompromise
+            | Self::UserKeyPhysicalCompromise
+            | Self::Revoked => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct StatusReport {
+    pub status: AuthenticatorStatus,
+    #[serde(rename(deserialize = "effectiveDate"))]
+    pub effective_date: Option<String>,
+    pub url: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct MetadataStatement {
+    pub description: String,
+    #[serde(rename(deserialize = "authenticatorVersion"))]
+    pub authenticator_version: u32,
+    #[serde(rename(deserialize = "protocolFamily"))]
+    pub protocol_family: String,
+    #[serde(rename(deserialize = "attestationTypes"), default)]
+    pub attestation_types: Vec<String>,
+    // Standard base64 DER certificates.
+    #[serde(rename(deserialize = "attestationRootCertificates"), default)]
+    pub attestation_root_certificates: Vec<String>,
+    pub icon: Option<String>,
+}
+
+impl MetadataStatement {
+    pub fn root_certificates(&self) -> Vec<X509> {
+        self.attestation_root_certificates.iter()
+            .filter_map(|c| base64::decode(c).ok())
+            .filter_map(|der| X509::from_der(&der).ok())
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct MetadataBlobPayloadEntry {
+    pub aaguid: Option<String>,
+    #[serde(rename(deserialize = "attestationCertificateKeyIdentifiers"), default)]
+    pub attestation_certificate_key_identifiers: Vec<String>,
+    #[serde(rename(deserialize = "metadataStatement"))]
+    pub metadata_statement: Option<MetadataStatement>,
+    #[serde(rename(deserialize = "statusReports"), default)]
+    pub status_reports: Vec<StatusReport>,
+    #[serde(rename(deserialize = "timeOfLastStatusChange"))]
+    pub time_of_last_status_change: Option<String>,
+}
+
+impl MetadataBlobPayloadEntry {
+    // Reports are dated with ISO 8601 dates, which sort lexicographically.
+    pub fn latest_status(&self) -> Option<AuthenticatorStatus> {
+        self.status_reports.iter()
+            .enumerate()
+            .max_by_key(|(i, report)| (report.effective_date.clone(), *i))
+            .map(|(_, report)| report.status)
+    }
+}
+
+#[derive(Deserialize)]
+struct MetadataBlobPayload {
+    no: u64,
+    #[serde(rename(deserialize = "nextUpdate"))]
+    next_update: String,
+    entries: Vec<MetadataBlobPayloadEntry>,
+}
+
+// hex encoded SHA-1 of the subjectPublicKey BIT STRING, as in attestationCertificateKeyIdentifiers.
+pub fn attestation_key_identifier(certificate: &X509Ref) -> Option<String> {
+    const TAG_BIT_STRING: u32 = 0x03;
+    let spki = certificate.public_key().ok()?.public_key_to_der().ok()?;
+    let (spki, _) = der::parse(&spki).ok()?;
+    let fields = spki.children().ok()?;
+    // The first octet of a BIT STRING is the number of unused bits.
+    let public_key = fields.get(1)?.expect(TAG_BIT_STRING).ok()?.get(1..)?;
+    let digest = hash(MessageDigest::sha1(), public_key).ok()?;
+    Some(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+pub struct MetadataStore {
+    pub serial_number: u64,
+    pub next_update: String,
+    entries: Vec<MetadataBlobPayloadEntry>,
+    by_aaguid: HashMap<[u8; 16], usize>,
+    by_key_identifier: HashMap<String, usize>,
+}
+
+impl MetadataStore {
+    pub fn load<P: AsRef<Path>, Q: AsRef<Path>>(blob_path: P, root_certificate_path: Q) -> Result<Self, MetadataError> {
+        let blob_path = blob_path.as_ref();
+        let root_certificate_path = root_certificate_path.as_ref();
+        let blob = fs::read_to_string(blob_path).map_err(|e| MetadataError::Io(blob_path.to_owned(), e))?;
+        let root = fs::read(root_certificate_path).map_err(|e| MetadataError::Io(root_certificate_path.to_owned(), e))?;
+        let root = X509::from_pem(&root)
+            .or_else(|_| X509::from_der(&root))
+            .map_err(|_| MetadataError::InvalidRootCertificate(root_certificate_path.to_owned()))?;
+        Self::from_blob(blob.trim(), root)
+    }
+
+    pub fn from_blob(blob: &str, root: X509) -> Result<Self, MetadataError> {
+        let jws = Jws::<MetadataBlobPayload>::parse(blob).map_err(MetadataError::MalformedBlob)?;
+
+        // The BLOB is signed by the first certificate of x5c, which has to chain up to the root.
+        let x5c = jws.certificates().map_err(MetadataError::MalformedBlob)?;
+        let (leaf, intermediates) = x5c.split_first().ok_or(MetadataError::UntrustedBlob)?;
+        let mut builder = X509StoreBuilder::new().map_err(MetadataError::Store)?;
+        builder.add_cert(root).map_err(MetadataError::Store)?;
+        let store = builder.build();
+        let mut chain = Stack::new().map_err(MetadataError::Store)?;
+        for certificate in intermediates {
+            chain.push(certificate.clone()).map_err(MetadataError::Store)?;
+        }
+        let mut context = X509StoreContext::new().map_err(MetadataError::Store)?;
+        if !context.init(&store, leaf, &chain, |c| c.verify_cert()).map_err(MetadataError::Store)? {
+            return Err(MetadataError::UntrustedBlob)
+        }
+
+        let leaf_key = leaf.public_key().map_err(MetadataError::Store)?;
+        if !jws.verify(&leaf_key).map_err(MetadataError::MalformedBlob)? {
+            return Err(MetadataError::InvalidSignature)
+        }
+
+        let payload = jws.payload;
+        let mut by_aaguid = HashMap::new();
+        let mut by_key_identifier = HashMap::new();
+        for (i, entry) in payload.entries.iter().enumerate() {
+            if let Some(aaguid) = entry.aaguid.as_ref().and_then(|v| parse_aaguid(v)) {
+                by_aaguid.insert(aaguid, i);
+            }
+            for key_identifier in &entry.attestation_certificate_key_identifiers {
+                by_key_identifier.insert(key_identifier.to_lowercase(), i);
+            }
+        }
+        Ok(MetadataStore {
+            serial_number: payload.no,
+            next_update: payload.next_update,
+            entries: payload.entries,
+            by_aaguid,
+            by_key_identifier,
+        })
+    }
+
+    pub fn entries(&self) -> &[MetadataBlobPayloadEntry] {
+        &self.entries
+    }
+
+    pub fn find_by_aaguid(&self, aaguid: &[u8; 16]) -> Option<&MetadataBlobPayloadEntry> {
+        self.by_aaguid.get(aaguid).map(|i| &self.entries[*i])
+    }
+
+    pub fn find_by_key_identifier(&self, key_identifier: &str) -> Option<&MetadataBlobPayloadEntry> {
+        self.by_key_identifier.get(&key_identifier.to_lowercase()).map(|i| &self.entries[*i])
+    }
+
+    // U2F authenticators have no AAGUID and are identified by their attestation certificate instead.
+    pub fn find(&self, aaguid: &[u8; 16], trust_path: &[X509]) -> Option<&MetadataBlobPayloadEntry> {
+        self.find_by_aaguid(aaguid).or_else(|| {
+            trust_path.first()
+                .and_then(|leaf| attestation_key_identifier(leaf))
+                .and_then(|key_identifier| self.find_by_key_identifier(&key_identifier))
+        })
+    }
+
+    pub fn status_reports(&self, aaguid: &[u8; 16]) -> &[StatusReport] {
+        self.find_by_aaguid(aaguid).map_or(&[], |entry| &entry.status_reports)
+    }
+
+    pub fn statement(&self, aaguid: &[u8; 16]) -> Option<&MetadataStatement> {
+        self.find_by_aaguid(aaguid).and_then(|entry| entry.metadata_statement.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_fixture::{self, Ca};
+
+    const AAGUID: &str = "cb69481e-8ff7-4039-93ec-0a2729a154a8";
+
+    #[test]
+    fn loads_blob_signed_under_root() {
+        let root = Ca::root("Fixture Metadata Root");
+        let blob = test_fixture::metadata_blob(&root, serde_json::json!([
+            test_fixture::metadata_entry(AAGUID, &["FIDO_CERTIFIED_L1"]),
+        ]));
+        let metadata = MetadataStore::from_blob(&blob, root.certificate).unwrap();
+        assert_eq!(metadata.serial_number, 5);
+        let entry = metadata.find_by_aaguid(&parse_aaguid(AAGUID).unwrap()).unwrap();
+        assert_eq!(entry.latest_status(), Some(AuthenticatorStatus::FidoCertifiedL1));
+        assert!(metadata.find_by_aaguid(&[0; 16]).is_none());
+    }
+
+    #[test]
+    fn rejects_blob_signed_under_other_root() {
+        let blob = test_fixture::metadata_blob(&Ca::root("Fixture Metadata Root"), serde_json::json!([]));
+        let root = Ca::root("Fixture Metadata Root");
+        match MetadataStore::from_blob(&blob, root.certificate) {
+            Err(MetadataError::UntrustedBlob) => {},
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("loaded the metadata BLOB"),
+        }
+    }
+
+    #[test]
+    fn rejects_blob_with_bad_signature() {
+        let root = Ca::root("Fixture Metadata Root");
+        let blob = test_fixture::metadata_blob(&root, serde_json::json!([
+            test_fixture::metadata_entry(AAGUID, &["FIDO_CERTIFIED_L1"]),
+        ]));
+        // Swap in the payload of another BLOB, as if the entries had been edited after signing.
+        let other = test_fixture::metadata_blob(&root, serde_json::json!([
+            test_fixture::metadata_entry(AAGUID, &["FIDO_CERTIFIED_L3"]),
+        ]));
+        let segments: Vec<&str> = blob.split('.').collect();
+        let tampered = format!("{}.{}.{}", segments[0], other.split('.').nth(1).unwrap(), segments[2]);
+        match MetadataStore::from_blob(&tampered, root.certificate) {
+            Err(MetadataError::InvalidSignature) => {},
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("loaded the metadata BLOB"),
+        }
+    }
+
+    #[test]
+    fn reports_latest_status() {
+        let root = Ca::root("Fixture Metadata Root");
+        let blob = test_fixture::metadata_blob(&root, serde_json::json!([
+            test_fixture::metadata_entry(AAGUID, &["FIDO_CERTIFIED_L1", "REVOKED"]),
+            test_fixture::metadata_entry("00000000-0000-0000-0000-000000000001", &["FIDO_CERTIFIED_L1", "SOMETHING_NEW"]),
+        ]));
+        let metadata = MetadataStore::from_blob(&blob, root.certificate).unwrap();
+        let revoked = metadata.find_by_aaguid(&parse_aaguid(AAGUID).unwrap()).unwrap().latest_status().unwrap();
+        assert_eq!(revoked, AuthenticatorStatus::Revoked);
+        assert!(revoked.is_compromised());
+        let mut aaguid = [0; 16];
+        aaguid[15] = 1;
+        let unknown = metadata.find_by_aaguid(&aaguid).unwrap().latest_status().unwrap();
+        assert_eq!(unknown, AuthenticatorStatus::Unknown);
+        assert!(!unknown.is_compromised());
+    }
+}
@@ -1,7 +1,26 @@
 pub mod credential_option;
 pub mod error;
 pub mod attestation_response;
+pub mod assertion_response;
+pub mod authenticator_data;
+pub mod cose_key;
+pub mod attestation_format;
+pub mod der;
+pub mod trust_anchor;
+pub mod jws;
+pub mod metadata;
+pub mod origin;
 pub mod helper;
+#[cfg(test)]
+pub mod test_fixture;
 
+pub use error::WebAuthnError;
 pub use credential_option::*;
 pub use attestation_response::*;
+pub use assertion_response::*;
+pub use authenticator_data::*;
+pub use cose_key::*;
+pub use attestation_format::{AttestationFormat, AttestationType};
+pub use trust_anchor::*;
+pub use metadata::{AuthenticatorStatus, MetadataStore};
+pub use origin::{OriginError, OriginPolicy};
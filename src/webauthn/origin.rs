@@ -0,0 +1,173 @@
+// Which origins may run ceremonies for the RP ID, checked against C.origin of the client data.
+// Spec: https://w3c.github.io/webauthn/#sctn-validating-origin
+// Related origins: https://w3c.github.io/webauthn/#sctn-related-origins
+
+const ANDROID_ORIGIN_PREFIX: &str = "android:apk-key-hash:";
+const IOS_ORIGIN_PREFIX: &str = "ios:bundle-id:";
+
+#[derive(Debug, Fail)]
+pub enum OriginError {
+    #[fail(display = "{:?} is not a valid RP ID", _0)]
+    InvalidRpId(String),
+    #[fail(display = "{:?} is not a valid origin", _0)]
+    Malformed(String),
+    #[fail(display = "{:?} is not a secure origin", _0)]
+    Insecure(String),
+    #[fail(display = "{:?} is not the RP ID {:?} or one of its subdomains", _0, _1)]
+    OutsideRpId(String, String),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct WebOrigin {
+    scheme: String,
+    host: String,
+    // Always set, the default port of the scheme when the origin omits it.
+    port: u16,
+}
+
+impl WebOrigin {
+    // scheme "://" host [ ":" port ], as serialized by browsers. A trailing slash is tolerated for configuration.
+    fn parse(origin: &str) -> Option<Self> {
+        let (scheme, rest) = origin.find("://").map(|i| (&origin[..i], &origin[i + 3..]))?;
+        let scheme = scheme.to_ascii_lowercase();
+        let rest = rest.trim_end_matches('/');
+        if rest.is_empty() || rest.contains(|c: char| c == '/' || c == '?' || c == '#' || c == '@') {
+            return None
+        }
+        let default_port = match scheme.as_str() {
+            "https" => 443,
+            "http" => 80,
+            _ => return None,
+        };
+        let (host, port) = match rest.rfind(':') {
+            Some(i) => (&rest[..i], rest[i + 1..].parse().ok()?),
+            None => (rest, default_port),
+        };
+        if host.is_empty() {
+            return None
+        }
+        Some(WebOrigin { scheme, host: host.to_ascii_lowercase(), port })
+    }
+
+    // Browsers only treat plain http as a secure context on localhost.
+    fn is_secure(&self) -> bool {
+        self.scheme == "https" || self.host == "localhost"
+    }
+
+    fn is_within(&self, domain: &str) -> bool {
+        self.host == domain || self.host.ends_with(&format!(".{}", domain))
+    }
+
+    fn serialize(&self) -> String {
+        let default_port = if self.scheme == "https" { 443 } else { 80 };
+        if self.port == default_port {
+            format!("{}://{}", self.scheme, self.host)
+        } else {
+            format!("{}://{}:{}", self.scheme, self.host, self.port)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum AllowedOrigin {
+    Exact(WebOrigin),
+    // "https://*.example.com", any subdomain but not example.com itself.
+    Subdomains(WebOrigin),
+    // Native apps, compared as is.
+    App(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct OriginPolicy {
+    rp_id: String,
+    allowed: Vec<AllowedOrigin>,
+    related: Vec<WebOrigin>,
+}
+
+impl OriginPolicy {
+    /// origins must be the RP ID or its subdomains, with an optional port and "*." for any subdomain.
+    pub fn new(rp_id: &str, origins: &[String]) -> Result<Self, OriginError> {
+        let rp_id = rp_id.to_ascii_lowercase();
+        // Without the public suffix list this cannot tell "co.uk" from "example.com",
+        // but it rules out single label names apart from localhost.
+        let valid_rp_id = !rp_id.is_empty()
+            && !rp_id.starts_with('.')
+            && !rp_id.ends_with('.')
+            && !rp_id.contains(|c: char| c == ':' || c == '/')
+            && (rp_id == "localhost" || rp_id.contains('.'));
+        if !valid_rp_id {
+            return Err(OriginError::InvalidRpId(rp_id))
+        }
+        let mut allowed = vec![];
+        for origin in origins {
+            let (subdomains, web_origin) = match origin.find("://*.") {
+                Some(i) => (true, WebOrigin::parse(&format!("{}://{}", &origin[..i], &origin[i + 5..]))),
+                None => (false, WebOrigin::parse(origin)),
+            };
+            let web_origin = web_origin.ok_or_else(|| OriginError::Malformed(origin.clone()))?;
+            if !web_origin.is_secure() {
+                return Err(OriginError::Insecure(origin.clone()))
+            }
+            if !web_origin.is_within(&rp_id) {
+                return Err(OriginError::OutsideRpId(origin.clone(), rp_id))
+            }
+            allowed.push(if subdomains { AllowedOrigin::Subdomains(web_origin) } else { AllowedOrigin::Exact(web_origin) });
+        }
+        Ok(OriginPolicy { rp_id, allowed, related: vec![] })
+    }
+
+    /// Origins on other domains, listed in /.well-known/webauthn of the RP ID so that browsers let them use it.
+    pub fn with_related_origins(mut self, origins: &[String]) -> Result<Self, OriginError> {
+        for origin in origins {
+            let web_origin = WebOrigin::parse(origin).ok_or_else(|| OriginError::Malformed(origin.clone()))?;
+            if !web_origin.is_secure() {
+                return Err(OriginError::Insecure(origin.clone()))
+            }
+            self.related.push(web_origin);
+        }
+        Ok(self)
+    }
+
+    /// key_hashes are the base64url SHA-256 fingerprints of the APK signing certificates.
+    pub fn with_android_apps(mut self, key_hashes: &[String]) -> Self {
+        self.allowed.extend(key_hashes.iter().map(|hash| AllowedOrigin::App(format!("{}{}", ANDROID_ORIGIN_PREFIX, hash))));
+        self
+    }
+
+    pub fn with_ios_apps(mut self, bundle_ids: &[String]) -> Self {
+        self.allowed.extend(bundle_ids.iter().map(|id| AllowedOrigin::App(format!("{}{}", IOS_ORIGIN_PREFIX, id))));
+        self
+    }
+
+    pub fn rp_id(&self) -> &str {
+        &self.rp_id
+    }
+
+    pub fn allows(&self, origin: &str) -> bool {
+        if origin.starts_with(ANDROID_ORIGIN_PREFIX) || origin.starts_with(IOS_ORIGIN_PREFIX) {
+            return self.allowed.iter().any(|allowed| match allowed {
+                AllowedOrigin::App(app) => app == origin,
+                _ => false,
+            })
+        }
+        let origin = match WebOrigin::parse(origin) {
+            Some(origin) => origin,
+            None => return false,
+        };
+        let allowed = self.allowed.iter().any(|allowed| match allowed {
+            AllowedOrigin::Exact(allowed) => *allowed == origin,
+            AllowedOrigin::Subdomains(parent) => {
+                parent.scheme == origin.scheme
+                    && parent.port == origin.port
+                    && origin.host.ends_with(&format!(".{}", parent.host))
+            },
+            AllowedOrigin::App(_) => false,
+        });
+        allowed || self.related.contains(&origin)
+    }
+
+    /// The origins member of the /.well-known/webauthn document.
+    pub fn related_origins(&self) -> Vec<String> {
+        self.related.iter().map(WebOrigin::serialize).collect()
+    }
+}
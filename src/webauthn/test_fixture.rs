@@ -0,0 +1,283 @@
+// Keys, certificates and attestation statements built with openssl while testing, for the formats and chains no
+// authenticator at hand produces. None of this is captured from a real device.
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, PKeyRef, Private};
+use openssl::rsa::Rsa;
+use openssl::sign::Signer;
+use openssl::x509::{X509, X509Builder, X509Extension, X509NameBuilder, X509Ref};
+use serde_cbor::Value;
+use crate::AttestationResponse;
+use super::attestation_format::AttestationInput;
+use super::authenticator_data::{AttestedCredentialData, AuthenticatorData};
+use super::cose_key::{CoseKey, CoseKeyParameters, Curve};
+use super::credential_option::Algorithm;
+use super::helper::sha256;
+
+pub const RP_ID: &str = "localhost";
+pub const ORIGIN: &str = "https://localhost:55301";
+pub const CHALLENGE: &str = "oV3hU0ZHVtJw2m6cB1j8kQ4yXn5sTa7dLq9RfEgPiKc";
+
+/// A DER TLV, with the length in short or long form.
+pub fn tlv(tag: &[u8], value: &[u8]) -> Vec<u8> {
+    let length = match value.len() {
+        length if length < 0x80 => vec![length as u8],
+        length if length <= 0xff => vec![0x81, length as u8],
+        length => vec![0x82, (length >> 8) as u8, length as u8],
+    };
+    [tag, &length, value].concat()
+}
+
+/// An extension value given as DER, for X509Extension::new.
+pub fn der_value(value: &[u8]) -> String {
+    let hex: Vec<String> = value.iter().map(|b| format!("{:02X}", b)).collect();
+    format!("DER:{}", hex.join(":"))
+}
+
+/// A P-256 key and the ES256 credential public key for it.
+pub fn ec_key() -> (PKey<Private>, CoseKey) {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let ec_key = EcKey::generate(&group).unwrap();
+    let mut context = BigNumContext::new().unwrap();
+    let (mut x, mut y) = (BigNum::new().unwrap(), BigNum::new().unwrap());
+    ec_key.public_key().affine_coordinates_gfp(&group, &mut x, &mut y, &mut context).unwrap();
+    let parameters = CoseKeyParameters::Ec2 {
+        crv: Curve::P256,
+        x: x.to_vec_padded(32).unwrap(),
+        y: y.to_vec_padded(32).unwrap(),
+    };
+    (PKey::from_ec_key(ec_key).unwrap(), CoseKey { alg: Algorithm::ES256, parameters })
+}
+
+/// A 2048 bits RSA key and the RS256 credential public key for it.
+pub fn rsa_key() -> (PKey<Private>, CoseKey) {
+    let rsa = Rsa::generate(2048).unwrap();
+    let parameters = CoseKeyParameters::Rsa { n: rsa.n().to_vec(), e: rsa.e().to_vec() };
+    (PKey::from_rsa(rsa).unwrap(), CoseKey { alg: Algorithm::RS256, parameters })
+}
+
+/// A certificate for key, self-signed unless an issuer is given. Without a common name the subject is empty.
+/// Extensions are (name, value) as openssl configuration files spell them, see der_value for arbitrary ones.
+#[allow(deprecated)]
+pub fn certificate(
+    common_name: Option<&str>,
+    key: &PKeyRef<Private>,
+    issuer: Option<(&X509Ref, &PKeyRef<Private>)>,
+    extensions: &[(&str, &str)],
+) -> X509 {
+    let mut name = X509NameBuilder::new().unwrap();
+    if let Some(common_name) = common_name {
+        name.append_entry_by_nid(Nid::COMMONNAME, common_name).unwrap();
+    }
+    let name = name.build();
+    let mut builder = X509Builder::new().unwrap();
+    builder.set_version(2).unwrap();
+    let serial = BigNum::from_u32(rand::random::<u32>() >> 1).unwrap();
+    builder.set_serial_number(&serial.to_asn1_integer().unwrap()).unwrap();
+    builder.set_subject_name(&name).unwrap();
+    match issuer {
+        Some((issuer, _)) => builder.set_issuer_name(issuer.subject_name()).unwrap(),
+        None => builder.set_issuer_name(&name).unwrap(),
+    }
+    builder.set_pubkey(key).unwrap();
+    builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+    builder.set_not_after(&Asn1Time::days_from_now(30).unwrap()).unwrap();
+    for (name, value) in extensions {
+        builder.append_extension(X509Extension::new(None, None, name, value).unwrap()).unwrap();
+    }
+    let signing_key = issuer.map(|(_, issuer_key)| issuer_key).unwrap_or(key);
+    builder.sign(signing_key, MessageDigest::sha256()).unwrap();
+    builder.build()
+}
+
+const CA_EXTENSIONS: &[(&str, &str)] = &[
+    ("basicConstraints", "critical,CA:TRUE"),
+    ("keyUsage", "critical,keyCertSign,cRLSign"),
+];
+
+/// A certificate authority, for chains of attestation certificates and of metadata BLOB signers.
+pub struct Ca {
+    pub key: PKey<Private>,
+    pub certificate: X509,
+}
+
+impl Ca {
+    pub fn root(common_name: &str) -> Self {
+        let (key, _) = ec_key();
+        let certificate = certificate(Some(common_name), &key, None, CA_EXTENSIONS);
+        Ca { key, certificate }
+    }
+
+    pub fn intermediate(&self, common_name: &str) -> Self {
+        let (key, _) = ec_key();
+        let certificate = self.issue(Some(common_name), &key, CA_EXTENSIONS);
+        Ca { key, certificate }
+    }
+
+    pub fn issue(&self, common_name: Option<&str>, key: &PKeyRef<Private>, extensions: &[(&str, &str)]) -> X509 {
+        certificate(common_name, key, Some((&self.certificate, &self.key)), extensions)
+    }
+}
+
+pub fn sign(key: &PKeyRef<Private>, data: &[u8]) -> Vec<u8> {
+    let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+    signer.update(data).unwrap();
+    signer.sign_to_vec().unwrap()
+}
+
+// The COSE_Key of an ES256 or RS256 credential public key, as authenticators encode it.
+fn encode_cose_key(key: &CoseKey) -> Vec<u8> {
+    let int = |v: i64| Value::Integer(i128::from(v));
+    let mut map = BTreeMap::new();
+    map.insert(int(3), int(key.alg.code()));
+    match &key.parameters {
+        CoseKeyParameters::Ec2 { x, y, .. } => {
+            map.insert(int(1), int(2));
+            map.insert(int(-1), int(1));
+            map.insert(int(-2), Value::Bytes(x.clone()));
+            map.insert(int(-3), Value::Bytes(y.clone()));
+        },
+        CoseKeyParameters::Rsa { n, e } => {
+            map.insert(int(1), int(3));
+            map.insert(int(-1), Value::Bytes(n.clone()));
+            map.insert(int(-2), Value::Bytes(e.clone()));
+        },
+        CoseKeyParameters::Okp { .. } => unimplemented!(),
+    }
+    serde_cbor::to_vec(&Value::Map(map)).unwrap()
+}
+
+/// A JWS in compact serialization, signed with RS256 by an RSA key, carrying x5c in its header.
+pub fn jws(key: &PKeyRef<Private>, x5c: &[&X509Ref], payload: &serde_json::Value) -> String {
+    let x5c: Vec<String> = x5c.iter().map(|certificate| base64::encode(&certificate.to_der().unwrap())).collect();
+    let header = serde_json::json!({ "alg": "RS256", "x5c": x5c });
+    let encode = |value: &serde_json::Value| base64::encode_config(&serde_json::to_vec(value).unwrap(), base64::URL_SAFE_NO_PAD);
+    let signing_input = format!("{}.{}", encode(&header), encode(payload));
+    let signature = sign(key, signing_input.as_bytes());
+    format!("{}.{}", signing_input, base64::encode_config(&signature, base64::URL_SAFE_NO_PAD))
+}
+
+/// A metadata BLOB with entries, signed by a certificate that signer issues.
+pub fn metadata_blob(signer: &Ca, entries: serde_json::Value) -> String {
+    let (key, _) = rsa_key();
+    let certificate = signer.issue(Some("Fixture Metadata BLOB Signer"), &key, &[]);
+    jws(&key, &[&certificate], &serde_json::json!({ "no": 5, "nextUpdate": "2030-01-01", "entries": entries }))
+}
+
+/// A metadata BLOB entry for a FIDO2 authenticator whose status reports are statuses, oldest first.
+pub fn metadata_entry(aaguid: &str, statuses: &[&str]) -> serde_json::Value {
+    let status_reports: Vec<serde_json::Value> = statuses.iter().enumerate()
+        .map(|(i, status)| serde_json::json!({ "status": status, "effectiveDate": format!("202{}-01-01", i) }))
+        .collect();
+    serde_json::json!({
+        "aaguid": aaguid,
+        "metadataStatement": {
+            "description": "Fixture Authenticator",
+            "authenticatorVersion": 1,
+            "protocolFamily": "fido2",
+            "attestationTypes": ["basic_full"],
+        },
+        "statusReports": status_reports,
+    })
+}
+
+/// What an attestation statement verifier is given, owned so that tests can change any part of it.
+pub struct Attestation {
+    pub att_stmt: BTreeMap<Value, Value>,
+    pub raw_auth_data: Vec<u8>,
+    pub auth_data: AuthenticatorData,
+    pub credential: AttestedCredentialData,
+    pub credential_public_key: CoseKey,
+    pub client_data: Vec<u8>,
+    pub client_data_hash: Vec<u8>,
+}
+
+impl Attestation {
+    /// Authenticator data for RP_ID with UP and AT set, client data for CHALLENGE and ORIGIN, and an empty attStmt.
+    pub fn new(credential_public_key: CoseKey, aaguid: [u8; 16]) -> Self {
+        let credential_id = [0x01; 16];
+        let raw_auth_data = [
+            &sha256(RP_ID.as_bytes())[..],
+            // flags, then signCount
+            &[0x41, 0, 0, 0, 0],
+            &aaguid,
+            &(credential_id.len() as u16).to_be_bytes(),
+            &credential_id,
+            &encode_cose_key(&credential_public_key),
+        ].concat();
+        let client_data = format!(r#"{{"type":"webauthn.create","challenge":"{}","origin":"{}"}}"#, CHALLENGE, ORIGIN).into_bytes();
+        let mut auth_data = AuthenticatorData::parse(&raw_auth_data).unwrap();
+        Attestation {
+            att_stmt: BTreeMap::new(),
+            credential: auth_data.attested_credential_data.take().unwrap(),
+            auth_data,
+            raw_auth_data,
+            credential_public_key,
+            client_data_hash: sha256(&client_data),
+            client_data,
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: Value) {
+        self.att_stmt.insert(Value::Text(key.to_owned()), value);
+    }
+
+    pub fn set_x5c(&mut self, certificates: &[&X509Ref]) {
+        let certificates = certificates.iter().map(|certificate| Value::Bytes(certificate.to_der().unwrap())).collect();
+        self.set("x5c", Value::Array(certificates));
+    }
+
+    pub fn signed_data(&self) -> Vec<u8> {
+        [&self.raw_auth_data[..], &self.client_data_hash].concat()
+    }
+
+    /// A SafetyNet payload for this attestation, as attest.android.com returns it.
+    pub fn safetynet_payload(&self) -> serde_json::Value {
+        let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        serde_json::json!({
+            "nonce": base64::encode(&sha256(&self.signed_data())),
+            "timestampMs": timestamp_ms,
+            "apkPackageName": "com.google.android.gms",
+            "ctsProfileMatch": true,
+            "basicIntegrity": true,
+        })
+    }
+
+    /// An android-safetynet statement whose response is signed by a certificate for common_name issued by issuer,
+    /// the x5c of the response ending with x5c_root.
+    pub fn set_safetynet_response(&mut self, common_name: &str, issuer: &Ca, x5c_root: &X509Ref, payload: &serde_json::Value) {
+        let (leaf_key, _) = rsa_key();
+        let leaf = issuer.issue(Some(common_name), &leaf_key, &[]);
+        self.set("ver", Value::Text("14799021".to_owned()));
+        self.set("response", Value::Bytes(jws(&leaf_key, &[&leaf, x5c_root], payload).into_bytes()));
+    }
+
+    /// The registration as the browser posts it.
+    pub fn response(&self, fmt: &str) -> AttestationResponse {
+        let mut attestation_object = BTreeMap::new();
+        attestation_object.insert(Value::Text("fmt".to_owned()), Value::Text(fmt.to_owned()));
+        attestation_object.insert(Value::Text("attStmt".to_owned()), Value::Map(self.att_stmt.clone()));
+        attestation_object.insert(Value::Text("authData".to_owned()), Value::Bytes(self.raw_auth_data.clone()));
+        AttestationResponse {
+            att_obj: base64::encode_config(&serde_cbor::to_vec(&Value::Map(attestation_object)).unwrap(), base64::URL_SAFE_NO_PAD),
+            client_data: base64::encode_config(&self.client_data, base64::URL_SAFE_NO_PAD),
+            transports: vec![],
+        }
+    }
+
+    pub fn input(&self) -> AttestationInput<'_> {
+        AttestationInput {
+            att_stmt: &self.att_stmt,
+            raw_auth_data: &self.raw_auth_data,
+            auth_data: &self.auth_data,
+            credential: &self.credential,
+            credential_public_key: &self.credential_public_key,
+            client_data_hash: &self.client_data_hash,
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use openssl::error::ErrorStack;
+use openssl::stack::Stack;
+use openssl::x509::{X509, X509StoreContext};
+use openssl::x509::store::{X509Store, X509StoreBuilder};
+use super::attestation_format::AttestationFormat;
+use super::helper::parse_aaguid;
+use super::metadata::{attestation_key_identifier, MetadataStore};
+
+#[derive(Debug, Fail)]
+pub enum TrustAnchorError {
+    #[fail(display = "failed to read trust anchors from {:?}: {}", _0, _1)]
+    Io(PathBuf, #[cause] io::Error),
+    #[fail(display = "trust anchor directory {:?} is neither an AAGUID nor an attestation format", _0)]
+    InvalidDirectoryName(PathBuf),
+    #[fail(display = "{:?} is not a PEM or DER certificate", _0)]
+    InvalidCertificate(PathBuf),
+    #[fail(display = "failed to build certificate store: {}", _0)]
+    Store(#[cause] ErrorStack),
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum TrustAnchorKey {
+    Aaguid([u8; 16]),
+    Format(AttestationFormat),
+    // attestationCertificateKeyIdentifiers of the FIDO metadata, for authenticators without an AAGUID.
+    KeyIdentifier(String),
+}
+
+impl TrustAnchorKey {
+    fn from_directory_name(name: &str) -> Option<Self> {
+        AttestationFormat::from_identifier(name).map(TrustAnchorKey::Format)
+            .or_else(|| parse_aaguid(name).map(TrustAnchorKey::Aaguid))
+    }
+}
+
+// Attestation root certificates, looked up by the AAGUID of the authenticator (or the key identifier of its attestation certificate)
+// first and the attestation format second.
+// On disk every key is a sub directory of the trust anchor directory holding PEM or DER files:
+//
+//   trust_anchors/
+//     android-safetynet/gts-root-r1.pem (required to accept android-safetynet at all)
+//     apple/apple-webauthn-root.pem
+//     cb69481e-8ff7-4039-93ec-0a2729a154a8/yubico-u2f-root.der
+#[derive(Default)]
+pub struct TrustAnchorStore {
+    anchors: HashMap<TrustAnchorKey, Vec<X509>>,
+}
+
+impl TrustAnchorStore {
+    pub fn new() -> Self {
+        TrustAnchorStore::default()
+    }
+
+    // A missing directory is an empty store, so that trust anchors stay optional.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self, TrustAnchorError> {
+        let dir = dir.as_ref();
+        let mut store = TrustAnchorStore::new();
+        if !dir.is_dir() {
+            return Ok(store)
+        }
+        for entry in fs::read_dir(dir).map_err(|e| TrustAnchorError::Io(dir.to_owned(), e))? {
+            let path = entry.map_err(|e| TrustAnchorError::Io(dir.to_owned(), e))?.path();
+            if !path.is_dir() {
+                continue
+            }
+            let key = path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(TrustAnchorKey::from_directory_name)
+                .ok_or_else(|| TrustAnchorError::InvalidDirectoryName(path.clone()))?;
+            for file in fs::read_dir(&path).map_err(|e| TrustAnchorError::Io(path.clone(), e))? {
+                let file = file.map_err(|e| TrustAnchorError::Io(path.clone(), e))?.path();
+                if file.is_file() {
+                    for certificate in Self::read_certificates(&file)? {
+                        store.add(key.clone(), certificate);
+                    }
+                }
+            }
+        }
+        Ok(store)
+    }
+
+    fn read_certificates(path: &Path) -> Result<Vec<X509>, TrustAnchorError> {
+        let data = fs::read(path).map_err(|e| TrustAnchorError::Io(path.to_owned(), e))?;
+        X509::stack_from_pem(&data)
+            .ok()
+            .filter(|certificates| !certificates.is_empty())
+            .or_else(|| X509::from_der(&data).ok().map(|c| vec![c]))
+            .ok_or_else(|| TrustAnchorError::InvalidCertificate(path.to_owned()))
+    }
+
+    pub fn add(&mut self, key: TrustAnchorKey, certificate: X509) {
+        self.anchors.entry(key).or_insert_with(Vec::new).push(certificate);
+    }
+
+    // attestationRootCertificates of every metadata statement, keyed the same way the BLOB identifies the authenticator.
+    pub fn add_metadata(&mut self, metadata: &MetadataStore) {
+        for entry in metadata.entries() {
+            let statement = match &entry.metadata_statement {
+                Some(statement) => statement,
+                None => continue,
+            };
+            let mut keys: Vec<TrustAnchorKey> = entry.attestation_certificate_key_identifiers.iter()
+                .map(|key_identifier| TrustAnchorKey::KeyIdentifier(key_identifier.to_lowercase()))
+                .collect();
+            if let Some(aaguid) = entry.aaguid.as_ref().and_then(|v| parse_aaguid(v)) {
+                keys.push(TrustAnchorKey::Aaguid(aaguid));
+            }
+            for certificate in statement.root_certificates() {
+                for key in &keys {
+                    self.add(key.clone(), certificate.clone());
+                }
+            }
+        }
+    }
+
+    pub fn anchors_for(&self, aaguid: &[u8; 16], key_identifier: Option<&str>, format: AttestationFormat) -> Vec<&X509> {
+        let by_aaguid = self.anchors.get(&TrustAnchorKey::Aaguid(*aaguid)).into_iter().flatten();
+        let by_key_identifier = key_identifier
+            .and_then(|key_identifier| self.anchors.get(&TrustAnchorKey::KeyIdentifier(key_identifier.to_owned())))
+            .into_iter()
+            .flatten();
+        let by_format = self.anchors.get(&TrustAnchorKey::Format(format)).into_iter().flatten();
+        by_aaguid.chain(by_key_identifier).chain(by_format).collect()
+    }
+
+    pub fn build_store(&self, aaguid: &[u8; 16], key_identifier: Option<&str>, format: AttestationFormat) -> Result<Option<X509Store>, TrustAnchorError> {
+        let anchors = self.anchors_for(aaguid, key_identifier, format);
+        if anchors.is_empty() {
+            return Ok(None)
+        }
+        let mut builder = X509StoreBuilder::new().map_err(TrustAnchorError::Store)?;
+        for anchor in anchors {
+            builder.add_cert(anchor.clone()).map_err(TrustAnchorError::Store)?;
+        }
+        Ok(Some(builder.build()))
+    }
+
+    // Returns None when there is no trust anchor to validate against, otherwise whether trust_path chains up to one of them.
+    pub fn verify_chain(&self, trust_path: &[X509], aaguid: &[u8; 16], format: AttestationFormat) -> Result<Option<bool>, TrustAnchorError> {
+        let (leaf, intermediates) = match trust_path.split_first() {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let key_identifier = attestation_key_identifier(leaf);
+        let store = match self.build_store(aaguid, key_identifier.as_deref(), format)? {
+            Some(store) => store,
+            None => return Ok(None),
+        };
+        let mut chain = Stack::new().map_err(TrustAnchorError::Store)?;
+        for certificate in intermediates {
+            chain.push(certificate.clone()).map_err(TrustAnchorError::Store)?;
+        }
+        let mut context = X509StoreContext::new().map_err(TrustAnchorError::Store)?;
+        context.init(&store, leaf, &chain, |c| c.verify_cert())
+            .map(Some)
+            .map_err(TrustAnchorError::Store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_fixture::{self, Ca};
+
+    const AAGUID: [u8; 16] = [0xcb, 0x69, 0x48, 0x1e, 0x8f, 0xf7, 0x40, 0x39, 0x93, 0xec, 0x0a, 0x27, 0x29, 0xa1, 0x54, 0xa8];
+
+    // An attestation certificate under an intermediate of root, as x5c lists them.
+    fn trust_path(root: &Ca) -> Vec<X509> {
+        let intermediate = root.intermediate("Fixture Attestation CA");
+        let (key, _) = test_fixture::ec_key();
+        vec![intermediate.issue(Some("Fixture Authenticator"), &key, &[]), intermediate.certificate]
+    }
+
+    #[test]
+    fn verifies_chain_to_anchor() {
+        let root = Ca::root("Fixture Root CA");
+        let mut store = TrustAnchorStore::new();
+        store.add(TrustAnchorKey::Format(AttestationFormat::Packed), root.certificate.clone());
+        assert_eq!(store.verify_chain(&trust_path(&root), &[0; 16], AttestationFormat::Packed).unwrap(), Some(true));
+    }
+
+    #[test]
+    fn rejects_chain_to_foreign_root() {
+        let mut store = TrustAnchorStore::new();
+        store.add(TrustAnchorKey::Format(AttestationFormat::Packed), Ca::root("Fixture Root CA").certificate);
+        let foreign_root = Ca::root("Fixture Root CA");
+        assert_eq!(store.verify_chain(&trust_path(&foreign_root), &[0; 16], AttestationFormat::Packed).unwrap(), Some(false));
+    }
+
+    #[test]
+    fn looks_anchors_up_by_aaguid_then_format() {
+        let root = Ca::root("Fixture Root CA");
+        let mut store = TrustAnchorStore::new();
+        store.add(TrustAnchorKey::Aaguid(AAGUID), root.certificate.clone());
+        let trust_path = trust_path(&root);
+        assert_eq!(store.verify_chain(&trust_path, &AAGUID, AttestationFormat::Packed).unwrap(), Some(true));
+        // Neither the AAGUID nor the format has anchors.
+        assert_eq!(store.verify_chain(&trust_path, &[0; 16], AttestationFormat::Packed).unwrap(), None);
+    }
+
+    #[test]
+    fn loads_anchor_directories() {
+        let dir = std::env::temp_dir().join(format!("yo-trust-anchors-{}", std::process::id()));
+        let root = Ca::root("Fixture Root CA");
+        fs::create_dir_all(dir.join("packed")).unwrap();
+        fs::write(dir.join("packed").join("root.pem"), root.certificate.to_pem().unwrap()).unwrap();
+        let store = TrustAnchorStore::load(&dir);
+        fs::create_dir_all(dir.join("not-an-aaguid")).unwrap();
+        let invalid = TrustAnchorStore::load(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let store = store.unwrap();
+        assert_eq!(store.verify_chain(&trust_path(&root), &[0; 16], AttestationFormat::Packed).unwrap(), Some(true));
+        match invalid {
+            Err(TrustAnchorError::InvalidDirectoryName(_)) => {},
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("loaded a directory named neither by an AAGUID nor a format"),
+        }
+    }
+}